@@ -0,0 +1,66 @@
+//! Baselines for the lexer and the tree-walking interpreter. Each program
+//! in `benches/fixtures` is run from source, so parsing is included, but
+//! it is small next to running the program.
+
+use std::fs;
+use std::path::Path;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rustylox::lexer::Lexer;
+use rustylox::{interpret_source, new_globals, RunOptions};
+
+/// Programs to run, by fixture name.
+const PROGRAMS: &[&str] = &["fib", "loop", "strings", "closures"];
+
+/// How many copies of the fixtures make up the large tokenizer input.
+const TOKENIZE_COPIES: usize = 500;
+
+/// Copies of the fixtures lexed to check that lexing time grows linearly
+/// with the input: the throughput should be the same at every size.
+const SCALING_COPIES: &[usize] = &[50, 100, 200, 400, 800];
+
+fn fixture(name: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/fixtures").join(format!("{}.lox", name));
+    fs::read_to_string(&path).unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e))
+}
+
+fn tokenize(c: &mut Criterion) {
+    let source = PROGRAMS.iter().map(|name| fixture(name)).collect::<String>().repeat(TOKENIZE_COPIES);
+    let mut group = c.benchmark_group("tokenize");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.bench_function("large_file", |b| b.iter(|| Lexer::new(black_box(source.clone())).count()));
+    group.finish();
+}
+
+fn tokenize_scaling(c: &mut Criterion) {
+    let fixtures = PROGRAMS.iter().map(|name| fixture(name)).collect::<String>();
+    let mut group = c.benchmark_group("tokenize_scaling");
+    for copies in SCALING_COPIES {
+        let source = fixtures.repeat(*copies);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(copies), &source, |b, source| {
+            b.iter(|| Lexer::new(black_box(source.clone())).count())
+        });
+    }
+    group.finish();
+}
+
+fn run(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run");
+    // Some programs take seconds per run.
+    group.sample_size(10);
+    for name in PROGRAMS {
+        let source = fixture(name);
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                let options = RunOptions::default();
+                interpret_source(black_box(&source), new_globals(options.capabilities), &options)
+                    .expect("benchmark programs run without errors")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tokenize, tokenize_scaling, run);
+criterion_main!(benches);
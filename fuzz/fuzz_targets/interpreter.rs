@@ -0,0 +1,23 @@
+#![no_main]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use libfuzzer_sys::fuzz_target;
+use rustylox::environ::Environment;
+use rustylox::natives::{define_native_functions_where, Capabilities};
+use rustylox::{interpret_source, RunOptions};
+
+/// Statements each input may run, so infinite loops end quickly.
+const FUEL: u64 = 10_000;
+
+/// Nested calls each input may make, kept low for the fuzzer's instrumented build.
+const MAX_CALL_DEPTH: usize = 200;
+
+fuzz_target!(|source: &str| {
+    let globals = Rc::new(RefCell::new(Environment::new()));
+    // Reading stdin would block the fuzzer.
+    define_native_functions_where(&mut globals.borrow_mut(), Capabilities::default(), |name| name != "read_all_stdin");
+    let options = RunOptions { fuel: Some(FUEL), max_call_depth: MAX_CALL_DEPTH, ..RunOptions::default() };
+    let _ = interpret_source(source, globals, &options);
+});
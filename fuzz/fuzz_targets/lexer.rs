@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rustylox::lexer::Lexer;
+
+fuzz_target!(|source: &str| {
+    for _token in Lexer::new(source.to_string()) {}
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rustylox::parse_program;
+
+fuzz_target!(|source: &str| {
+    let _ = parse_program(source);
+});
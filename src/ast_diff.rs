@@ -0,0 +1,162 @@
+use std::fmt;
+
+use crate::error::RunError;
+use crate::parse_program;
+use crate::printer::{expr_to_source, to_minified_source, to_source};
+use crate::stmt::Stmt;
+use crate::token::Span;
+
+/// One difference between two versions of a program. Lines are in the new
+/// version, except for what was removed, which is only in the old one.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// A top-level declaration, such as `fun area`, only in the new version.
+    Added { declaration: String, line: usize },
+    Removed { declaration: String, line: usize },
+    /// A declaration in both versions, and what about it differs.
+    Changed { declaration: String, line: usize, differences: Vec<String> },
+    /// A top-level statement other than a declaration, as one line of source.
+    StatementAdded { source: String, line: usize },
+    StatementRemoved { source: String, line: usize },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { declaration, line } => write!(f, "+ {} (line {})", declaration, line),
+            Change::Removed { declaration, line } => write!(f, "- {} (line {})", declaration, line),
+            Change::Changed { declaration, line, differences } => {
+                write!(f, "~ {} (line {}): {}", declaration, line, differences.join(", "))
+            }
+            Change::StatementAdded { source, line } => write!(f, "+ line {}: {}", line, source),
+            Change::StatementRemoved { source, line } => write!(f, "- line {}: {}", line, source),
+        }
+    }
+}
+
+/// A top-level declaration with the parts worth comparing, each rendered
+/// as source so that layout and comments make no difference.
+struct Declaration {
+    /// `fun name`, `class name` or `var name`, which pairs it up across versions.
+    key: String,
+    line: usize,
+    parts: Vec<(&'static str, String)>,
+}
+
+/// Compares two versions of a program by their syntax trees rather than
+/// their text, so reformatting or editing comments shows up as nothing.
+/// Declarations are paired by name and compared part by part; the other
+/// top-level statements are compared as a sequence.
+pub fn diff_sources(old: &str, new: &str) -> Result<Vec<Change>, RunError> {
+    let old_statements = parse_program(old).map_err(RunError::Parse)?;
+    let new_statements = parse_program(new).map_err(RunError::Parse)?;
+    let (old_declarations, old_others) = split(old, &old_statements);
+    let (new_declarations, new_others) = split(new, &new_statements);
+
+    let mut changes = Vec::new();
+    for declaration in &old_declarations {
+        if !new_declarations.iter().any(|new| new.key == declaration.key) {
+            changes.push(Change::Removed { declaration: declaration.key.clone(), line: declaration.line });
+        }
+    }
+    for declaration in &new_declarations {
+        let Some(old) = old_declarations.iter().find(|old| old.key == declaration.key) else {
+            changes.push(Change::Added { declaration: declaration.key.clone(), line: declaration.line });
+            continue;
+        };
+        let differences: Vec<String> = declaration
+            .parts
+            .iter()
+            .zip(&old.parts)
+            .filter(|((_, new), (_, old))| new != old)
+            .map(|((part, _), _)| format!("{} changed", part))
+            .collect();
+        if !differences.is_empty() {
+            changes.push(Change::Changed { declaration: declaration.key.clone(), line: declaration.line, differences });
+        }
+    }
+    changes.extend(diff_statements(&old_others, &new_others));
+    Ok(changes)
+}
+
+/// Sorts top-level statements into declarations and the rest, the latter
+/// as one line of source and the line they start on.
+fn split(source: &str, statements: &[Stmt]) -> (Vec<Declaration>, Vec<(String, usize)>) {
+    let mut declarations: Vec<Declaration> = Vec::new();
+    let mut others = Vec::new();
+    for stmt in statements {
+        let line = line_of(source, stmt.span());
+        let (key, parts) = match stmt {
+            Stmt::Function(name, params, body, signature, _) => {
+                let annotations = signature.params.iter().chain([&signature.returns]).map(|annotation| {
+                    annotation.as_ref().map_or("_", |token| token.lexeme.as_str())
+                });
+                let mut params = params.clone();
+                if let Some(last) = params.last_mut().filter(|_| signature.rest) {
+                    last.insert_str(0, "...");
+                }
+                (format!("fun {}", name), vec![
+                    ("parameters", params.join(", ")),
+                    ("type annotations", annotations.collect::<Vec<_>>().join(", ")),
+                    ("body", to_source(body)),
+                ])
+            }
+            Stmt::Class(name, superclass, methods, _) => (format!("class {}", name), vec![
+                ("superclass", superclass.as_ref().map_or(String::new(), |token| token.lexeme.clone())),
+                ("methods", to_source(methods)),
+            ]),
+            Stmt::Var(name, annotation, initializer, _) => (format!("var {}", name), vec![
+                ("type annotation", annotation.as_ref().map_or(String::new(), |token| token.lexeme.clone())),
+                ("initializer", initializer.as_ref().map_or(String::new(), expr_to_source)),
+            ]),
+            Stmt::Const(name, annotation, value, _) => (format!("const {}", name), vec![
+                ("type annotation", annotation.as_ref().map_or(String::new(), |token| token.lexeme.clone())),
+                ("value", expr_to_source(value)),
+            ]),
+            other => {
+                others.push((to_minified_source(std::slice::from_ref(other)), line));
+                continue;
+            }
+        };
+        // A name declared again replaces the earlier declaration.
+        declarations.retain(|declaration| declaration.key != key);
+        declarations.push(Declaration { key, line, parts });
+    }
+    (declarations, others)
+}
+
+/// Statements only in the old sequence or only in the new one, found from
+/// their longest common subsequence.
+fn diff_statements(old: &[(String, usize)], new: &[(String, usize)]) -> Vec<Change> {
+    // common[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i].0 == new[j].0 {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i].0 == new[j].0 {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            changes.push(Change::StatementRemoved { source: old[i].0.clone(), line: old[i].1 });
+            i += 1;
+        } else {
+            changes.push(Change::StatementAdded { source: new[j].0.clone(), line: new[j].1 });
+            j += 1;
+        }
+    }
+    changes
+}
+
+fn line_of(source: &str, span: Span) -> usize {
+    source[..span.start.min(source.len())].matches('\n').count() + 1
+}
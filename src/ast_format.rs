@@ -0,0 +1,148 @@
+use std::str::FromStr;
+
+use crate::error::ParserError;
+use crate::expr::{Expr, LiteralExpr};
+use crate::stmt::{pretty_print_program, ForStmt, Stmt};
+use crate::token::Span;
+
+/// A versioned text rendering of the syntax tree, for tools that read the
+/// `parse` command's output. A released version never changes: a change to
+/// the layout of `Stmt::pretty_print` or `Expr::pretty_print` needs a new
+/// version, with the old one kept emitting what it always did. The
+/// snapshots in `tests/ast_format` hold each version to that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AstFormat {
+    /// The indented tree printed by `Stmt::pretty_print`, one statement after
+    /// another, followed by an `Errors:` section when parsing failed. `for`
+    /// loops appear as the blocks and `while` loop they behave like.
+    V1,
+    /// Like [`AstFormat::V1`], with `for` loops as `For` nodes.
+    V2,
+}
+
+impl AstFormat {
+    /// The newest version, used when a caller does not ask for one.
+    pub const LATEST: AstFormat = AstFormat::V2;
+
+    /// The first line of the output, naming the version that follows.
+    pub fn header(self) -> &'static str {
+        match self {
+            AstFormat::V1 => "AST text format v1",
+            AstFormat::V2 => "AST text format v2",
+        }
+    }
+
+    /// Renders a parsed program, header first.
+    pub fn emit(self, mut statements: Vec<Stmt>, errors: Vec<ParserError>) -> String {
+        if self == AstFormat::V1 {
+            statements.iter_mut().for_each(desugar_for);
+        }
+        format!("{}\n{}", self.header(), pretty_print_program((statements, errors)))
+    }
+}
+
+impl FromStr for AstFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" | "1" => Ok(AstFormat::V1),
+            "v2" | "2" => Ok(AstFormat::V2),
+            _ => Err(format!("Unknown AST format '{}', expected 'v1' or 'v2'", s)),
+        }
+    }
+}
+
+/// Rewrites every `for` loop in `stmt` into what the parser turned it into
+/// before it had a node of its own: a block declaring the initializer
+/// around a `while` loop, whose body is followed by the increment.
+fn desugar_for(stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Expression(expr, _) | Stmt::Print(expr, _) => desugar_for_in_expr(expr),
+        Stmt::Var(_, _, value, _) | Stmt::Return(value, _) => value.iter_mut().for_each(desugar_for_in_expr),
+        Stmt::Const(_, _, value, _) => desugar_for_in_expr(value),
+        Stmt::Block(statements, _) | Stmt::Function(_, _, statements, _, _) | Stmt::Class(_, _, statements, _) => {
+            statements.iter_mut().for_each(desugar_for)
+        }
+        Stmt::If(condition, then_branch, else_branch, _) => {
+            desugar_for_in_expr(condition);
+            desugar_for(then_branch);
+            else_branch.iter_mut().for_each(|else_branch| desugar_for(else_branch));
+        }
+        Stmt::While(_, condition, body, _) => {
+            desugar_for_in_expr(condition);
+            desugar_for(body);
+        }
+        Stmt::ParallelAssign(_, values, _) => values.iter_mut().for_each(desugar_for_in_expr),
+        Stmt::Break(_) | Stmt::Continue(_) => {}
+        // A `for`-`in` loop never had another shape, so only its parts change.
+        Stmt::ForIn(for_in) => {
+            desugar_for_in_expr(&mut for_in.iterable);
+            desugar_for(&mut for_in.body);
+        }
+        Stmt::For(for_stmt) => {
+            let ForStmt { keyword, mut initializer, mut condition, mut increment, mut body, span } = (**for_stmt).clone();
+            initializer.iter_mut().for_each(desugar_for);
+            condition.iter_mut().chain(increment.iter_mut()).for_each(desugar_for_in_expr);
+            desugar_for(&mut body);
+
+            // A missing condition was an always-true one, empty where it would be written.
+            let condition = condition.unwrap_or(Expr::Constant(LiteralExpr::Boolean(true), Span::default()));
+            if let Some(increment) = increment {
+                let increment_span = increment.span();
+                body = Stmt::Block(vec![body, Stmt::Expression(increment, increment_span)], span);
+            }
+            let mut desugared = Stmt::While(keyword, condition, Box::new(body), span);
+            if let Some(initializer) = initializer {
+                desugared = Stmt::Block(vec![initializer, desugared], span);
+            }
+            *stmt = desugared;
+        }
+    }
+}
+
+/// [`desugar_for`] for the statements inside block expressions and lambdas.
+fn desugar_for_in_expr(expr: &mut Expr) {
+    match expr {
+        Expr::Binary(binary) => {
+            desugar_for_in_expr(&mut binary.left);
+            desugar_for_in_expr(&mut binary.right);
+        }
+        Expr::Logical(logical) => {
+            desugar_for_in_expr(&mut logical.left);
+            desugar_for_in_expr(&mut logical.right);
+        }
+        Expr::Grouping(inner, _) | Expr::Assign(_, inner, _) => desugar_for_in_expr(inner),
+        Expr::Unary(unary) => desugar_for_in_expr(&mut unary.right),
+        Expr::Call(call) => {
+            desugar_for_in_expr(&mut call.callee);
+            call.arguments.iter_mut().for_each(desugar_for_in_expr);
+        }
+        Expr::Get(get) => desugar_for_in_expr(&mut get.object),
+        Expr::Set(set) => {
+            desugar_for_in_expr(&mut set.object);
+            desugar_for_in_expr(&mut set.value);
+        }
+        Expr::Index(index) => {
+            desugar_for_in_expr(&mut index.object);
+            desugar_for_in_expr(&mut index.index);
+        }
+        Expr::IndexSet(set) => {
+            desugar_for_in_expr(&mut set.object);
+            desugar_for_in_expr(&mut set.index);
+            desugar_for_in_expr(&mut set.value);
+        }
+        Expr::Map(map) => map.entries.iter_mut().for_each(|(_, value)| desugar_for_in_expr(value)),
+        Expr::If(if_expr) => {
+            desugar_for_in_expr(&mut if_expr.condition);
+            desugar_for_in_expr(&mut if_expr.then_branch);
+            desugar_for_in_expr(&mut if_expr.else_branch);
+        }
+        Expr::Lambda(lambda) => lambda.body.iter_mut().for_each(desugar_for),
+        Expr::Block(block) => {
+            block.statements.iter_mut().for_each(desugar_for);
+            block.value.iter_mut().for_each(desugar_for_in_expr);
+        }
+        Expr::Constant(..) | Expr::Variable(..) | Expr::This(_) | Expr::Super(..) => {}
+    }
+}
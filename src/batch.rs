@@ -0,0 +1,97 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::golden::lox_files;
+use crate::{new_globals, run_with_sinks, RunOptions};
+
+/// What one script of a batch printed and reported.
+#[derive(Debug)]
+pub struct ScriptResult {
+    pub script: PathBuf,
+    pub output: String,
+    /// Syntax or runtime errors, rendered in the batch's error format.
+    pub diagnostics: String,
+    pub succeeded: bool,
+}
+
+/// The outcome of a batch, in the order the scripts were given.
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub results: Vec<ScriptResult>,
+}
+
+impl BatchReport {
+    pub fn succeeded(&self) -> usize {
+        self.results.iter().filter(|result| result.succeeded).count()
+    }
+}
+
+impl fmt::Display for BatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            let status = if result.succeeded { "ok" } else { "FAILED" };
+            writeln!(f, "{:6} {}", status, result.script.display())?;
+            for line in result.diagnostics.lines() {
+                writeln!(f, "       {}", line)?;
+            }
+        }
+        write!(f, "{} of {} scripts succeeded", self.succeeded(), self.results.len())
+    }
+}
+
+/// Runs `scripts` on `jobs` threads. Each script gets an interpreter and
+/// globals of its own, so scripts cannot see each other's state; only the
+/// text they print crosses threads.
+pub fn run_batch(scripts: &[PathBuf], options: &RunOptions, jobs: usize) -> BatchReport {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(scripts.len()));
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, scripts.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(script) = scripts.get(index) else {
+                    break;
+                };
+                let result = run_script(script, options);
+                results.lock().unwrap_or_else(|e| e.into_inner()).push((index, result));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap_or_else(|e| e.into_inner());
+    results.sort_by_key(|(index, _)| *index);
+    BatchReport { results: results.into_iter().map(|(_, result)| result).collect() }
+}
+
+/// Runs every `.lox` file directly inside `dir`, see [`run_batch`].
+pub fn run_batch_dir(dir: &Path, options: &RunOptions, jobs: usize) -> io::Result<BatchReport> {
+    Ok(run_batch(&lox_files(dir)?, options, jobs))
+}
+
+fn run_script(script: &Path, options: &RunOptions) -> ScriptResult {
+    let source = match fs::read_to_string(script) {
+        Ok(source) => source,
+        Err(e) => {
+            return ScriptResult {
+                script: script.to_path_buf(),
+                output: String::new(),
+                diagnostics: format!("Failed to read file: {}\n", e),
+                succeeded: false,
+            }
+        }
+    };
+    let (mut output, mut diagnostics) = (Vec::new(), Vec::new());
+    // Writing to a `Vec` cannot fail.
+    let succeeded = run_with_sinks(&source, new_globals(options.capabilities), options, &mut output, &mut diagnostics)
+        .is_ok_and(|report| report.succeeded);
+    ScriptResult {
+        script: script.to_path_buf(),
+        output: String::from_utf8_lossy(&output).into_owned(),
+        diagnostics: String::from_utf8_lossy(&diagnostics).into_owned(),
+        succeeded,
+    }
+}
@@ -0,0 +1,128 @@
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+
+use crate::error::EvalError;
+use crate::value::Value;
+use crate::token::TokenType;
+
+/// The exact value of a number as written: `0.1` is one tenth rather than
+/// the nearest `f64`. Literals reach the interpreter as `f64`s, so this
+/// goes through the shortest decimal that reads back as the same `f64`,
+/// which is the literal itself unless it has more than 15 or so significant
+/// digits. Infinities and NaN have no exact value.
+pub fn exact(n: f64) -> Option<BigRational> {
+    if !n.is_finite() {
+        return None;
+    }
+    let text = n.to_string();
+    let (whole, fraction) = text.split_once('.').unwrap_or((&text, ""));
+    let numerator: BigInt = format!("{}{}", whole, fraction).parse().ok()?;
+    let denominator = BigInt::from(10).pow(fraction.len() as u32);
+    Some(BigRational::new(numerator, denominator))
+}
+
+/// A number's exact value, whichever way it is held.
+fn exact_operand(value: &Value) -> Option<BigRational> {
+    match value {
+        Value::BigNumber(n) => Some((**n).clone()),
+        Value::Number(n) => exact(*n),
+        _ => None,
+    }
+}
+
+/// The nearest `f64`, for natives and anything else that needs one.
+pub fn to_f64(n: &BigRational) -> f64 {
+    n.to_f64().unwrap_or(f64::NAN)
+}
+
+/// Renders a number the way `print` shows it: whole numbers and
+/// terminating decimals in full, others as their nearest `f64`.
+pub fn format(n: &BigRational) -> String {
+    if n.is_integer() {
+        return n.to_integer().to_string();
+    }
+    // A fraction in lowest terms terminates when its denominator has no
+    // prime factors but 2 and 5; it then needs as many decimal places as
+    // the larger of the two powers.
+    let mut denominator = n.denom().clone();
+    let (two, five) = (BigInt::from(2), BigInt::from(5));
+    let (mut twos, mut fives) = (0u32, 0u32);
+    while (&denominator % &two).is_zero() {
+        denominator /= &two;
+        twos += 1;
+    }
+    while (&denominator % &five).is_zero() {
+        denominator /= &five;
+        fives += 1;
+    }
+    if denominator != BigInt::from(1) {
+        return to_f64(n).to_string();
+    }
+    let places = twos.max(fives) as usize;
+    let scaled = (n * BigRational::from_integer(BigInt::from(10).pow(places as u32))).to_integer();
+    let digits = scaled.magnitude().to_string();
+    let digits = format!("{:0>width$}", digits, width = places + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - places);
+    let sign = if scaled < BigInt::zero() { "-" } else { "" };
+    format!("{}{}.{}", sign, whole, fraction)
+}
+
+fn number(n: BigRational) -> Value {
+    Value::BigNumber(Rc::new(n))
+}
+
+/// Applies a binary operator with exact arithmetic, when both operands are
+/// numbers with an exact value. Returns `None` to leave any other operands
+/// to the usual rules.
+pub fn binary(operator: &TokenType, left: &Value, right: &Value, integer_division: bool) -> Option<Result<Value, EvalError>> {
+    let (l, r) = (exact_operand(left)?, exact_operand(right)?);
+    Some(match operator {
+        TokenType::Plus => Ok(number(l + r)),
+        TokenType::Minus => Ok(number(l - r)),
+        TokenType::Star => Ok(number(l * r)),
+        TokenType::Slash | TokenType::Percent if r.is_zero() => Err(EvalError::DivisionByZero),
+        TokenType::Slash if integer_division && l.is_integer() && r.is_integer() => Ok(number((l / r).trunc())),
+        TokenType::Slash => Ok(number(l / r)),
+        TokenType::Percent => Ok(number(l % r)),
+        TokenType::EqualEqual => Ok(Value::Boolean(l == r)),
+        TokenType::BangEqual => Ok(Value::Boolean(l != r)),
+        TokenType::Greater => Ok(Value::Boolean(l > r)),
+        TokenType::GreaterEqual => Ok(Value::Boolean(l >= r)),
+        TokenType::Less => Ok(Value::Boolean(l < r)),
+        TokenType::LessEqual => Ok(Value::Boolean(l <= r)),
+        TokenType::Ampersand | TokenType::Pipe | TokenType::Caret | TokenType::LessLess | TokenType::GreaterGreater => {
+            bitwise(operator, &l, &r)
+        }
+        _ => return None,
+    })
+}
+
+/// Applies a bitwise operator to two whole numbers of any size, which act
+/// as if in two's complement.
+fn bitwise(operator: &TokenType, l: &BigRational, r: &BigRational) -> Result<Value, EvalError> {
+    let (l, r) = (whole_number(l)?, whole_number(r)?);
+    let shift = || r.to_usize().ok_or_else(|| EvalError::TypeError(format!("Shift amount must not be negative, not {}", r)));
+    Ok(number(BigRational::from_integer(match operator {
+        TokenType::Ampersand => &l & &r,
+        TokenType::Pipe => &l | &r,
+        TokenType::Caret => &l ^ &r,
+        TokenType::LessLess => l << shift()?,
+        _ => l >> shift()?,
+    })))
+}
+
+/// `~n`: the bitwise complement of a whole number, `-n - 1`.
+pub fn not(n: &BigRational) -> Result<Value, EvalError> {
+    Ok(number(BigRational::from_integer(-whole_number(n)? - 1)))
+}
+
+fn whole_number(n: &BigRational) -> Result<BigInt, EvalError> {
+    if n.is_integer() {
+        Ok(n.to_integer())
+    } else {
+        Err(EvalError::TypeError(format!("Bitwise operands must be whole numbers, not {}", format(n))))
+    }
+}
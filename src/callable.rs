@@ -2,10 +2,11 @@ use std::cell::RefCell;
 use std::fmt;
 use std::rc::Rc;
 
+use crate::class::LoxClass;
 use crate::environ::Environment;
-use crate::error::{ControlFlow, EvalError};
-use crate::expr::{Expr, LiteralExpr};
-use crate::interpreter::interpret_with_env;
+use crate::error::EvalError;
+use crate::value::Value;
+use crate::interpreter::{evaluate, execute_all, notify_binding, BindingEvent, Context, ControlFlow};
 use crate::resolver::Resolver;
 use crate::stmt::Stmt;
 use std::fmt::Debug;
@@ -16,24 +17,60 @@ pub struct LoxFunction {
     pub params: Vec<String>,
     pub body: Vec<Stmt>,
     pub closure: Rc<RefCell<Environment>>,
+    /// A class's `init` method, which always returns the instance.
+    pub is_initializer: bool,
+    /// Whether the last parameter collects the arguments past the others
+    /// into a list.
+    pub rest: bool,
 }
 
 impl LoxFunction {
     pub fn new(name: String, params: Vec<String>, body: Vec<Stmt>, closure: Rc<RefCell<Environment>>) -> Self {
-        LoxFunction { name, params, body, closure }
+        LoxFunction { name, params, body, closure, is_initializer: false, rest: false }
+    }
+
+    /// Makes the last parameter a rest parameter if `rest` is set.
+    pub fn with_rest(self, rest: bool) -> Self {
+        LoxFunction { rest, ..self }
+    }
+
+    /// A copy of this method whose body sees `instance` as `this`.
+    pub fn bind(&self, instance: Value) -> LoxFunction {
+        let mut environment = Environment::new_enclosed(self.closure.clone());
+        environment.define("this".to_string(), instance);
+        LoxFunction { closure: Rc::new(RefCell::new(environment)), ..self.clone() }
     }
 }
 
 pub trait LoxCallable {
+    /// How many arguments a call passes, or for a variadic callable the
+    /// fewest it may pass.
     fn arity(&self) -> usize;
+
+    /// Whether calls may pass any number of arguments past
+    /// [`LoxCallable::arity`].
+    fn variadic(&self) -> bool {
+        false
+    }
+
     fn name(&self) -> &str;
     fn call(
         &self,
-        arguments: Vec<LiteralExpr>, 
+        arguments: Vec<Value>, 
         environment: Rc<RefCell<Environment>>,
         resolver: &Resolver,
-        output : &mut String
-    ) -> Result<Expr, EvalError>;
+        context: &mut Context
+    ) -> Result<Value, EvalError>;
+
+    /// The Lox function behind this callable, if it is one rather than a native.
+    fn as_function(&self) -> Option<&LoxFunction> {
+        None
+    }
+
+    /// The class behind this callable, if it is one.
+    fn as_class(&self) -> Option<&LoxClass> {
+        None
+    }
 }
 
 impl Debug for dyn LoxCallable {
@@ -44,33 +81,66 @@ impl Debug for dyn LoxCallable {
 
 impl LoxCallable for LoxFunction {
     fn arity(&self) -> usize {
-        self.params.len()
+        self.params.len() - usize::from(self.rest)
+    }
+
+    fn variadic(&self) -> bool {
+        self.rest
     }
 
     fn name(&self) -> &str {
         &self.name
     }
 
+    fn as_function(&self) -> Option<&LoxFunction> {
+        Some(self)
+    }
+
     fn call(
         &self, 
-        arguments: Vec<LiteralExpr>, 
+        arguments: Vec<Value>, 
         _environment: Rc<RefCell<Environment>>,
-        _resolver: &Resolver,
-        output: &mut String
-    ) -> Result<Expr, EvalError> {
+        resolver: &Resolver,
+        context: &mut Context
+    ) -> Result<Value, EvalError> {
+        // The caller has already pushed this call's frame.
+        let depth = context.frames.len();
+        if depth > context.max_call_depth {
+            return Err(EvalError::StackOverflow(self.name.clone(), depth));
+        }
         let mut function_env = Environment::new_enclosed(self.closure.clone());
+        context.stats.record_environment(&function_env);
 
-        // Bind the arguments to the parameters
-        for (param, arg) in self.params.iter().zip(arguments) {
+        // Bind the arguments to the parameters, the rest parameter taking those left over
+        let mut arguments = arguments.into_iter();
+        for (i, param) in self.params.iter().enumerate() {
+            let arg = if self.rest && i + 1 == self.params.len() {
+                Value::list(arguments.by_ref().collect())
+            } else {
+                arguments.next().unwrap_or(Value::Nil)
+            };
+            notify_binding(context, BindingEvent::Define, param, &arg);
             function_env.define(param.clone(), arg);
         }
 
-        // Execute the function body and pass the output buffer
+        // With implicit returns on, a trailing expression statement is the result
+        let (body, result) = match self.body.split_last() {
+            Some((Stmt::Expression(result, _), body)) if context.options.implicit_return => (body, Some(result)),
+            _ => (self.body.as_slice(), None),
+        };
+
+        // Execute the function body in the same run context
         let body_env = Rc::new(RefCell::new(function_env));
-        match interpret_with_env(&self.body, Some(body_env), &_resolver, output) {
-            Ok(_) => Ok(Expr::Literal(LiteralExpr::Nil)),
-            Err(EvalError::ControlFlow(ControlFlow::Return(value))) => Ok(value),
-            Err(e) => Err(e),
+        let value = match execute_all(body, body_env.clone(), resolver, context)? {
+            ControlFlow::Return(value) => value,
+            _ => match result {
+                Some(result) => evaluate(result, body_env, resolver, context).or_else(|unwind| unwind.into_return_value())?,
+                None => Value::Nil,
+            },
+        };
+        match self.closure.borrow().get_local("this") {
+            Some(instance) if self.is_initializer => Ok(instance),
+            _ => Ok(value),
         }
     }
 }
@@ -79,15 +149,60 @@ impl LoxCallable for LoxFunction {
 pub struct NativeFunction {
     name: String,
     arity: usize,
-    function: fn(Vec<LiteralExpr>) -> Result<LiteralExpr, EvalError>,
+    function: NativeBody,
+}
+
+pub(crate) type ContextNativeFn = fn(Vec<Value>, &mut Context) -> Result<Value, EvalError>;
+
+enum NativeBody {
+    Plain(fn(Vec<Value>) -> Result<Value, EvalError>),
+    /// Natives that need the run context, such as those writing program output.
+    WithContext(ContextNativeFn),
+    #[cfg(feature = "plugins")]
+    Plugin(crate::plugin::PluginFn),
 }
 
 impl NativeFunction {
-    pub fn new(name: &str, arity: usize, function: fn(Vec<LiteralExpr>) -> Result<LiteralExpr, EvalError>) -> Self {
+    pub fn new(name: &str, arity: usize, function: fn(Vec<Value>) -> Result<Value, EvalError>) -> Self {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: NativeBody::Plain(function),
+        }
+    }
+
+    pub(crate) fn with_context(name: &str, arity: usize, function: ContextNativeFn) -> Self {
         NativeFunction {
             name: name.to_string(),
             arity,
-            function,
+            function: NativeBody::WithContext(function),
+        }
+    }
+
+    #[cfg(feature = "plugins")]
+    pub(crate) fn from_plugin(name: &str, arity: usize, function: crate::plugin::PluginFn) -> Self {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: NativeBody::Plugin(function),
+        }
+    }
+
+    fn invoke(&self, arguments: Vec<Value>, context: &mut Context) -> Result<Value, EvalError> {
+        // Natives work with `f64`s, so exact numbers are rounded on the way in.
+        #[cfg(feature = "bignum")]
+        let arguments = arguments
+            .into_iter()
+            .map(|argument| match argument {
+                Value::BigNumber(n) => Value::Number(crate::bignum::to_f64(&n)),
+                other => other,
+            })
+            .collect();
+        match self.function {
+            NativeBody::Plain(function) => function(arguments),
+            NativeBody::WithContext(function) => function(arguments, context),
+            #[cfg(feature = "plugins")]
+            NativeBody::Plugin(function) => crate::plugin::call(&self.name, function, arguments),
         }
     }
 }
@@ -103,12 +218,48 @@ impl LoxCallable for NativeFunction {
 
     fn call(
         &self, 
-        arguments: Vec<LiteralExpr>, 
+        arguments: Vec<Value>, 
+        _environment: Rc<RefCell<Environment>>,
+        _resolver: &Resolver,
+        context: &mut Context
+    ) -> Result<Value, EvalError> {
+        let result = self.invoke(arguments, context)?;
+        Ok(result)
+    }
+}
+
+/// A built-in method of a primitive value, bound to the value it was read
+/// from. The receiver is passed to the implementation as its first argument.
+pub struct BoundMethod {
+    receiver: Value,
+    method: NativeFunction,
+}
+
+impl BoundMethod {
+    pub fn new(receiver: Value, method: NativeFunction) -> Self {
+        BoundMethod { receiver, method }
+    }
+}
+
+impl LoxCallable for BoundMethod {
+    fn arity(&self) -> usize {
+        self.method.arity
+    }
+
+    fn name(&self) -> &str {
+        &self.method.name
+    }
+
+    fn call(
+        &self,
+        arguments: Vec<Value>,
         _environment: Rc<RefCell<Environment>>,
         _resolver: &Resolver,
-        _output : &mut String
-    ) -> Result<Expr, EvalError> {
-        let result = (self.function)(arguments)?;
-        Ok(Expr::Literal(result))
+        context: &mut Context
+    ) -> Result<Value, EvalError> {
+        let mut receiver_and_arguments = vec![self.receiver.clone()];
+        receiver_and_arguments.extend(arguments);
+        let result = self.method.invoke(receiver_and_arguments, context)?;
+        Ok(result)
     }
-}
\ No newline at end of file
+}
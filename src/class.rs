@@ -0,0 +1,103 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::callable::{LoxCallable, LoxFunction};
+use crate::environ::Environment;
+use crate::error::EvalError;
+use crate::value::Value;
+use crate::interpreter::Context;
+use crate::resolver::Resolver;
+
+/// A class declared with `class`. Calling it makes a new instance and runs
+/// its `init` method, if it has one, with the arguments. Cloning is cheap,
+/// since the methods and superclass are shared.
+#[derive(Debug, Clone)]
+pub struct LoxClass {
+    pub name: String,
+    pub superclass: Option<Rc<LoxClass>>,
+    pub methods: Rc<HashMap<String, Rc<LoxFunction>>>,
+}
+
+impl LoxClass {
+    pub fn new(name: String, superclass: Option<Rc<LoxClass>>, methods: HashMap<String, Rc<LoxFunction>>) -> Self {
+        LoxClass { name, superclass, methods: Rc::new(methods) }
+    }
+
+    /// Looks up a method in this class, then up the superclass chain.
+    pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        match self.methods.get(name) {
+            Some(method) => Some(method.clone()),
+            None => self.superclass.as_ref().and_then(|superclass| superclass.find_method(name)),
+        }
+    }
+}
+
+impl LoxCallable for LoxClass {
+    fn arity(&self) -> usize {
+        self.find_method("init").map_or(0, |init| init.arity())
+    }
+
+    fn variadic(&self) -> bool {
+        self.find_method("init").is_some_and(|init| init.variadic())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn as_class(&self) -> Option<&LoxClass> {
+        Some(self)
+    }
+
+    fn call(
+        &self,
+        arguments: Vec<Value>,
+        environment: Rc<RefCell<Environment>>,
+        resolver: &Resolver,
+        context: &mut Context
+    ) -> Result<Value, EvalError> {
+        let instance = Value::Instance(Rc::new(LoxInstance::new(self.clone())));
+        if let Some(init) = self.find_method("init") {
+            init.bind(instance.clone()).call(arguments, environment, resolver, context)?;
+        }
+        Ok(instance)
+    }
+}
+
+/// An object made by calling a class. Fields are created by assigning to
+/// them and shadow methods of the same name.
+pub struct LoxInstance {
+    pub class: LoxClass,
+    fields: RefCell<HashMap<String, Value>>,
+}
+
+impl LoxInstance {
+    pub fn new(class: LoxClass) -> Self {
+        LoxInstance { class, fields: RefCell::new(HashMap::new()) }
+    }
+
+    /// Reads a field of `instance`, or failing that a method of its class
+    /// bound to it.
+    pub fn get(instance: &Rc<LoxInstance>, name: &str) -> Result<Value, EvalError> {
+        if let Some(value) = instance.fields.borrow().get(name) {
+            return Ok(value.clone());
+        }
+        match instance.class.find_method(name) {
+            Some(method) => Ok(Value::Callable(Rc::new(method.bind(Value::Instance(instance.clone()))))),
+            None => Err(EvalError::TypeError(format!("{} instance has no property '{}'", instance.class.name, name))),
+        }
+    }
+
+    pub fn set(&self, name: &str, value: Value) {
+        self.fields.borrow_mut().insert(name.to_string(), value);
+    }
+}
+
+impl fmt::Debug for LoxInstance {
+    // Fields can refer back to the instance, so they are left out.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
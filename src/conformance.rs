@@ -0,0 +1,193 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{ErrorFormat, RunError};
+use crate::{new_globals, parse_program_with_options, run_statements, RunOptions};
+
+/// Chapters of Crafting Interpreters and the directories of the upstream
+/// test suite that exercise them. Files directly in the suite's root count
+/// towards the first chapter.
+const CHAPTERS: &[(&str, &[&str])] = &[
+    ("7. Evaluating Expressions", &["", "operator"]),
+    ("8. Statements and State", &["assignment", "block", "bool", "comments", "nil", "number", "print", "string", "variable"]),
+    ("9. Control Flow", &["if", "logical_operator", "while", "for"]),
+    ("10. Functions", &["call", "function", "return"]),
+    ("11. Resolving and Binding", &["closure", "regression"]),
+    ("12. Classes", &["class", "constructor", "field", "method", "this"]),
+    ("13. Inheritance", &["inheritance", "super"]),
+];
+
+/// Tests for earlier chapters' intermediate interpreters, for clox only, or
+/// that only measure speed.
+const SKIPPED: &[&str] = &["benchmark", "expressions", "limit", "scanning"];
+
+/// Chapter for suite directories missing from [`CHAPTERS`].
+const OTHER: &str = "Other";
+
+/// Statement budget per test, so a test that loops forever fails instead of hanging.
+const FUEL: u64 = 10_000_000;
+
+/// What a test's annotations say running it should produce.
+#[derive(Debug, Default, PartialEq)]
+struct Expectations {
+    /// Lines from `// expect: ...`.
+    output: Vec<String>,
+    /// `[line N] Error...` from `// Error...` and `// [line N] Error...`.
+    compile_errors: Vec<String>,
+    /// Message from `// expect runtime error: ...`. The annotation gives no
+    /// line, so only the message is compared.
+    runtime_error: Option<String>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Expectations {
+        let mut expectations = Expectations::default();
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            if let Some(start) = line.find("// expect:") {
+                let output = &line[start + "// expect:".len()..];
+                expectations.output.push(output.strip_prefix(' ').unwrap_or(output).to_string());
+            } else if let Some(start) = line.find("// Error") {
+                expectations.compile_errors.push(format!("[line {}] {}", line_number, &line[start + 3..]));
+            } else if let Some(start) = line.find("// expect runtime error: ") {
+                let message = &line[start + "// expect runtime error: ".len()..];
+                expectations.runtime_error = Some(message.to_string());
+            } else if let Some(start) = line.find("// [") {
+                // `[c line N]` errors are clox's alone.
+                let annotation = &line[start + 3..];
+                let error = annotation.strip_prefix("[line ").or_else(|| annotation.strip_prefix("[java line "));
+                if let Some((error_line, message)) = error.and_then(|error| error.split_once("] ")) {
+                    if message.starts_with("Error") {
+                        expectations.compile_errors.push(format!("[line {}] {}", error_line, message));
+                    }
+                }
+            }
+        }
+        expectations
+    }
+}
+
+/// What running a test actually produced, in the same shape as [`Expectations`].
+fn run_test(source: &str) -> Expectations {
+    let options = RunOptions::default();
+    let statements = match parse_program_with_options(source, &options) {
+        Ok(statements) => statements,
+        Err(errors) => {
+            return Expectations {
+                compile_errors: errors.iter().map(|e| format!("[line {}] {}", e.line, e.message)).collect(),
+                ..Expectations::default()
+            }
+        }
+    };
+    let options = RunOptions { fuel: Some(FUEL), ..options };
+    let (context, result) = run_statements(&statements, new_globals(options.capabilities), &options);
+    let mut actual =
+        Expectations { output: context.output.lines().map(str::to_string).collect(), ..Expectations::default() };
+    match result {
+        Ok(()) => {}
+        Err(RunError::Resolve(errors)) => {
+            actual.compile_errors = errors.iter().map(|e| format!("[line {}] {}", e.line, e.message)).collect();
+        }
+        Err(RunError::Runtime(e)) => actual.runtime_error = Some(e.unlocated().to_string()),
+        Err(e) => actual.runtime_error = Some(e.render(ErrorFormat::Human)),
+    }
+    actual
+}
+
+/// Passing and failing tests of one chapter.
+#[derive(Debug)]
+pub struct ChapterResult {
+    pub chapter: &'static str,
+    pub passed: usize,
+    pub failures: Vec<PathBuf>,
+}
+
+/// The outcome of running the upstream suite, chapter by chapter in book order.
+#[derive(Debug, Default)]
+pub struct ConformanceReport {
+    pub chapters: Vec<ChapterResult>,
+}
+
+impl ConformanceReport {
+    fn record(&mut self, chapter: &'static str, test: PathBuf, passed: bool) {
+        let index = match self.chapters.iter().position(|result| result.chapter == chapter) {
+            Some(index) => index,
+            None => {
+                self.chapters.push(ChapterResult { chapter, passed: 0, failures: Vec::new() });
+                self.chapters.len() - 1
+            }
+        };
+        let result = &mut self.chapters[index];
+        if passed {
+            result.passed += 1;
+        } else {
+            result.failures.push(test);
+        }
+    }
+
+    fn sort(&mut self) {
+        let order = |chapter: &str| CHAPTERS.iter().position(|(name, _)| *name == chapter).unwrap_or(CHAPTERS.len());
+        self.chapters.sort_by_key(|result| order(result.chapter));
+        for result in &mut self.chapters {
+            result.failures.sort();
+        }
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.chapters.iter().map(|result| result.chapter.len()).chain(["Total".len()]).max().unwrap_or(0);
+        writeln!(f, "{:width$}  {:>6}  {:>6}", "Chapter", "Passed", "Failed")?;
+        let (mut passed, mut failed) = (0, 0);
+        for result in &self.chapters {
+            writeln!(f, "{:width$}  {:>6}  {:>6}", result.chapter, result.passed, result.failures.len())?;
+            passed += result.passed;
+            failed += result.failures.len();
+        }
+        write!(f, "{:width$}  {:>6}  {:>6}", "Total", passed, failed)
+    }
+}
+
+/// Runs the `test` directory of the Crafting Interpreters repository,
+/// checking each script against its `// expect: ...`, `// Error ...` and
+/// `// expect runtime error: ...` annotations the way the upstream test
+/// runner does for jlox. Error messages must match the book's wording.
+pub fn run_conformance(dir: &Path) -> io::Result<ConformanceReport> {
+    let mut report = ConformanceReport::default();
+    for test in test_files(dir)? {
+        let suite_dir = match test.strip_prefix(dir).ok().and_then(|relative| relative.parent()) {
+            Some(parent) => parent.components().next().map(|c| c.as_os_str().to_string_lossy().into_owned()),
+            None => None,
+        }
+        .unwrap_or_default();
+        if SKIPPED.contains(&suite_dir.as_str()) {
+            continue;
+        }
+        let chapter = CHAPTERS
+            .iter()
+            .find(|(_, dirs)| dirs.contains(&suite_dir.as_str()))
+            .map_or(OTHER, |(chapter, _)| chapter);
+
+        let source = fs::read_to_string(&test)?;
+        let passed = run_test(&source) == Expectations::parse(&source);
+        report.record(chapter, test, passed);
+    }
+    report.sort();
+    Ok(report)
+}
+
+/// Every `.lox` file under `dir`, at any depth.
+fn test_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(test_files(&path)?);
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
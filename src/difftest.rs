@@ -0,0 +1,81 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::golden::lox_files;
+use crate::{new_globals, run_with_sinks, RunOptions};
+
+/// What a script printed, and whether it stopped with an error.
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub output: String,
+    pub failed: bool,
+}
+
+/// A script whose output or success differs between rustylox and the reference.
+#[derive(Debug)]
+pub struct Divergence {
+    pub script: PathBuf,
+    pub ours: Outcome,
+    pub reference: Outcome,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = |outcome: &Outcome| if outcome.failed { " (failed)" } else { "" };
+        write!(
+            f,
+            "{}: outputs differ\n--- rustylox{}\n{}--- reference{}\n{}",
+            self.script.display(),
+            status(&self.ours),
+            self.ours.output,
+            status(&self.reference),
+            self.reference.output
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DiffReport {
+    pub matched: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+/// Runs every `.lox` file in `dir` through rustylox and through `reference`,
+/// a command line such as `java -jar jlox.jar` that gets the script's path
+/// appended, and collects the scripts on which the two disagree. Only
+/// standard output and whether each run failed are compared, since error
+/// messages are worded differently across implementations.
+pub fn run_difftest(dir: &Path, reference: &str) -> io::Result<DiffReport> {
+    let mut words = reference.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty reference command"))?;
+    let arguments = words.collect::<Vec<_>>();
+
+    let mut report = DiffReport::default();
+    for script in lox_files(dir)? {
+        let ours = run_ours(&fs::read_to_string(&script)?)?;
+        let output = Command::new(program).args(&arguments).arg(&script).output()?;
+        let reference = Outcome {
+            output: String::from_utf8_lossy(&output.stdout).into_owned(),
+            failed: !output.status.success(),
+        };
+        if ours == reference {
+            report.matched += 1;
+        } else {
+            report.divergences.push(Divergence { script, ours, reference });
+        }
+    }
+    Ok(report)
+}
+
+/// Runs a script, keeping what it printed before any runtime error.
+fn run_ours(source: &str) -> io::Result<Outcome> {
+    let options = RunOptions::default();
+    let mut output = Vec::new();
+    let report = run_with_sinks(source, new_globals(options.capabilities), &options, &mut output, &mut io::sink())?;
+    Ok(Outcome { output: String::from_utf8_lossy(&output).into_owned(), failed: !report.succeeded })
+}
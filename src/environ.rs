@@ -1,14 +1,24 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
-use crate::expr::LiteralExpr;
+use crate::value::Value;
 use crate::error::EvalError;
 use crate::token::Token;
 
 #[derive(Debug, Clone)]
 pub struct Environment {
     pub enclosing: Option<Rc<RefCell<Environment>>>,
-    values: HashMap<String, LiteralExpr>,
+    values: HashMap<String, Value>,
+    /// The names in `values` declared with `const`.
+    constants: HashSet<String>,
+    /// How many environments enclose this one.
+    depth: usize,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Environment {
@@ -16,56 +26,156 @@ impl Environment {
         Environment {
             enclosing: None,
             values: HashMap::new(),
+            constants: HashSet::new(),
+            depth: 0,
         }
     }
 
     pub fn new_enclosed(enclosing: Rc<RefCell<Environment>>) -> Self {
+        let depth = enclosing.borrow().depth + 1;
         Environment {
             enclosing: Some(enclosing),
             values: HashMap::new(),
+            constants: HashSet::new(),
+            depth,
         }
     }
 
-    pub fn define(&mut self, name: String, value: LiteralExpr) {
+    /// How many environments enclose this one; the globals are at depth 0.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The outermost environment around `environment`, holding the globals.
+    pub fn globals(environment: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let depth = environment.borrow().depth;
+        Environment::ancestor(environment, depth)
+    }
+
+    /// The variables defined directly in this environment, in no particular
+    /// order.
+    pub fn variables(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.values.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// A variable defined directly in this environment.
+    pub(crate) fn get_local(&self, name: &str) -> Option<Value> {
+        self.values.get(name).cloned()
+    }
+
+    pub fn define(&mut self, name: String, value: Value) {
+        self.constants.remove(&name);
+        self.values.insert(name, value);
+    }
+
+    /// Defines `name` as a constant, which assignments may not change.
+    pub fn define_constant(&mut self, name: String, value: Value) {
+        self.constants.insert(name.clone());
         self.values.insert(name, value);
     }
 
-    pub fn assign(&mut self, name: &Token, value: LiteralExpr) -> Result<(), EvalError> {
+    pub fn assign(&mut self, name: &Token, value: Value) -> Result<(), EvalError> {
         if self.values.contains_key(&name.lexeme) {
-            self.values.insert(name.lexeme.clone(), value);
-            Ok(())
+            self.set_existing(name, value)
         } else {
             if let Some(enclosing) = &self.enclosing {
                 enclosing.borrow_mut().assign(name, value)
             } else {
-                Err(EvalError::UndefinedVariable(name.lexeme.clone()))
+                Err(self.undefined(name))
             }
         }
     }
 
-    pub fn get(&self, name: &Token) -> Result<LiteralExpr, EvalError> {
+    pub fn get(&self, name: &Token) -> Result<Value, EvalError> {
         if let Some(value) = self.values.get(&name.lexeme) {
             Ok(value.clone())
         } else {
             if let Some(enclosing) = &self.enclosing {
                 enclosing.borrow().get(name)
             } else {
-                Err(EvalError::UndefinedVariable(name.lexeme.clone()))
+                Err(self.undefined(name))
+            }
+        }
+    }
+
+    /// The environment `distance` steps out from `environment`, or the
+    /// globals if the chain is shorter than that.
+    pub fn ancestor(environment: &Rc<RefCell<Environment>>, distance: usize) -> Rc<RefCell<Environment>> {
+        let mut current = environment.clone();
+        for _ in 0..distance {
+            let enclosing = current.borrow().enclosing.clone();
+            match enclosing {
+                Some(enclosing) => current = enclosing,
+                None => break,
             }
         }
+        current
     }
 
-    pub fn get_at_depth(&self, name: &Token, depth: usize) -> Result<LiteralExpr, EvalError> {
-        let mut environment = Rc::new(RefCell::new(self.clone()));
-    
-        for _ in 0..depth {
-            let env = environment.clone();
-            environment = match &env.borrow().enclosing {
-                Some(enclosing) => enclosing.clone(),
-                None => return Err(EvalError::UndefinedVariable(name.lexeme.clone())),
-            };
+    /// Reads `name` from the environment `distance` steps out, where the
+    /// resolver found it, without looking any further.
+    pub fn get_at(environment: &Rc<RefCell<Environment>>, distance: usize, name: &Token) -> Result<Value, EvalError> {
+        let ancestor = Environment::ancestor(environment, distance);
+        let ancestor = ancestor.borrow();
+        match ancestor.values.get(&name.lexeme) {
+            Some(value) => Ok(value.clone()),
+            None => Err(ancestor.undefined(name)),
         }
-    
-        let temp = environment.borrow().get(name); temp
-    }    
+    }
+
+    /// Assigns `name` in the environment `distance` steps out.
+    pub fn assign_at(environment: &Rc<RefCell<Environment>>, distance: usize, name: &Token, value: Value) -> Result<(), EvalError> {
+        let ancestor = Environment::ancestor(environment, distance);
+        let mut ancestor = ancestor.borrow_mut();
+        if !ancestor.values.contains_key(&name.lexeme) {
+            return Err(ancestor.undefined(name));
+        }
+        ancestor.set_existing(name, value)
+    }
+
+    /// Changes the value of `name`, defined in this environment, unless it
+    /// is a constant. The resolver rejects most such assignments before the
+    /// program runs; this catches those it cannot see, such as one made in a
+    /// later REPL input.
+    fn set_existing(&mut self, name: &Token, value: Value) -> Result<(), EvalError> {
+        if self.constants.contains(&name.lexeme) {
+            return Err(EvalError::ConstantAssignment(name.lexeme.clone()));
+        }
+        self.values.insert(name.lexeme.clone(), value);
+        Ok(())
+    }
+
+    /// Builds an undefined-variable error, suggesting the closest visible name.
+    fn undefined(&self, name: &Token) -> EvalError {
+        let mut suggestion: Option<(usize, String)> = None;
+        let mut names: Vec<String> = self.values.keys().cloned().collect();
+        let mut enclosing = self.enclosing.clone();
+        while let Some(env) = enclosing {
+            names.extend(env.borrow().values.keys().cloned());
+            enclosing = env.borrow().enclosing.clone();
+        }
+
+        for candidate in names {
+            let distance = edit_distance(&name.lexeme, &candidate);
+            if distance <= 2 && suggestion.as_ref().is_none_or(|(best, _)| distance < *best) {
+                suggestion = Some((distance, candidate));
+            }
+        }
+        EvalError::UndefinedVariable(name.lexeme.clone(), suggestion.map(|(_, candidate)| candidate))
+    }
+}
+
+/// Levenshtein distance between two identifiers.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
 }
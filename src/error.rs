@@ -1,10 +1,162 @@
 use std::fmt;
+use std::str::FromStr;
 
-use crate::expr::Expr;
+use serde::Serialize;
+
+use crate::token::{Span, Token};
+
+/// How the CLI renders errors, selected with `--error-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorFormat {
+    #[default]
+    Human,
+    /// One JSON object per line, for editors and CI.
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("Unknown error format '{}', expected 'human' or 'json'", s)),
+        }
+    }
+}
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Where a [`Diagnostic`] points: always a line, and the column and byte
+/// range too when the problem is tied to a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DiagnosticSpan {
+    pub line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<usize>,
+}
+
+impl DiagnosticSpan {
+    pub fn line(line: usize) -> Self {
+        DiagnosticSpan { line, column: None, start: None, end: None }
+    }
+
+    pub fn at(line: usize, column: usize, span: Span) -> Self {
+        DiagnosticSpan { line, column: Some(column), start: Some(span.start), end: Some(span.end) }
+    }
+}
+
+/// A problem found in any phase, from lexing to running the program. Each
+/// phase has its own error type, and all of them turn into this one shape
+/// to be rendered, so the CLI and the wasm bindings show every problem the
+/// same way.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub severity: Severity,
+    /// How human output introduces the message, e.g. "Error" or "Type error".
+    #[serde(skip)]
+    pub kind: &'static str,
+    pub message: String,
+    pub span: Option<DiagnosticSpan>,
+    /// Extra hints shown below the message.
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: String, span: Option<DiagnosticSpan>) -> Self {
+        Diagnostic { code, severity: Severity::Error, kind: "Error", message, span, notes: Vec::new() }
+    }
+
+    /// Renders the diagnostic in `format`. Human output shows the line of
+    /// `source`, when given, that the diagnostic points at, with the
+    /// offending token underlined.
+    pub fn render(&self, format: ErrorFormat, source: Option<&str>) -> String {
+        match format {
+            ErrorFormat::Human => {
+                let mut rendered = match self.span {
+                    Some(span) => format!("[line {}] {}: {}", span.line, self.kind, self.message),
+                    None => self.message.clone(),
+                };
+                if let Some(snippet) = source.and_then(|source| self.snippet(source)) {
+                    rendered.push_str(&snippet);
+                }
+                for note in &self.notes {
+                    rendered.push_str(&format!("\n    note: {}", note));
+                }
+                rendered
+            }
+            ErrorFormat::Json => self.to_json(),
+        }
+    }
+
+    /// One line of JSON, for editors and CI.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("diagnostics are always serializable")
+    }
+
+    /// The source line the diagnostic points at with its token underlined.
+    fn snippet(&self, source: &str) -> Option<String> {
+        let span = self.span?;
+        let (column, start, end) = (span.column?, span.start?, span.end?);
+        let text = source.lines().nth(span.line.checked_sub(1)?)?;
+        // Tabs are kept so the underline lines up however they are shown.
+        let indent: String = text.chars().take(column - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let width = source.get(start..end).map_or(1, |lexeme| lexeme.lines().next().unwrap_or("").chars().count().max(1));
+        let gutter = " ".repeat(span.line.to_string().len());
+        Some(format!("\n {} | {}\n {} | {}{}", span.line, text, gutter, indent, "^".repeat(width)))
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(ErrorFormat::Human, None))
+    }
+}
+
+/// Source text the lexer could not make a token of, such as a stray
+/// character or an unterminated string.
+#[derive(Debug, Clone)]
+pub struct LexError {
+    pub line: usize,
+    pub column: usize,
+    pub span: Span,
+    pub message: String,
+}
 
 #[derive(Debug, Clone)]
 pub struct ParserError {
     pub line: usize,
+    /// Column of the offending token, when the error is tied to one.
+    pub column: Option<usize>,
+    /// Byte range of the offending token, underlined when the error is
+    /// shown with its source.
+    pub span: Option<Span>,
+    pub message: String,
+    /// Extra hints shown below the message.
+    pub notes: Vec<String>,
+}
+
+/// A mistake found by [`crate::resolver::Resolver`] once the program has
+/// parsed but before it runs, such as reading a local variable in its own
+/// initializer.
+#[derive(Debug, Clone)]
+pub struct ResolveError {
+    pub line: usize,
+    pub column: usize,
+    /// Byte range of the offending token.
+    pub span: Span,
     pub message: String,
 }
 
@@ -14,16 +166,166 @@ pub struct RuntimeError {
     pub line: usize,
 }
 
+/// A place where a program disagrees with its type annotations, found by
+/// [`crate::typecheck::typecheck`] before the program runs.
 #[derive(Debug, Clone)]
-pub enum ControlFlow {
-    Break,
-    Continue,
-    Return(Expr),
+pub struct TypeCheckError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Code that stops the program with a type error whenever it runs, found
+/// by [`crate::typecheck::typecheck`]. Reported without failing the check.
+#[derive(Debug, Clone)]
+pub struct Warning {
+    pub line: usize,
+    pub message: String,
+}
+
+impl LexError {
+    pub fn code(&self) -> &'static str {
+        "E0002"
+    }
+
+    pub fn to_json(&self) -> String {
+        Diagnostic::from(self).to_json()
+    }
+}
+
+impl From<&LexError> for Diagnostic {
+    fn from(error: &LexError) -> Self {
+        let span = DiagnosticSpan::at(error.line, error.column, error.span);
+        Diagnostic::error(error.code(), error.message.clone(), Some(span))
+    }
+}
+
+/// The parser reports lexer errors among its own.
+impl From<LexError> for ParserError {
+    fn from(error: LexError) -> Self {
+        ParserError {
+            column: Some(error.column),
+            span: Some(error.span),
+            ..ParserError::new(error.line, error.message)
+        }
+    }
 }
 
 impl ParserError {
     pub fn new(line: usize, message: String) -> Self {
-        ParserError { line, message }
+        ParserError { line, column: None, span: None, message, notes: Vec::new() }
+    }
+
+    /// An error pointing at `token`.
+    pub fn at(token: &Token, message: String) -> Self {
+        ParserError { column: Some(token.column), span: Some(token.span), ..ParserError::new(token.line, message) }
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn code(&self) -> &'static str {
+        "E0001"
+    }
+
+    pub fn to_json(&self) -> String {
+        Diagnostic::from(self).to_json()
+    }
+
+    /// Like the `Display` output, with the source line the error is on
+    /// shown under the message and the offending token underlined.
+    pub fn render_with_source(&self, source: &str) -> String {
+        Diagnostic::from(self).render(ErrorFormat::Human, Some(source))
+    }
+}
+
+impl From<&ParserError> for Diagnostic {
+    fn from(error: &ParserError) -> Self {
+        let span = match (error.column, error.span) {
+            (Some(column), Some(span)) => DiagnosticSpan::at(error.line, column, span),
+            _ => DiagnosticSpan::line(error.line),
+        };
+        Diagnostic { notes: error.notes.clone(), ..Diagnostic::error(error.code(), error.message.clone(), Some(span)) }
+    }
+}
+
+impl ResolveError {
+    /// An error pointing at `token`.
+    pub fn at(token: &Token, message: &str) -> Self {
+        ResolveError {
+            line: token.line,
+            column: token.column,
+            span: token.span,
+            message: format!("Error at '{}': {}", token.lexeme, message),
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        "E0003"
+    }
+
+    pub fn to_json(&self) -> String {
+        Diagnostic::from(self).to_json()
+    }
+}
+
+impl From<&ResolveError> for Diagnostic {
+    fn from(error: &ResolveError) -> Self {
+        let span = DiagnosticSpan::at(error.line, error.column, error.span);
+        Diagnostic::error(error.code(), error.message.clone(), Some(span))
+    }
+}
+
+impl TypeCheckError {
+    pub fn new(line: usize, message: String) -> Self {
+        TypeCheckError { line, message }
+    }
+
+    pub fn code(&self) -> &'static str {
+        "E0201"
+    }
+
+    pub fn to_json(&self) -> String {
+        Diagnostic::from(self).to_json()
+    }
+}
+
+impl From<&TypeCheckError> for Diagnostic {
+    fn from(error: &TypeCheckError) -> Self {
+        let span = DiagnosticSpan::line(error.line);
+        Diagnostic { kind: "Type error", ..Diagnostic::error(error.code(), error.message.clone(), Some(span)) }
+    }
+}
+
+impl Warning {
+    pub fn new(line: usize, message: String) -> Self {
+        Warning { line, message }
+    }
+
+    pub fn code(&self) -> &'static str {
+        "W0001"
+    }
+
+    pub fn to_json(&self) -> String {
+        Diagnostic::from(self).to_json()
+    }
+
+    pub fn render(&self, format: ErrorFormat) -> String {
+        Diagnostic::from(self).render(format, None)
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        Diagnostic {
+            code: warning.code(),
+            severity: Severity::Warning,
+            kind: "Warning",
+            message: warning.message.clone(),
+            span: Some(DiagnosticSpan::line(warning.line)),
+            notes: Vec::new(),
+        }
     }
 }
 
@@ -31,22 +333,183 @@ impl ParserError {
 #[allow(dead_code)]
 pub enum EvalError {
     DivisionByZero,
-    UndefinedVariable(String),
+    /// Name of the variable and the closest defined name, if any.
+    UndefinedVariable(String, Option<String>),
     TypeError(String),
     SyntaxError(String),
-    ControlFlow(ControlFlow),
     ArityError(usize, usize),
+    /// A call passed fewer arguments than the least a function with a rest
+    /// parameter takes: that least, then how many it passed.
+    TooFewArguments(usize, usize),
+    /// A native function could not do its work, e.g. a command failed to start.
+    NativeError(String),
+    /// Too many calls were active at once, usually from unbounded recursion:
+    /// the function being called and how many calls deep it was.
+    StackOverflow(String, usize),
+    /// The program ran out of the statement budget it was given.
+    OutOfFuel,
+    /// A single run of the loop on this line went past the iteration limit.
+    LoopLimitExceeded(usize, u64),
+    /// An assignment to the variable declared with `const` of this name.
+    ConstantAssignment(String),
+    /// Another error, raised by code on this line.
+    AtLine(usize, Box<EvalError>),
+}
+
+impl EvalError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            EvalError::DivisionByZero => "E0101",
+            EvalError::UndefinedVariable(..) => "E0102",
+            EvalError::TypeError(_) => "E0103",
+            EvalError::SyntaxError(_) => "E0104",
+            EvalError::ArityError(..) | EvalError::TooFewArguments(..) => "E0106",
+            EvalError::NativeError(_) => "E0107",
+            EvalError::StackOverflow(..) => "E0108",
+            EvalError::OutOfFuel => "E0109",
+            EvalError::LoopLimitExceeded(..) => "E0110",
+            EvalError::ConstantAssignment(_) => "E0111",
+            EvalError::AtLine(_, error) => error.code(),
+        }
+    }
+
+    /// Points the error at `line`, unless it already names a line.
+    pub fn at_line(self, line: Option<usize>) -> EvalError {
+        match (self, line) {
+            (error @ (EvalError::AtLine(..) | EvalError::LoopLimitExceeded(..)), _) => error,
+            (error, Some(line)) => EvalError::AtLine(line, Box::new(error)),
+            (error, None) => error,
+        }
+    }
+
+    /// The error without the line it was raised on.
+    pub fn unlocated(&self) -> &EvalError {
+        match self {
+            EvalError::AtLine(_, error) => error.unlocated(),
+            error => error,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        Diagnostic::from(self).to_json()
+    }
+}
+
+impl From<&EvalError> for Diagnostic {
+    fn from(error: &EvalError) -> Self {
+        let EvalError::AtLine(line, inner) = error else {
+            return Diagnostic::error(error.code(), error.to_string(), None);
+        };
+        let (kind, message) = match inner.as_ref() {
+            EvalError::TypeError(message) => ("Type error", format!("{}.", message)),
+            EvalError::SyntaxError(message) => ("Syntax error", format!("{}.", message)),
+            inner => ("Error", inner.to_string()),
+        };
+        Diagnostic { kind, ..Diagnostic::error(error.code(), message, Some(DiagnosticSpan::line(*line))) }
+    }
+}
+
+/// Why running a program failed: it did not parse, the resolver rejected
+/// it, it failed type checking, or it stopped at a runtime error.
+#[derive(Debug)]
+pub enum RunError {
+    Parse(Vec<ParserError>),
+    Resolve(Vec<ResolveError>),
+    Type(Vec<TypeCheckError>),
+    Runtime(EvalError),
+}
+
+impl RunError {
+    /// Every problem that stopped the run, in order.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            RunError::Parse(errors) => errors.iter().map(Diagnostic::from).collect(),
+            RunError::Resolve(errors) => errors.iter().map(Diagnostic::from).collect(),
+            RunError::Type(errors) => errors.iter().map(Diagnostic::from).collect(),
+            RunError::Runtime(error) => vec![Diagnostic::from(error)],
+        }
+    }
+
+    pub fn render(&self, format: ErrorFormat) -> String {
+        self.render_diagnostics(format, None)
+    }
+
+    /// Like [`RunError::render`], showing in human format the line of
+    /// `source` each problem is on.
+    pub fn render_with_source(&self, format: ErrorFormat, source: &str) -> String {
+        self.render_diagnostics(format, Some(source))
+    }
+
+    fn render_diagnostics(&self, format: ErrorFormat, source: Option<&str>) -> String {
+        self.diagnostics().iter().map(|d| d.render(format, source)).collect::<Vec<_>>().join("\n")
+    }
 }
 
 impl fmt::Display for EvalError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            EvalError::DivisionByZero => write!(f, "Division by zero."),
+            EvalError::UndefinedVariable(name, None) => write!(f, "Undefined variable '{}'.", name),
+            EvalError::UndefinedVariable(name, Some(suggestion)) => {
+                write!(f, "Undefined variable '{}'. Did you mean '{}'?", name, suggestion)
+            }
+            EvalError::TypeError(message) => write!(f, "Type error: {}.", message),
+            EvalError::SyntaxError(message) => write!(f, "Syntax error: {}.", message),
+            EvalError::ArityError(expected, got) => write!(
+                f,
+                "Expected {} argument{} but got {}.",
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                got
+            ),
+            EvalError::TooFewArguments(least, got) => write!(
+                f,
+                "Expected at least {} argument{} but got {}.",
+                least,
+                if *least == 1 { "" } else { "s" },
+                got
+            ),
+            EvalError::NativeError(message) => write!(f, "{}.", message),
+            EvalError::StackOverflow(function, depth) => {
+                write!(f, "Stack overflow in '{}' at call depth {}.", function, depth)
+            }
+            EvalError::OutOfFuel => write!(f, "Program ran out of fuel."),
+            EvalError::LoopLimitExceeded(line, limit) => {
+                write!(f, "Loop on line {} ran more than {} iterations.", line, limit)
+            }
+            EvalError::ConstantAssignment(name) => write!(f, "Can't assign to constant '{}'.", name),
+            EvalError::AtLine(..) => Diagnostic::from(self).fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Diagnostic::from(self).fmt(f)
     }
 }
 
 impl fmt::Display for ParserError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "[line {}] Error: {}", self.line, self.message)
+        Diagnostic::from(self).fmt(f)
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Diagnostic::from(self).fmt(f)
+    }
+}
+
+impl fmt::Display for TypeCheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Diagnostic::from(self).fmt(f)
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Diagnostic::from(self).fmt(f)
     }
 }
 
@@ -58,4 +521,6 @@ impl fmt::Display for RuntimeError {
 
 impl std::error::Error for RuntimeError {}
 
-impl std::error::Error for ParserError {}
\ No newline at end of file
+impl std::error::Error for ParserError {}
+
+impl std::error::Error for LexError {}
\ No newline at end of file
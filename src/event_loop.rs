@@ -0,0 +1,215 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use wasm_bindgen::prelude::*;
+
+use crate::callable::{ContextNativeFn, LoxCallable, NativeFunction};
+use crate::environ::Environment;
+use crate::error::{ErrorFormat, EvalError, RunError};
+use crate::value::Value;
+use crate::interpreter::{CallFrame, Context};
+use crate::resolver::Resolver;
+use crate::{new_globals, parse_program_with_options, run_statements, RunOptions};
+
+/// Intervals shorter than this are stretched to it, as browsers do, so that
+/// advancing the clock always finishes.
+const MIN_INTERVAL_MS: f64 = 1.0;
+
+/// Callbacks [`EventLoop::run_until_idle`] runs before giving up on an
+/// interval that is never cleared.
+const MAX_IDLE_CALLBACKS: usize = 10_000;
+
+static NEXT_TIMER: AtomicUsize = AtomicUsize::new(1);
+
+/// A change to the timers asked for by a running program, picked up by the
+/// event loop once the program or callback returns.
+#[derive(Debug)]
+pub enum TimerRequest {
+    Schedule {
+        id: usize,
+        callback: Rc<dyn LoxCallable>,
+        delay_ms: f64,
+        /// Run again every `delay_ms` until cleared.
+        repeat: bool,
+    },
+    Clear(usize),
+}
+
+#[derive(Debug)]
+struct Timer {
+    id: usize,
+    callback: Rc<dyn LoxCallable>,
+    due_ms: f64,
+    interval_ms: Option<f64>,
+}
+
+/// A playground session: globals that outlive each run, and timers set by
+/// `set_timeout` and `set_interval` that run on a clock the page moves
+/// forward. A page drives it from the browser's event loop, calling
+/// `advance` from `requestAnimationFrame` or from a `setTimeout` of
+/// `next_delay()` milliseconds, so scripts can animate or poll without
+/// blocking the page.
+#[wasm_bindgen]
+pub struct EventLoop {
+    globals: Rc<RefCell<Environment>>,
+    options: RunOptions,
+    now_ms: f64,
+    timers: Vec<Timer>,
+}
+
+impl Default for EventLoop {
+    fn default() -> Self {
+        EventLoop::new()
+    }
+}
+
+#[wasm_bindgen]
+impl EventLoop {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> EventLoop {
+        let options = RunOptions::default();
+        let globals = new_globals(options.capabilities);
+        define_timer_natives(&mut globals.borrow_mut());
+        EventLoop { globals, options, now_ms: 0.0, timers: Vec::new() }
+    }
+
+    /// Runs a program in the session's globals, returning what it printed
+    /// followed by any error. Timers it sets start counting from now.
+    pub fn run(&mut self, code: &str) -> String {
+        let statements = match parse_program_with_options(code, &self.options) {
+            Ok(statements) => statements,
+            Err(errors) => return RunError::Parse(errors).render(ErrorFormat::Human),
+        };
+        let (context, result) = run_statements(&statements, self.globals.clone(), &self.options);
+        self.finish(context, result)
+    }
+
+    /// Moves the clock forward by `ms`, running the callbacks that fall due
+    /// on the way in order, and returns what they printed.
+    pub fn advance(&mut self, ms: f64) -> String {
+        let until = self.now_ms + ms.max(0.0);
+        let mut output = String::new();
+        while let Some(index) = self.next_timer().filter(|&index| self.timers[index].due_ms <= until) {
+            output.push_str(&self.fire(index));
+        }
+        self.now_ms = until;
+        output
+    }
+
+    /// Runs callbacks in order, without waiting for them to fall due, until
+    /// no timers are left, and returns what they printed. Stops after
+    /// 10000 callbacks, since an interval that is never cleared runs forever.
+    pub fn run_until_idle(&mut self) -> String {
+        let mut output = String::new();
+        for _ in 0..MAX_IDLE_CALLBACKS {
+            match self.next_timer() {
+                Some(index) => output.push_str(&self.fire(index)),
+                None => return output,
+            }
+        }
+        output.push_str(&format!("Stopped after {} timer callbacks; clear intervals with clear_timer.\n", MAX_IDLE_CALLBACKS));
+        output
+    }
+
+    /// Milliseconds until the next callback falls due, if any timers are set.
+    pub fn next_delay(&self) -> Option<f64> {
+        self.next_timer().map(|index| (self.timers[index].due_ms - self.now_ms).max(0.0))
+    }
+
+    /// How many timers are set.
+    pub fn pending(&self) -> usize {
+        self.timers.len()
+    }
+}
+
+impl EventLoop {
+    /// The timer due soonest, the one set first among those due together.
+    fn next_timer(&self) -> Option<usize> {
+        (0..self.timers.len()).min_by(|&a, &b| {
+            let (a, b) = (&self.timers[a], &self.timers[b]);
+            a.due_ms.total_cmp(&b.due_ms).then(a.id.cmp(&b.id))
+        })
+    }
+
+    /// Runs the timer at `index`, setting it again first if it repeats.
+    fn fire(&mut self, index: usize) -> String {
+        let timer = self.timers.remove(index);
+        self.now_ms = self.now_ms.max(timer.due_ms);
+        if let Some(interval_ms) = timer.interval_ms {
+            self.timers.push(Timer { due_ms: self.now_ms + interval_ms, callback: timer.callback.clone(), ..timer });
+        }
+
+        let mut context = Context::new(self.options.language);
+        context.fuel = self.options.fuel;
+        context.max_call_depth = self.options.max_call_depth;
+        context.max_loop_iterations = self.options.max_loop_iterations;
+        context.frames.push(CallFrame { function: timer.callback.name().to_string(), line: 0 });
+        let result = timer.callback.call(Vec::new(), self.globals.clone(), &Resolver::new(), &mut context);
+        context.frames.pop();
+        self.finish(context, result.map(|_| ()).map_err(RunError::Runtime))
+    }
+
+    /// Applies the timer changes a run asked for and renders what it printed.
+    fn finish(&mut self, context: Context, result: Result<(), RunError>) -> String {
+        for request in context.timers {
+            match request {
+                TimerRequest::Schedule { id, callback, delay_ms, repeat } => {
+                    let delay_ms = if repeat { delay_ms.max(MIN_INTERVAL_MS) } else { delay_ms.max(0.0) };
+                    let interval_ms = repeat.then_some(delay_ms);
+                    self.timers.push(Timer { id, callback, due_ms: self.now_ms + delay_ms, interval_ms });
+                }
+                TimerRequest::Clear(id) => self.timers.retain(|timer| timer.id != id),
+            }
+        }
+        let mut output = context.output;
+        if let Err(e) = result {
+            output.push_str(&e.render(ErrorFormat::Human));
+            output.push('\n');
+        }
+        output
+    }
+}
+
+/// Defines `set_timeout`, `set_interval` and `clear_timer`, which only
+/// programs run by an [`EventLoop`] have.
+fn define_timer_natives(environment: &mut Environment) {
+    let natives: [(&str, usize, ContextNativeFn); 3] = [
+        ("set_timeout", 2, set_timeout),
+        ("set_interval", 2, set_interval),
+        ("clear_timer", 1, clear_timer),
+    ];
+    for (name, arity, function) in natives {
+        let native = NativeFunction::with_context(name, arity, function);
+        environment.define(name.to_string(), Value::Callable(Rc::new(native)));
+    }
+}
+
+fn schedule(args: Vec<Value>, context: &mut Context, repeat: bool) -> Result<Value, EvalError> {
+    let (callback, delay_ms) = match (&args[0], &args[1]) {
+        (Value::Callable(callback), Value::Number(delay_ms)) if callback.arity() == 0 => (callback.clone(), *delay_ms),
+        _ => return Err(EvalError::TypeError("Timers take a function taking no arguments and a delay in milliseconds".to_string())),
+    };
+    let id = NEXT_TIMER.fetch_add(1, Ordering::Relaxed);
+    context.timers.push(TimerRequest::Schedule { id, callback, delay_ms, repeat });
+    Ok(Value::Number(id as f64))
+}
+
+/// `set_timeout(f, ms)` runs `f` once, `ms` milliseconds from now, and returns a timer id.
+fn set_timeout(args: Vec<Value>, context: &mut Context) -> Result<Value, EvalError> {
+    schedule(args, context, false)
+}
+
+/// `set_interval(f, ms)` runs `f` every `ms` milliseconds until the timer is cleared.
+fn set_interval(args: Vec<Value>, context: &mut Context) -> Result<Value, EvalError> {
+    schedule(args, context, true)
+}
+
+/// `clear_timer(id)` stops a timeout or an interval.
+fn clear_timer(args: Vec<Value>, context: &mut Context) -> Result<Value, EvalError> {
+    match args[0] {
+        Value::Number(id) => context.timers.push(TimerRequest::Clear(id as usize)),
+        ref other => return Err(EvalError::TypeError(format!("Expected a timer id but got {}", other.type_name()))),
+    }
+    Ok(Value::Nil)
+}
@@ -0,0 +1,69 @@
+use std::fmt::Write;
+
+use crate::expr::Expr;
+use crate::interpreter::{format_element, BindingEvent, EvalHook, Unwind};
+use crate::printer::expr_to_source;
+use crate::value::Value;
+
+/// The hook behind `--explain`: narrates each step of the evaluation,
+/// indented by how deeply it is nested, between the lines the program
+/// prints. Each expression made of others is announced before its parts
+/// are evaluated and followed by the value it came to, and every variable
+/// read, assigned or defined on the way is noted.
+///
+/// ```text
+/// evaluate a + b * 2
+///   read a = 1
+///   evaluate b * 2
+///     read b = 3
+///   b * 2 is 6
+/// a + b * 2 is 7
+/// ```
+#[derive(Debug, Default)]
+pub struct Explain {
+    depth: usize,
+}
+
+impl Explain {
+    fn line(&self, output: &mut String, text: &str) {
+        // Writing to a `String` cannot fail.
+        let _ = writeln!(output, "{:indent$}{}", "", text, indent = self.depth * 2);
+    }
+}
+
+/// Values and variables speak for themselves, so only the expressions
+/// combining others get a step of their own.
+fn is_step(expr: &Expr) -> bool {
+    !matches!(expr, Expr::Constant(..) | Expr::Variable(..) | Expr::Grouping(..))
+}
+
+impl EvalHook for Explain {
+    fn enter_expr(&mut self, expr: &Expr, output: &mut String) {
+        if is_step(expr) {
+            self.line(output, &format!("evaluate {}", expr_to_source(expr)));
+            self.depth += 1;
+        }
+    }
+
+    fn exit_expr(&mut self, expr: &Expr, result: &Result<Value, Unwind>, output: &mut String) {
+        if !is_step(expr) {
+            return;
+        }
+        self.depth -= 1;
+        match result {
+            Ok(value) => self.line(output, &format!("{} is {}", expr_to_source(expr), format_element(value))),
+            // Returning, breaking or continuing is not a failure.
+            Err(Unwind::Jump(_)) => (),
+            Err(Unwind::Error(e)) => self.line(output, &format!("{} fails: {}", expr_to_source(expr), e)),
+        }
+    }
+
+    fn binding(&mut self, event: BindingEvent, name: &str, value: &Value, output: &mut String) {
+        let verb = match event {
+            BindingEvent::Read => "read",
+            BindingEvent::Assign => "assign",
+            BindingEvent::Define => "define",
+        };
+        self.line(output, &format!("{} {} = {}", verb, name, format_element(value)));
+    }
+}
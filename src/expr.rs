@@ -1,18 +1,37 @@
-use std::{fmt, rc::Rc};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::{callable::LoxCallable, token::Token};
-use std::fmt::Debug;
+use crate::{stmt::Stmt, token::{Literal, Span, Token}};
 
+/// An expression. Each node can tell the source range it was parsed from
+/// through [`Expr::span`]: nodes holding tokens take it from them, the rest
+/// store it.
 #[derive(Debug, Clone)]
 pub enum Expr {
     Binary(Box<BinaryExpr>),
-    Grouping(Box<Expr>),
-    Literal(LiteralExpr),
+    /// A parenthesized expression and the span including the parentheses.
+    Grouping(Box<Expr>, Span),
+    /// A literal written in the source, such as `1`, `"a"` or `nil`.
+    Constant(LiteralExpr, Span),
     Unary(Box<UnaryExpr>),
-    Variable(Token),
-    Assign(Token, Box<Expr>),
+    Variable(Token, ExprId),
+    Assign(Token, Box<Expr>, ExprId),
     Logical(Box<LogicalExpr>),
     Call(Box<CallExpr>),
+    Get(Box<GetExpr>),
+    Set(Box<SetExpr>),
+    /// Subscript access, `object[index]`.
+    Index(Box<IndexExpr>),
+    /// Assignment through a subscript, `object[index] = value`.
+    IndexSet(Box<IndexSetExpr>),
+    /// A map literal, `{"key": value}`.
+    Map(Box<MapExpr>),
+    /// The `this` keyword, the instance a method was called on.
+    This(Token),
+    /// `super.method`: the `super` keyword and the method's name.
+    Super(Token, Token),
+    If(Box<IfExpr>),
+    Lambda(Box<LambdaExpr>),
+    Block(Box<BlockExpr>),
 }
 
 #[derive(Debug, Clone)]
@@ -28,23 +47,34 @@ pub struct UnaryExpr {
     pub right: Expr,
 }
 
-#[derive(Clone)]
+/// Identifies a variable or assignment expression, so the resolver can
+/// record which scope each use of a name refers to. Ids are unique across
+/// every program parsed in the process, and clones of a node share its id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExprId(usize);
+
+impl ExprId {
+    pub fn fresh() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        ExprId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// A literal written in the source. Values only made while running, such
+/// as functions and instances, are [`Value`](crate::value::Value)s instead.
+#[derive(Debug, Clone)]
 pub enum LiteralExpr {
     Number(f64),
     String(String),
     Boolean(bool),
-    Callable(Rc<dyn LoxCallable>),
-    Nil
+    Nil,
 }
 
-impl Debug for LiteralExpr {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            LiteralExpr::Number(n) => write!(f, "Number({})", n),
-            LiteralExpr::String(s) => write!(f, "String(\"{}\")", s),
-            LiteralExpr::Boolean(b) => write!(f, "Boolean({})", b),
-            LiteralExpr::Callable(_) => write!(f, "Callable(<function>)"),
-            LiteralExpr::Nil => write!(f, "Nil"),
+impl From<&Literal> for LiteralExpr {
+    fn from(literal: &Literal) -> Self {
+        match literal {
+            Literal::Number(n) => LiteralExpr::Number(*n),
+            Literal::String(s) => LiteralExpr::String(s.clone()),
         }
     }
 }
@@ -61,6 +91,139 @@ pub struct CallExpr {
     pub callee: Expr,
     pub paren: Token,
     pub arguments: Vec<Expr>,
+    /// Whether the last argument is written `...list`, passing each item of
+    /// the list as an argument of its own.
+    pub spread: bool,
+}
+
+/// Property access, `object.name`, or `object?.name` when `optional`.
+#[derive(Debug, Clone)]
+pub struct GetExpr {
+    pub object: Expr,
+    pub name: Token,
+    /// Evaluates to nil instead of failing when the object is nil.
+    pub optional: bool,
+}
+
+/// Assignment to a property, `object.name = value`.
+#[derive(Debug, Clone)]
+pub struct SetExpr {
+    pub object: Expr,
+    pub name: Token,
+    pub value: Expr,
+}
+
+/// Subscript access, `object[index]`, reading a map by key or a list by
+/// position.
+#[derive(Debug, Clone)]
+pub struct IndexExpr {
+    pub object: Expr,
+    pub index: Expr,
+    /// The closing bracket.
+    pub bracket: Token,
+}
+
+/// Assignment through a subscript, `object[index] = value`, setting a key
+/// of a map or replacing an item of a list.
+#[derive(Debug, Clone)]
+pub struct IndexSetExpr {
+    pub object: Expr,
+    pub index: Expr,
+    /// The closing bracket.
+    pub bracket: Token,
+    pub value: Expr,
+}
+
+/// A map literal. Keys are string literals, in the order written.
+#[derive(Debug, Clone)]
+pub struct MapExpr {
+    pub brace: Token,
+    pub entries: Vec<(Token, Expr)>,
+    /// From the opening brace to the closing one.
+    pub span: Span,
+}
+
+/// `if (condition) then_branch else else_branch` used as an expression.
+#[derive(Debug, Clone)]
+pub struct IfExpr {
+    pub keyword: Token,
+    pub condition: Expr,
+    pub then_branch: Expr,
+    pub else_branch: Expr,
+}
+
+/// An anonymous function. The arrow form `(a, b) -> a + b` is parsed into a
+/// body that returns the expression after the arrow.
+#[derive(Debug, Clone)]
+pub struct LambdaExpr {
+    pub arrow: Token,
+    pub params: Vec<String>,
+    pub body: Vec<Stmt>,
+    /// From the opening parenthesis of the parameters to the end of the body.
+    pub span: Span,
+}
+
+/// A block in expression position, `{ var t = f(); t * 2 }`. Its value is
+/// the trailing expression written without a semicolon, or nil.
+#[derive(Debug, Clone)]
+pub struct BlockExpr {
+    pub brace: Token,
+    pub statements: Vec<Stmt>,
+    pub value: Option<Expr>,
+    /// From the opening brace to the closing one.
+    pub span: Span,
+}
+
+impl Expr {
+    /// Whether this is an optional property access, `object?.name`.
+    pub fn is_optional_get(&self) -> bool {
+        matches!(self, Expr::Get(get) if get.optional)
+    }
+
+    /// The source range the expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Constant(_, span) | Expr::Grouping(_, span) => *span,
+            Expr::Variable(name, _) | Expr::This(name) => name.span,
+            Expr::Super(keyword, method) => keyword.span.to(method.span),
+            Expr::Assign(name, value, _) => name.span.to(value.span()),
+            Expr::Binary(binary) => binary.left.span().to(binary.right.span()),
+            Expr::Logical(logical) => logical.left.span().to(logical.right.span()),
+            Expr::Unary(unary) => unary.operator.span.to(unary.right.span()),
+            Expr::Call(call) => call.callee.span().to(call.paren.span),
+            Expr::Get(get) => get.object.span().to(get.name.span),
+            Expr::Set(set) => set.object.span().to(set.value.span()),
+            Expr::Index(index) => index.object.span().to(index.bracket.span),
+            Expr::IndexSet(set) => set.object.span().to(set.value.span()),
+            Expr::If(if_expr) => if_expr.keyword.span.to(if_expr.else_branch.span()),
+            Expr::Lambda(lambda) => lambda.span,
+            Expr::Block(block) => block.span,
+            Expr::Map(map) => map.span,
+        }
+    }
+
+    /// The line of the token that says what the expression does, such as
+    /// its operator or the parenthesis closing a call, for pointing runtime
+    /// errors at. Literals carry no token, so they have no line.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Expr::Constant(..) => None,
+            Expr::Grouping(inner, _) => inner.line(),
+            Expr::Variable(name, _) | Expr::This(name) | Expr::Super(name, _) | Expr::Assign(name, ..) => Some(name.line),
+            Expr::Unary(unary) => Some(unary.operator.line),
+            Expr::Binary(binary) => Some(binary.operator.line),
+            Expr::Logical(logical) => Some(logical.operator.line),
+            Expr::Call(call) => Some(call.paren.line),
+            Expr::Get(get) => Some(get.name.line),
+            Expr::Set(set) => Some(set.name.line),
+            Expr::Index(index) => Some(index.bracket.line),
+            Expr::IndexSet(set) => Some(set.bracket.line),
+            Expr::Map(map) => Some(map.brace.line),
+            Expr::If(if_expr) => Some(if_expr.keyword.line),
+            Expr::Lambda(lambda) => Some(lambda.arrow.line),
+            Expr::Block(block) => Some(block.brace.line),
+        }
+    }
 }
 
 
@@ -81,17 +244,16 @@ impl Expr {
                 indentation,
                 expr.right.pretty_print_with_indent(indent + 1)
             ),
-            Expr::Grouping(expr) => format!(
+            Expr::Grouping(expr, _) => format!(
                 "{}Grouping\n{}└── {}",
                 indentation,
                 indentation,
                 expr.pretty_print_with_indent(indent + 1)
             ),
-            Expr::Literal(expr) => match expr {
+            Expr::Constant(expr, _) => match expr {
                 LiteralExpr::Number(n) => format!("{}Number ({})", indentation, n),
                 LiteralExpr::String(s) => format!("{}String ({})", indentation, s),
                 LiteralExpr::Boolean(b) => format!("{}Boolean ({})", indentation, b),
-                LiteralExpr::Callable(func) => format!("{}Callable ({})", indentation, func.name()),
                 LiteralExpr::Nil => format!("{}Nil", indentation),
             },
             Expr::Unary(expr) => format!(
@@ -101,8 +263,10 @@ impl Expr {
                 indentation,
                 expr.right.pretty_print_with_indent(indent + 1)
             ),
-            Expr::Variable(token) => format!("{}Variable ({})", indentation, token.lexeme),
-            Expr::Assign(token, expr) => format!(
+            Expr::Variable(token, _) => format!("{}Variable ({})", indentation, token.lexeme),
+            Expr::This(_) => format!("{}This", indentation),
+            Expr::Super(_, method) => format!("{}Super ({})", indentation, method.lexeme),
+            Expr::Assign(token, expr, _) => format!(
                 "{}Assign ({})\n{}└── {}",
                 indentation,
                 token.lexeme,
@@ -124,15 +288,108 @@ impl Expr {
                     pretty_arguments.push_str(&argument.pretty_print_with_indent(indent + 1));
                 }
                 format!(
-                    "{}CallExpression\n{}├── {}\n{}└── Arguments\n{}{}",
+                    "{}CallExpression\n{}├── {}\n{}└── Arguments{}\n{}{}",
                     indentation,
                     indentation,
                     expr.callee.pretty_print_with_indent(indent + 1),
                     indentation,
+                    if expr.spread { " (last spread)" } else { "" },
                     pretty_arguments,
                     indentation
                 )
             }
+            Expr::Get(expr) => format!(
+                "{}{} ({})\n{}└── {}",
+                indentation,
+                if expr.optional { "OptionalGet" } else { "Get" },
+                expr.name.lexeme,
+                indentation,
+                expr.object.pretty_print_with_indent(indent + 1)
+            ),
+            Expr::Set(expr) => format!(
+                "{}Set ({})\n{}├── {}\n{}└── {}",
+                indentation,
+                expr.name.lexeme,
+                indentation,
+                expr.object.pretty_print_with_indent(indent + 1),
+                indentation,
+                expr.value.pretty_print_with_indent(indent + 1)
+            ),
+            Expr::Index(expr) => format!(
+                "{}Index\n{}├── {}\n{}└── {}",
+                indentation,
+                indentation,
+                expr.object.pretty_print_with_indent(indent + 1),
+                indentation,
+                expr.index.pretty_print_with_indent(indent + 1)
+            ),
+            Expr::IndexSet(expr) => format!(
+                "{}IndexSet\n{}├── {}\n{}├── {}\n{}└── {}",
+                indentation,
+                indentation,
+                expr.object.pretty_print_with_indent(indent + 1),
+                indentation,
+                expr.index.pretty_print_with_indent(indent + 1),
+                indentation,
+                expr.value.pretty_print_with_indent(indent + 1)
+            ),
+            Expr::Map(expr) => {
+                let mut result = format!("{}Map", indentation);
+                for (key, value) in &expr.entries {
+                    result.push_str(&format!(
+                        "\n{}├── Entry ({})\n{}  └── {}",
+                        indentation,
+                        key.lexeme,
+                        indentation,
+                        value.pretty_print_with_indent(indent + 2)
+                    ));
+                }
+                result
+            }
+            Expr::If(expr) => format!(
+                "{}IfExpression\n{}├── {}\n{}├── {}\n{}└── {}",
+                indentation,
+                indentation,
+                expr.condition.pretty_print_with_indent(indent + 1),
+                indentation,
+                expr.then_branch.pretty_print_with_indent(indent + 1),
+                indentation,
+                expr.else_branch.pretty_print_with_indent(indent + 1)
+            ),
+            Expr::Lambda(expr) => {
+                let mut result = format!(
+                    "{}Lambda\n{}├── Parameters: {}",
+                    indentation,
+                    indentation,
+                    expr.params.join(", ")
+                );
+                for statement in &expr.body {
+                    result.push_str(&format!(
+                        "\n{}└── {}",
+                        indentation,
+                        statement.pretty_print_with_indent(indent + 1)
+                    ));
+                }
+                result
+            }
+            Expr::Block(expr) => {
+                let mut result = format!("{}BlockExpression", indentation);
+                for statement in &expr.statements {
+                    result.push_str(&format!(
+                        "\n{}├── {}",
+                        indentation,
+                        statement.pretty_print_with_indent(indent + 1)
+                    ));
+                }
+                if let Some(value) = &expr.value {
+                    result.push_str(&format!(
+                        "\n{}└── {}",
+                        indentation,
+                        value.pretty_print_with_indent(indent + 1)
+                    ));
+                }
+                result
+            }
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,98 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::environ::Environment;
+use crate::error::ErrorFormat;
+use crate::{interpret_source, new_globals, RunOptions};
+
+/// Extension of the file holding a fixture's expected output.
+pub const EXPECTED_EXTENSION: &str = "expected";
+
+/// The outcome of running a directory of golden fixtures.
+#[derive(Debug, Default)]
+pub struct GoldenReport {
+    pub passed: usize,
+    pub failures: Vec<GoldenMismatch>,
+}
+
+/// A fixture whose output differs from its `.expected` file.
+#[derive(Debug)]
+pub struct GoldenMismatch {
+    pub fixture: PathBuf,
+    /// `None` when the fixture has no `.expected` file yet.
+    pub expected: Option<String>,
+    pub actual: String,
+}
+
+impl GoldenMismatch {
+    /// Path of the `.expected` file next to the fixture.
+    pub fn expected_path(&self) -> PathBuf {
+        self.fixture.with_extension(EXPECTED_EXTENSION)
+    }
+}
+
+impl fmt::Display for GoldenMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.expected {
+            Some(expected) => write!(
+                f,
+                "{}: output differs\n--- expected\n{}--- actual\n{}",
+                self.fixture.display(),
+                expected,
+                self.actual
+            ),
+            None => write!(f, "{}: missing {}", self.fixture.display(), self.expected_path().display()),
+        }
+    }
+}
+
+/// Runs every `.lox` file in `dir` and compares what it prints, or the
+/// error it stops with, against the sibling `.expected` file.
+pub fn run_golden(dir: &Path) -> io::Result<GoldenReport> {
+    run_golden_with(dir, run_fixture)
+}
+
+/// Like [`run_golden`], running each fixture's source with `run`, which
+/// returns what it printed or the error it stopped with.
+pub fn run_golden_with(dir: &Path, run: impl Fn(&str) -> String) -> io::Result<GoldenReport> {
+    let mut report = GoldenReport::default();
+    for fixture in lox_files(dir)? {
+        let source = fs::read_to_string(&fixture)?;
+        let actual = run(&source);
+        let expected = fs::read_to_string(fixture.with_extension(EXPECTED_EXTENSION)).ok();
+        if expected.as_deref() == Some(actual.as_str()) {
+            report.passed += 1;
+        } else {
+            report.failures.push(GoldenMismatch { fixture, expected, actual });
+        }
+    }
+    Ok(report)
+}
+
+/// The `.lox` files directly inside `dir`, sorted by name.
+pub(crate) fn lox_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    files.retain(|path| path.extension().is_some_and(|extension| extension == "lox"));
+    files.sort();
+    Ok(files)
+}
+
+/// What a fixture prints, or the error it stops with, as recorded in `.expected` files.
+pub(crate) fn run_fixture(source: &str) -> String {
+    let options = RunOptions::default();
+    run_fixture_in(source, new_globals(options.capabilities), &options)
+}
+
+/// Like [`run_fixture`], in `globals` with `options`.
+pub fn run_fixture_in(source: &str, globals: Rc<RefCell<Environment>>, options: &RunOptions) -> String {
+    match interpret_source(source, globals, options) {
+        Ok(output) => output,
+        Err(e) => format!("{}\n", e.render(ErrorFormat::Human)),
+    }
+}
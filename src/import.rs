@@ -0,0 +1,74 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::callable::LoxCallable;
+use crate::environ::Environment;
+use crate::error::{ErrorFormat, EvalError, RunError};
+use crate::interpreter::{execute_all, Context};
+use crate::resolver::Resolver;
+use crate::value::Value;
+use crate::parse_program;
+
+/// `import(file)` runs another script of the project in the globals, so
+/// the functions, classes and variables it defines become visible. The
+/// file is looked up in each search path in turn, and a file already
+/// imported is not run again.
+pub struct Import {
+    paths: Vec<PathBuf>,
+    imported: RefCell<HashSet<PathBuf>>,
+}
+
+impl Import {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        Import { paths, imported: RefCell::new(HashSet::new()) }
+    }
+
+    fn find(&self, file: &str) -> Option<PathBuf> {
+        self.paths.iter().map(|dir| dir.join(file)).find(|path| path.is_file())
+    }
+}
+
+impl LoxCallable for Import {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn name(&self) -> &str {
+        "import"
+    }
+
+    fn call(
+        &self,
+        arguments: Vec<Value>,
+        environment: Rc<RefCell<Environment>>,
+        _resolver: &Resolver,
+        context: &mut Context
+    ) -> Result<Value, EvalError> {
+        let file = match &arguments[0] {
+            Value::String(file) => file,
+            other => return Err(EvalError::TypeError(format!("import expects a file name but got {}", other.type_name()))),
+        };
+        let path = self.find(file).ok_or_else(|| EvalError::NativeError(format!("Cannot find {} in the import paths", file)))?;
+        let path = path.canonicalize().unwrap_or(path);
+        if !self.imported.borrow_mut().insert(path.clone()) {
+            return Ok(Value::Nil);
+        }
+
+        let source = fs::read_to_string(&path).map_err(|_| EvalError::NativeError(format!("Failed to read file {}", path.display())))?;
+        let failed = |error: RunError| EvalError::NativeError(format!("Failed to import {}: {}", file, error.render(ErrorFormat::Human)));
+        let statements = parse_program(&source).map_err(|errors| failed(RunError::Parse(errors)))?;
+        // The imported functions keep this resolver's view of their
+        // bodies only while the import runs; called later, they look their
+        // variables up by name.
+        let mut resolver = Resolver::new();
+        let errors = resolver.resolve(&statements);
+        if !errors.is_empty() {
+            return Err(failed(RunError::Resolve(errors)));
+        }
+        execute_all(&statements, Environment::globals(&environment), &resolver, context)?;
+        Ok(Value::Nil)
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Where `input()` and `readLine()` get their lines. The CLI reads standard
+/// input; the wasm build has none, so it is given its lines up front or
+/// none at all.
+pub trait InputProvider {
+    /// The next line, without its line ending, or `None` at the end of the
+    /// input. An error means no input can be had here at all.
+    fn read_line(&mut self) -> Result<Option<String>, String>;
+}
+
+impl fmt::Debug for dyn InputProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InputProvider")
+    }
+}
+
+/// Reads lines from standard input, waiting for each.
+#[derive(Debug, Default)]
+pub struct StdinInput;
+
+impl InputProvider for StdinInput {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(trim_line_ending(line))),
+            Err(e) => Err(format!("Failed to read stdin: {}", e)),
+        }
+    }
+}
+
+/// Hands out lines given in advance, then reports the end of the input.
+#[derive(Debug, Default)]
+pub struct LinesInput {
+    lines: VecDeque<String>,
+}
+
+impl LinesInput {
+    pub fn new(text: &str) -> Self {
+        LinesInput { lines: text.lines().map(str::to_string).collect() }
+    }
+}
+
+impl InputProvider for LinesInput {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        Ok(self.lines.pop_front())
+    }
+}
+
+/// For builds that cannot read input: asking for a line is an error rather
+/// than a wait that never ends.
+#[derive(Debug, Default)]
+pub struct NoInput;
+
+impl InputProvider for NoInput {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        Err("No input is available here".to_string())
+    }
+}
+
+/// The provider a run uses unless given another.
+pub fn default_input() -> Box<dyn InputProvider> {
+    if cfg!(target_arch = "wasm32") {
+        Box::new(NoInput)
+    } else {
+        Box::new(StdinInput)
+    }
+}
+
+fn trim_line_ending(mut line: String) -> String {
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    line
+}
@@ -1,251 +1,1004 @@
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::{error::Error, rc::Rc};
-use std::fmt::Write;
+use std::fmt::{self, Write};
+use std::time::Duration;
 use crate::callable::LoxFunction;
+use crate::class::{LoxClass, LoxInstance};
 use crate::resolver::Resolver;
-use crate::{error::EvalError, expr::{Expr, LiteralExpr}, stmt::Stmt, token::TokenType};
-use crate::error::ControlFlow;
+use crate::{error::EvalError, expr::{Expr, ExprId}, stmt::{ForInStmt, ForStmt, Stmt}, value::Value, token::{Literal, Token, TokenType}};
 use crate::environ::Environment;
+use crate::event_loop::TimerRequest;
+use crate::methods::get_method;
 use crate::natives::define_native_functions;
+use crate::random::Random;
+use crate::input::{default_input, InputProvider};
 
 impl Error for EvalError {}
 
+/// Settings that change the meaning of a program, chosen per run.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LanguageOptions {
+    /// Dividing one whole number by another truncates, so `5 / 2` is `2`.
+    pub integer_division: bool,
+    /// A function whose body ends in an expression statement returns its value.
+    pub implicit_return: bool,
+    /// `if` and `while` conditions must be booleans instead of being tested
+    /// for truthiness.
+    pub strict_conditions: bool,
+    /// Numbers are exact fractions of any size instead of `f64`s.
+    #[cfg(feature = "bignum")]
+    pub bignum: bool,
+}
+
+/// State carried through a whole run: the printed output so far, the
+/// language options in effect and the calls currently in progress.
+#[derive(Debug)]
+pub struct Context {
+    pub output: String,
+    /// Whether [`Context::flush_output`] writes the output to stdout, for
+    /// runs whose output ends up there anyway.
+    pub stream_output: bool,
+    /// How much of `output` has already been written to stdout.
+    pub flushed: usize,
+    pub options: LanguageOptions,
+    /// Active calls, outermost first.
+    pub frames: Vec<CallFrame>,
+    /// Statements left to run before the program is stopped, if limited.
+    pub fuel: Option<u64>,
+    /// How many Lox functions may be active at once before the program is
+    /// stopped with a stack overflow error.
+    pub max_call_depth: usize,
+    /// How many times one run of a loop may go round before the program is
+    /// stopped, if limited.
+    pub max_loop_iterations: Option<u64>,
+    /// Counters for `--stats`, updated as the program runs.
+    pub stats: ExecutionStats,
+    /// Timers set or cleared by the program, for the [`EventLoop`](crate::event_loop::EventLoop) running it.
+    pub timers: Vec<TimerRequest>,
+    /// Where `random()` and `randomInt()` get their numbers.
+    pub random: Random,
+    /// Where `input()` and `readLine()` get their lines.
+    pub input: Box<dyn InputProvider>,
+    /// Watches the run step by step, e.g. for `--explain`.
+    pub hook: Option<Box<dyn EvalHook>>,
+}
+
+/// How a statement finished: by running to its end, or by jumping out to
+/// the loop or function around it.
+#[derive(Debug, Clone)]
+pub enum ControlFlow {
+    Normal,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+/// Why evaluating an expression stopped without a value: it failed, or a
+/// statement in a block expression inside it jumped out.
+#[derive(Debug)]
+pub enum Unwind {
+    Error(EvalError),
+    Jump(ControlFlow),
+}
+
+impl From<EvalError> for Unwind {
+    fn from(error: EvalError) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+impl Unwind {
+    /// The value a function returns when evaluating its trailing expression
+    /// unwinds with `self`.
+    pub(crate) fn into_return_value(self) -> Result<Value, EvalError> {
+        match self {
+            Unwind::Jump(ControlFlow::Return(value)) => Ok(value),
+            // The parser only accepts `break` and `continue` inside a loop.
+            Unwind::Jump(_) => Ok(Value::Nil),
+            Unwind::Error(error) => Err(error),
+        }
+    }
+}
+
+/// Lets tools follow a run as it evaluates each expression and touches
+/// variables. Every method does nothing by default. `output` is what the
+/// program has printed so far; a hook may add to it, so that its notes
+/// appear between the program's own lines.
+pub trait EvalHook {
+    fn enter_expr(&mut self, _expr: &Expr, _output: &mut String) {}
+    fn exit_expr(&mut self, _expr: &Expr, _result: &Result<Value, Unwind>, _output: &mut String) {}
+    fn binding(&mut self, _event: BindingEvent, _name: &str, _value: &Value, _output: &mut String) {}
+}
+
+impl fmt::Debug for dyn EvalHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EvalHook")
+    }
+}
+
+/// How a variable was touched, as reported to [`EvalHook::binding`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BindingEvent {
+    Read,
+    Assign,
+    /// Declared, or bound to an argument.
+    Define,
+}
+
+/// Tells the run's hook, if any, that a variable was touched.
+pub(crate) fn notify_binding(context: &mut Context, event: BindingEvent, name: &str, value: &Value) {
+    if let Some(hook) = context.hook.as_mut() {
+        hook.binding(event, name, value, &mut context.output);
+    }
+}
+
+/// What a run did, as reported by `--stats`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecutionStats {
+    pub statements: u64,
+    /// Calls of functions, lambdas, natives and methods alike.
+    pub calls: u64,
+    /// Environments created, one for each block entered and function called.
+    /// They hold the program's variables and are its main heap allocations.
+    pub allocations: u64,
+    /// Deepest nesting of environments reached, the globals being depth 0.
+    pub peak_environment_depth: usize,
+    /// Time spent running, filled in by the caller that timed the run.
+    pub wall_time: Duration,
+}
+
+impl ExecutionStats {
+    pub(crate) fn record_environment(&mut self, environment: &Environment) {
+        self.allocations += 1;
+        self.peak_environment_depth = self.peak_environment_depth.max(environment.depth());
+    }
+}
+
+impl fmt::Display for ExecutionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Statements executed:        {}", self.statements)?;
+        writeln!(f, "Function calls:             {}", self.calls)?;
+        writeln!(f, "Allocations:                {}", self.allocations)?;
+        writeln!(f, "Peak environment depth:     {}", self.peak_environment_depth)?;
+        write!(f, "Wall time:                  {:.3} ms", self.wall_time.as_secs_f64() * 1000.0)
+    }
+}
+
+/// Default for [`Context::max_call_depth`]. Native builds grow the stack
+/// as deep calls need it, see [`evaluate`].
+#[cfg(not(target_arch = "wasm32"))]
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+/// Default for [`Context::max_call_depth`]. A wasm module cannot grow its
+/// stack, so this stays well inside the 1 MiB it is given.
+#[cfg(target_arch = "wasm32")]
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 100;
+
+/// Stack left below which [`evaluate`] moves to a fresh stack segment:
+/// more than the deepest run of frames between two evaluations, which is
+/// one Lox call in a debug build.
+#[cfg(not(target_arch = "wasm32"))]
+const STACK_RED_ZONE: usize = 256 * 1024;
+
+/// Size of each stack segment added for deep recursion.
+#[cfg(not(target_arch = "wasm32"))]
+const STACK_SEGMENT_SIZE: usize = 4 * 1024 * 1024;
+
+impl Default for Context {
+    fn default() -> Self {
+        Context::new(LanguageOptions::default())
+    }
+}
+
+impl Context {
+    pub fn new(options: LanguageOptions) -> Self {
+        Context {
+            output: String::new(),
+            stream_output: false,
+            flushed: 0,
+            options,
+            frames: Vec::new(),
+            fuel: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_loop_iterations: None,
+            stats: ExecutionStats::default(),
+            timers: Vec::new(),
+            random: Random::from_entropy(),
+            input: default_input(),
+            hook: None,
+        }
+    }
+}
+
+impl Context {
+    /// Writes the output printed since the last flush to stdout when
+    /// [`Context::stream_output`] is set, so it shows up before the program
+    /// waits for input.
+    pub fn flush_output(&mut self) -> Result<(), EvalError> {
+        use std::io::Write as _;
+        if !self.stream_output {
+            return Ok(());
+        }
+        let mut stdout = std::io::stdout().lock();
+        stdout
+            .write_all(&self.output.as_bytes()[self.flushed..])
+            .and_then(|_| stdout.flush())
+            .map_err(|e| EvalError::NativeError(format!("Failed to write output: {}", e)))?;
+        self.flushed = self.output.len();
+        Ok(())
+    }
+}
+
+/// A call in progress: the function called and the line it was called from.
+#[derive(Debug, Clone)]
+pub struct CallFrame {
+    pub function: String,
+    pub line: usize,
+}
+
 pub fn interpret(statements: &[Stmt], resolver: &Resolver) -> Result<String, EvalError> {
     let globals = Rc::new(RefCell::new(Environment::new()));
     define_native_functions(&mut globals.borrow_mut());
-    let mut output = String::new();
-    interpret_with_env(statements, Some(globals), resolver, &mut output)
+    interpret_with_env(statements, Some(globals), resolver, &mut Context::default())
 }
 
 pub fn interpret_with_env(
     statements: &[Stmt], 
     environ: Option<Rc<RefCell<Environment>>>, 
     resolver: &Resolver,
-    output: &mut String
+    context: &mut Context
 ) -> Result<String, EvalError> {
     let environment = environ.unwrap_or_else(|| Rc::new(RefCell::new(Environment::new())));
 
+    // The parser rejects jumps outside of loops and functions, so every
+    // top-level statement runs to its end.
+    execute_all(statements, environment, resolver, context)?;
+
+    Ok(context.output.clone())
+}
+
+/// Runs `statements` in `environment` until one of them jumps out.
+pub(crate) fn execute_all(
+    statements: &[Stmt],
+    environment: Rc<RefCell<Environment>>,
+    resolver: &Resolver,
+    context: &mut Context
+) -> Result<ControlFlow, EvalError> {
     for statement in statements {
-        execute(statement, environment.clone(), resolver, output)?;
+        match execute(statement, environment.clone(), resolver, context)? {
+            ControlFlow::Normal => (),
+            flow => return Ok(flow),
+        }
     }
+    Ok(ControlFlow::Normal)
+}
 
-    Ok(output.clone())
+fn execute(stmt: &Stmt, environment: Rc<RefCell<Environment>>, resolver: &Resolver, context: &mut Context) -> Result<ControlFlow, EvalError> {
+    match execute_stmt(stmt, environment, resolver, context) {
+        Ok(flow) | Err(Unwind::Jump(flow)) => Ok(flow),
+        Err(Unwind::Error(error)) => Err(error),
+    }
 }
 
-fn execute(stmt: &Stmt, environment: Rc<RefCell<Environment>>, resolver: &Resolver, output: &mut String) -> Result<(), EvalError> {
+/// Runs one statement. A block expression in it that jumps out makes the
+/// statement jump the same way, which [`execute`] turns back into how the
+/// statement finished.
+fn execute_stmt(stmt: &Stmt, environment: Rc<RefCell<Environment>>, resolver: &Resolver, context: &mut Context) -> Result<ControlFlow, Unwind> {
+    if let Some(fuel) = context.fuel.as_mut() {
+        if *fuel == 0 {
+            return Err(EvalError::OutOfFuel.into());
+        }
+        *fuel -= 1;
+    }
+    context.stats.statements += 1;
     match stmt {
-        Stmt::While(condition, body) => {
+        Stmt::While(keyword, condition, body, _) => {
+            let mut iterations = 0;
             while {
-                let condition_value = evaluate(condition, environment.clone(), resolver, output)?;
-                if let Expr::Literal(LiteralExpr::Boolean(b)) = condition_value {
-                    b
-                } else {
-                    return Err(EvalError::TypeError("While condition must be a boolean".to_string()));
-                }
+                let condition_value = evaluate(condition, environment.clone(), resolver, context)?;
+                test_condition("While", &condition_value, context).map_err(|e| e.at_line(Some(keyword.line)))?
             } {
-                match execute(&*body, environment.clone(), resolver, output) {
-                    Ok(()) => (),
-                    Err(EvalError::ControlFlow(ControlFlow::Break)) => break,
-                    Err(EvalError::ControlFlow(ControlFlow::Continue)) => continue,
-                    Err(e) => return Err(e),
+                if let Some(limit) = context.max_loop_iterations {
+                    if iterations == limit {
+                        return Err(EvalError::LoopLimitExceeded(keyword.line, limit).into());
+                    }
+                    iterations += 1;
+                }
+                match execute(body, environment.clone(), resolver, context)? {
+                    ControlFlow::Normal | ControlFlow::Continue => (),
+                    ControlFlow::Break => break,
+                    flow @ ControlFlow::Return(_) => return Ok(flow),
+                }
+            }
+        }
+        Stmt::For(for_stmt) => {
+            let ForStmt { keyword, initializer, condition, increment, body, .. } = for_stmt.as_ref();
+            // The initializer's variable lives in a scope of its own, shared by every iteration.
+            let loop_env = Environment::new_enclosed(environment.clone());
+            context.stats.record_environment(&loop_env);
+            let loop_env = Rc::new(RefCell::new(loop_env));
+            if let Some(initializer) = initializer {
+                execute(initializer, loop_env.clone(), resolver, context)?;
+            }
+            let mut iterations = 0;
+            loop {
+                if let Some(condition) = condition {
+                    let condition_value = evaluate(condition, loop_env.clone(), resolver, context)?;
+                    if !test_condition("For", &condition_value, context).map_err(|e| e.at_line(Some(keyword.line)))? {
+                        break;
+                    }
+                }
+                if let Some(limit) = context.max_loop_iterations {
+                    if iterations == limit {
+                        return Err(EvalError::LoopLimitExceeded(keyword.line, limit).into());
+                    }
+                    iterations += 1;
+                }
+                match execute(body, loop_env.clone(), resolver, context)? {
+                    // `continue` still runs the increment.
+                    ControlFlow::Normal | ControlFlow::Continue => (),
+                    ControlFlow::Break => break,
+                    flow @ ControlFlow::Return(_) => return Ok(flow),
+                }
+                if let Some(increment) = increment {
+                    evaluate(increment, loop_env.clone(), resolver, context)?;
                 }
             }
         }
-        Stmt::Block(statements) => {
-            let new_env = Rc::new(RefCell::new(Environment::new_enclosed(environment.clone())));
-            for statement in statements {
-                match execute(statement, new_env.clone(), resolver, output) {
-                    Ok(()) => (),
-                    Err(EvalError::ControlFlow(ControlFlow::Break)) => return Err(EvalError::ControlFlow(ControlFlow::Break)),
-                    Err(EvalError::ControlFlow(ControlFlow::Continue)) => return Err(EvalError::ControlFlow(ControlFlow::Continue)),
-                    Err(e) => return Err(e),
+        Stmt::ForIn(for_in) => {
+            let ForInStmt { keyword, name, iterable, body, .. } = for_in.as_ref();
+            let iterable = evaluate(iterable, environment.clone(), resolver, context)?;
+            let items = iteration_items(&iterable).map_err(|e| e.at_line(Some(keyword.line)))?;
+            for (iterations, item) in items.into_iter().enumerate() {
+                if let Some(limit) = context.max_loop_iterations {
+                    if iterations as u64 == limit {
+                        return Err(EvalError::LoopLimitExceeded(keyword.line, limit).into());
+                    }
+                }
+                // Each iteration binds the loop variable afresh, so closures made in the body keep their own item.
+                let mut loop_env = Environment::new_enclosed(environment.clone());
+                loop_env.define(name.lexeme.clone(), item);
+                context.stats.record_environment(&loop_env);
+                match execute(body, Rc::new(RefCell::new(loop_env)), resolver, context)? {
+                    ControlFlow::Normal | ControlFlow::Continue => (),
+                    ControlFlow::Break => break,
+                    flow @ ControlFlow::Return(_) => return Ok(flow),
                 }
             }
         }
-        Stmt::Break => return Err(EvalError::ControlFlow(ControlFlow::Break)),
-        Stmt::Continue => return Err(EvalError::ControlFlow(ControlFlow::Continue)),
-        Stmt::Expression(expr) => {
-            evaluate(expr, environment, resolver, output)?;
+        Stmt::Block(statements, _) => {
+            let new_env = Environment::new_enclosed(environment.clone());
+            context.stats.record_environment(&new_env);
+            let new_env = Rc::new(RefCell::new(new_env));
+            return Ok(execute_all(statements, new_env, resolver, context)?);
+        }
+        Stmt::ParallelAssign(names, values, _) => {
+            let mut evaluated = Vec::new();
+            for value in values {
+                evaluated.push(evaluate(value, environment.clone(), resolver, context)?);
+            }
+            for ((name, id), value) in names.iter().zip(evaluated) {
+                assign_variable(name, *id, value, &environment, resolver, context)?;
+            }
+        }
+        Stmt::Break(_) => return Ok(ControlFlow::Break),
+        Stmt::Continue(_) => return Ok(ControlFlow::Continue),
+        Stmt::Expression(expr, _) => {
+            evaluate(expr, environment, resolver, context)?;
         }
-        Stmt::If(condition, then_branch, else_branch) => {
-            let condition_value = evaluate(condition, environment.clone(), resolver, output)?;
+        Stmt::If(condition, then_branch, else_branch, _) => {
+            let condition_value = evaluate(condition, environment.clone(), resolver, context)?;
         
-            if let Expr::Literal(LiteralExpr::Boolean(b)) = condition_value {
-                if b {
-                    execute(&*then_branch, environment.clone(), resolver, output)?;
-                } else if let Some(else_branch) = else_branch {
-                    execute(&*else_branch, environment.clone(), resolver, output)?;
-                }
-            } else {
-                return Err(EvalError::TypeError("If condition must be a boolean".to_string()));
+            if test_condition("If", &condition_value, context).map_err(|e| e.at_line(condition.line()))? {
+                return Ok(execute(then_branch, environment.clone(), resolver, context)?);
+            } else if let Some(else_branch) = else_branch {
+                return Ok(execute(else_branch, environment.clone(), resolver, context)?);
             }
         }
-        Stmt::Function(name, params, body) => {
-            let function = LoxFunction::new(name.clone(), params.clone(), body.clone(), environment.clone());
-            environment.borrow_mut().define(name.clone(), LiteralExpr::Callable(Rc::new(function)));
+        Stmt::Function(name, params, body, signature, _) => {
+            let function = LoxFunction::new(name.clone(), params.clone(), body.clone(), environment.clone()).with_rest(signature.rest);
+            let function = Value::Callable(Rc::new(function));
+            notify_binding(context, BindingEvent::Define, name, &function);
+            environment.borrow_mut().define(name.clone(), function);
         }
-        Stmt::Return(Some(expr)) => {
-            let value = evaluate(expr, environment.clone(), resolver, output)?;
-            return Err(EvalError::ControlFlow(ControlFlow::Return(value)));
+        Stmt::Class(name, superclass, methods, _) => {
+            let superclass = match superclass {
+                Some(superclass_name) => {
+                    let value = environment.borrow().get(superclass_name)?;
+                    match &value {
+                        Value::Callable(callable) => match callable.as_class() {
+                            Some(class) => Some((Rc::new(class.clone()), value)),
+                            None => return Err(EvalError::TypeError("Superclass must be a class".to_string()).into()),
+                        },
+                        _ => return Err(EvalError::TypeError("Superclass must be a class".to_string()).into()),
+                    }
+                }
+                None => None,
+            };
+            // Methods of a subclass close over an environment holding `super`.
+            let method_env = match &superclass {
+                Some((_, value)) => {
+                    let mut super_env = Environment::new_enclosed(environment.clone());
+                    super_env.define("super".to_string(), value.clone());
+                    Rc::new(RefCell::new(super_env))
+                }
+                None => environment.clone(),
+            };
+            let methods = methods
+                .iter()
+                .filter_map(|method| match method {
+                    Stmt::Function(name, params, body, signature, _) => {
+                        let mut function =
+                            LoxFunction::new(name.clone(), params.clone(), body.clone(), method_env.clone()).with_rest(signature.rest);
+                        function.is_initializer = name == "init";
+                        Some((name.clone(), Rc::new(function)))
+                    }
+                    _ => None,
+                })
+                .collect();
+            let superclass = superclass.map(|(class, _)| class);
+            let class = Value::Callable(Rc::new(LoxClass::new(name.clone(), superclass, methods)));
+            notify_binding(context, BindingEvent::Define, name, &class);
+            environment.borrow_mut().define(name.clone(), class);
+        }
+        Stmt::Return(Some(expr), _) => {
+            let value = evaluate(expr, environment.clone(), resolver, context)?;
+            return Ok(ControlFlow::Return(value));
         },
-        Stmt::Return(None) => {
-            return Err(EvalError::ControlFlow(ControlFlow::Return(Expr::Literal(LiteralExpr::Nil))));
+        Stmt::Return(None, _) => {
+            return Ok(ControlFlow::Return(Value::Nil));
         },    
-        Stmt::Print(expr) => {
-            let value = evaluate(expr, environment, resolver, output)?;
-            match value {
-                Expr::Literal(literal) => {
-                    match literal {
-                        LiteralExpr::Number(n) => writeln!(output, "{}", n).unwrap(),
-                        LiteralExpr::String(s) => writeln!(output, "{}", s).unwrap(),
-                        LiteralExpr::Boolean(b) => writeln!(output, "{}", b).unwrap(),
-                        LiteralExpr::Callable(callable) => writeln!(output, "{:?}", callable).unwrap(),
-                        LiteralExpr::Nil => writeln!(output, "nil").unwrap(),
-                    }
-                },
-                _ => return Err(EvalError::TypeError("Invalid expression type in print statement".to_string())),
-            }
+        Stmt::Print(expr, _) => {
+            let value = evaluate(expr, environment, resolver, context)?;
+            writeln!(context.output, "{}", stringify(&value)).unwrap();
         }
-        Stmt::Var(name, initializer) => {
+        Stmt::Var(name, _, initializer, _) => {
             let value = if let Some(expr) = initializer {
-                evaluate(expr, environment.clone(), resolver, output)?
+                evaluate(expr, environment.clone(), resolver, context)?
             } else {
-                Expr::Literal(LiteralExpr::Nil)
+                Value::Nil
             };
 
-            if let Expr::Literal(literal_value) = value {
-                environment.borrow_mut().define(name.clone(), literal_value);
-            }
+            notify_binding(context, BindingEvent::Define, name, &value);
+            environment.borrow_mut().define(name.clone(), value);
         }    
+        Stmt::Const(name, _, value, _) => {
+            let value = evaluate(value, environment.clone(), resolver, context)?;
+            notify_binding(context, BindingEvent::Define, name, &value);
+            environment.borrow_mut().define_constant(name.clone(), value);
+        }
     }
-    Ok(())
+    Ok(ControlFlow::Normal)
 }
 
 /// Main evaluation function for expressions
-pub fn evaluate(expr: &Expr, environment: Rc<RefCell<Environment>>, resolver: &Resolver, output: &mut String) -> Result<Expr, EvalError> {
+pub fn evaluate(expr: &Expr, environment: Rc<RefCell<Environment>>, resolver: &Resolver, context: &mut Context) -> Result<Value, Unwind> {
+    // Calls recurse through here, so whatever thread a program runs on,
+    // recursion stops at the call depth limit instead of overflowing.
+    with_stack_to_spare(|| evaluate_hooked(expr, environment, resolver, context))
+}
+
+/// Runs `f`, on a new stack segment if little of the current one is left.
+#[cfg(not(target_arch = "wasm32"))]
+fn with_stack_to_spare<T>(f: impl FnOnce() -> T) -> T {
+    stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, f)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn with_stack_to_spare<T>(f: impl FnOnce() -> T) -> T {
+    f()
+}
+
+/// Evaluates `expr`, letting [`Context::hook`] watch.
+fn evaluate_hooked(expr: &Expr, environment: Rc<RefCell<Environment>>, resolver: &Resolver, context: &mut Context) -> Result<Value, Unwind> {
+    let locate = |unwind| match unwind {
+        Unwind::Error(error) => Unwind::Error(error.at_line(expr.line())),
+        jump => jump,
+    };
+    if context.hook.is_none() {
+        return evaluate_expr(expr, environment, resolver, context).map_err(locate);
+    }
+    if let Some(hook) = context.hook.as_mut() {
+        hook.enter_expr(expr, &mut context.output);
+    }
+    let result = evaluate_expr(expr, environment, resolver, context).map_err(locate);
+    if let Some(hook) = context.hook.as_mut() {
+        hook.exit_expr(expr, &result, &mut context.output);
+    }
+    result
+}
+
+fn evaluate_expr(expr: &Expr, environment: Rc<RefCell<Environment>>, resolver: &Resolver, context: &mut Context) -> Result<Value, Unwind> {
     match expr {
-        Expr::Literal(literal) => Ok(Expr::Literal(literal.clone())),
+        #[cfg(feature = "bignum")]
+        Expr::Constant(crate::expr::LiteralExpr::Number(n), _) if context.options.bignum => match crate::bignum::exact(*n) {
+            Some(n) => Ok(Value::BigNumber(Rc::new(n))),
+            None => Ok(Value::Number(*n)),
+        },
+        Expr::Constant(literal, _) => Ok(Value::from(literal)),
         Expr::Unary(unary) => {
-            let right = evaluate(&unary.right, environment.clone(), resolver, output)?;
+            let right = evaluate(&unary.right, environment.clone(), resolver, context)?;
+            // `!` negates truthiness, the same test `if` and `while` apply.
+            if unary.operator.token_type == TokenType::Bang {
+                return Ok(Value::Boolean(!test_condition("Negated", &right, context)?));
+            }
             match right {
-                Expr::Literal(LiteralExpr::Number(n)) => match unary.operator.token_type {
-                    TokenType::Minus => Ok(Expr::Literal(LiteralExpr::Number(-n))),
-                    TokenType::Bang => Ok(Expr::Literal(LiteralExpr::Boolean(n == 0.0))),
-                    _ => Err(EvalError::SyntaxError("Unknown unary operator".to_string())),
+                Value::Number(n) => match unary.operator.token_type {
+                    TokenType::Minus => Ok(Value::Number(-n)),
+                    TokenType::Tilde => Ok(Value::Number(!whole_number(n)? as f64)),
+                    _ => Err(EvalError::SyntaxError("Unknown unary operator".to_string()).into()),
                 },
-                Expr::Literal(LiteralExpr::Boolean(b)) => match unary.operator.token_type {
-                    TokenType::Bang => Ok(Expr::Literal(LiteralExpr::Boolean(!b))),
-                    _ => Err(EvalError::SyntaxError("Unknown unary operator".to_string())),
+                #[cfg(feature = "bignum")]
+                Value::BigNumber(n) => match unary.operator.token_type {
+                    TokenType::Minus => Ok(Value::BigNumber(Rc::new(-(*n).clone()))),
+                    TokenType::Tilde => Ok(crate::bignum::not(&n)?),
+                    _ => Err(EvalError::SyntaxError("Unknown unary operator".to_string()).into()),
                 },
-                _ => Err(EvalError::TypeError("Cannot apply unary operator to non-numeric or non-boolean type".to_string())),
+                _ => Err(EvalError::TypeError("Cannot apply unary operator to non-numeric type".to_string()).into()),
             }
         },
         Expr::Binary(binary) => {
-            let left = evaluate(&binary.left, environment.clone(), resolver, output)?;
-            let right = evaluate(&binary.right, environment.clone(), resolver, output)?;
+            let left = evaluate(&binary.left, environment.clone(), resolver, context)?;
+            let right = evaluate(&binary.right, environment.clone(), resolver, context)?;
+            #[cfg(feature = "bignum")]
+            let (left, right) = match (left, right) {
+                (left, right) if context.options.bignum => {
+                    let integer_division = context.options.integer_division;
+                    if let Some(result) = crate::bignum::binary(&binary.operator.token_type, &left, &right, integer_division) {
+                        return Ok(result?);
+                    }
+                    // Joined with a string, an exact number reads as it prints.
+                    let printed = |value: Value| match value {
+                        Value::BigNumber(n) => Value::String(crate::bignum::format(&n)),
+                        other => other,
+                    };
+                    // Repeating a string takes an ordinary count.
+                    let approximate = |value: Value| match value {
+                        Value::BigNumber(n) => Value::Number(crate::bignum::to_f64(&n)),
+                        other => other,
+                    };
+                    match binary.operator.token_type {
+                        TokenType::Plus => (printed(left), printed(right)),
+                        TokenType::Star => (approximate(left), approximate(right)),
+                        _ => (left, right),
+                    }
+                }
+                operands => operands,
+            };
             match (left, right) {
-                (Expr::Literal(LiteralExpr::Number(l)), Expr::Literal(LiteralExpr::Number(r))) => match binary.operator.token_type {
-                    TokenType::Plus => Ok(Expr::Literal(LiteralExpr::Number(l + r))),
-                    TokenType::Minus => Ok(Expr::Literal(LiteralExpr::Number(l - r))),
-                    TokenType::Star => Ok(Expr::Literal(LiteralExpr::Number(l * r))),
+                // Values of any types can be compared; different types are never equal.
+                (l, r) if binary.operator.token_type == TokenType::EqualEqual => Ok(Value::Boolean(values_equal(&l, &r))),
+                (l, r) if binary.operator.token_type == TokenType::BangEqual => Ok(Value::Boolean(!values_equal(&l, &r))),
+                (Value::Number(l), Value::Number(r)) => match binary.operator.token_type {
+                    TokenType::Plus => Ok(Value::Number(l + r)),
+                    TokenType::Minus => Ok(Value::Number(l - r)),
+                    TokenType::Star => Ok(Value::Number(l * r)),
                     TokenType::Slash => if r == 0.0 {
-                        Err(EvalError::DivisionByZero)
+                        Err(EvalError::DivisionByZero.into())
+                    } else if context.options.integer_division && l.fract() == 0.0 && r.fract() == 0.0 {
+                        Ok(Value::Number((l / r).trunc()))
                     } else {
-                        Ok(Expr::Literal(LiteralExpr::Number(l / r)))
+                        Ok(Value::Number(l / r))
                     },
                     TokenType::Percent => if r == 0.0 {
-                        Err(EvalError::DivisionByZero)
+                        Err(EvalError::DivisionByZero.into())
                     } else {
-                        Ok(Expr::Literal(LiteralExpr::Number(l % r)))
+                        Ok(Value::Number(l % r))
                     },
-                    TokenType::EqualEqual => Ok(Expr::Literal(LiteralExpr::Boolean(l == r))),
-                    TokenType::BangEqual => Ok(Expr::Literal(LiteralExpr::Boolean(l != r))),
-                    TokenType::Greater => Ok(Expr::Literal(LiteralExpr::Boolean(l > r))),
-                    TokenType::GreaterEqual => Ok(Expr::Literal(LiteralExpr::Boolean(l >= r))),
-                    TokenType::Less => Ok(Expr::Literal(LiteralExpr::Boolean(l < r))),
-                    TokenType::LessEqual => Ok(Expr::Literal(LiteralExpr::Boolean(l <= r))),
-                    _ => Err(EvalError::SyntaxError("Unknown binary operator".to_string())),
+                    TokenType::Greater => Ok(Value::Boolean(l > r)),
+                    TokenType::GreaterEqual => Ok(Value::Boolean(l >= r)),
+                    TokenType::Less => Ok(Value::Boolean(l < r)),
+                    TokenType::LessEqual => Ok(Value::Boolean(l <= r)),
+                    TokenType::Ampersand | TokenType::Pipe | TokenType::Caret | TokenType::LessLess | TokenType::GreaterGreater => {
+                        Ok(bitwise(&binary.operator.token_type, l, r)?)
+                    }
+                    _ => Err(EvalError::SyntaxError("Unknown binary operator".to_string()).into()),
                 },
-                (Expr::Literal(LiteralExpr::String(l)), Expr::Literal(LiteralExpr::String(r))) => match binary.operator.token_type {
-                    TokenType::Plus => Ok(Expr::Literal(LiteralExpr::String(l + &r))),
-                    _ => Err(EvalError::TypeError("Unsupported operation for strings".to_string())),
+                (Value::String(l), Value::String(r)) => match binary.operator.token_type {
+                    TokenType::Plus => Ok(Value::String(l + &r)),
+                    // Strings compare character by character, by code point.
+                    TokenType::Greater => Ok(Value::Boolean(l > r)),
+                    TokenType::GreaterEqual => Ok(Value::Boolean(l >= r)),
+                    TokenType::Less => Ok(Value::Boolean(l < r)),
+                    TokenType::LessEqual => Ok(Value::Boolean(l <= r)),
+                    _ => Err(EvalError::TypeError("Unsupported operation for strings".to_string()).into()),
                 },
-                (Expr::Literal(LiteralExpr::Number(l)), Expr::Literal(LiteralExpr::String(r))) => match binary.operator.token_type {
-                    TokenType::Plus => Ok(Expr::Literal(LiteralExpr::String(format!("{}{}", l, r)))),
-                    _ => Err(EvalError::TypeError("Unsupported operation for mixed types".to_string())),
+                (Value::Number(l), Value::String(r)) => match binary.operator.token_type {
+                    TokenType::Plus => Ok(Value::String(format!("{}{}", l, r))),
+                    TokenType::Star => Ok(repeat(&r, l)?),
+                    _ => Err(EvalError::TypeError("Unsupported operation for mixed types".to_string()).into()),
                 },
-                (Expr::Literal(LiteralExpr::String(l)), Expr::Literal(LiteralExpr::Number(r))) => match binary.operator.token_type {
-                    TokenType::Plus => Ok(Expr::Literal(LiteralExpr::String(format!("{}{}", l, r)))),
-                    _ => Err(EvalError::TypeError("Unsupported operation for mixed types".to_string())),
+                (Value::String(l), Value::Number(r)) => match binary.operator.token_type {
+                    TokenType::Plus => Ok(Value::String(format!("{}{}", l, r))),
+                    TokenType::Star => Ok(repeat(&l, r)?),
+                    _ => Err(EvalError::TypeError("Unsupported operation for mixed types".to_string()).into()),
                 },
-                _ => Err(EvalError::TypeError("Operands must be compatible for the operation".to_string())),
+                (Value::Map(_), Value::Map(_)) => Err(EvalError::TypeError("Unsupported operation for maps".to_string()).into()),
+                _ => Err(EvalError::TypeError("Operands must be compatible for the operation".to_string()).into()),
             }
         },
-        Expr::Grouping(grouping) => evaluate(&**grouping, environment.clone(), resolver, output),
-        Expr::Variable(name) => {
-            if let Some(scope_depth) = resolver.resolve_local(&name.lexeme) {
-                // If we have a scope depth, fetch from the local environment
-                match environment.borrow().get_at_depth(&name, scope_depth) {
-                    Ok(literal) => Ok(Expr::Literal(literal)),
-                    Err(_) => Err(EvalError::UndefinedVariable(name.lexeme.clone())),
-                }
-            } else {
-                // Otherwise, fetch from the global environment
-                match environment.borrow().get(&name) {
-                    Ok(literal) => Ok(Expr::Literal(literal)),
-                    Err(_) => Err(EvalError::UndefinedVariable(name.lexeme.clone())),
-                }
-            }
+        Expr::Grouping(grouping, _) => evaluate(grouping, environment.clone(), resolver, context),
+        Expr::Variable(name, id) => {
+            let value = match resolver.resolve_local(*id) {
+                Some(distance) => Environment::get_at(&environment, distance, name)?,
+                None if resolver.is_global(*id) => Environment::globals(&environment).borrow().get(name)?,
+                None => environment.borrow().get(name)?,
+            };
+            notify_binding(context, BindingEvent::Read, &name.lexeme, &value);
+            Ok(value)
         }
-        Expr::Assign(name, expr) => {
-            let value = evaluate(&expr, environment.clone(), resolver, output)?;
-            if let Expr::Literal(ref literal) = value {
-                environment.borrow_mut().assign(name, literal.clone())?;
+        Expr::This(keyword) => Ok(environment.borrow().get(keyword)?),
+        Expr::Super(keyword, method) => {
+            let superclass = environment.borrow().get(keyword)?;
+            let Some(superclass) = (match &superclass {
+                Value::Callable(callable) => callable.as_class(),
+                _ => None,
+            }) else {
+                return Err(EvalError::TypeError("Superclass must be a class".to_string()).into());
+            };
+            let this = Token { token_type: TokenType::This, lexeme: "this".to_string(), ..keyword.clone() };
+            let instance = environment.borrow().get(&this)?;
+            match superclass.find_method(&method.lexeme) {
+                Some(found) => Ok(Value::Callable(Rc::new(found.bind(instance)))),
+                None => Err(EvalError::TypeError(format!("{} has no method '{}'", superclass.name, method.lexeme)).into()),
             }
+        }
+        Expr::Assign(name, expr, id) => {
+            let value = evaluate(expr, environment.clone(), resolver, context)?;
+            assign_variable(name, *id, value.clone(), &environment, resolver, context)?;
             Ok(value)
         },
         Expr::Logical(logical) => {
-            let left = evaluate(&logical.left, environment.clone(), resolver, output)?;
-            if logical.operator.token_type == TokenType::Or {
-                if is_truthy(&left) {
-                    return Ok(left);
-                }
-            } else {
-                if !is_truthy(&left) {
-                    return Ok(left);
-                }
+            let left = evaluate(&logical.left, environment.clone(), resolver, context)?;
+            let short_circuits = match logical.operator.token_type {
+                TokenType::Or => is_truthy(&left),
+                TokenType::QuestionQuestion => !matches!(left, Value::Nil),
+                _ => !is_truthy(&left),
+            };
+            if short_circuits {
+                return Ok(left);
             }
-            evaluate(&logical.right, environment.clone(), resolver, output)
+            evaluate(&logical.right, environment.clone(), resolver, context)
         },
         Expr::Call(call_expr) => {
-            let callee = evaluate(&call_expr.callee, environment.clone(), resolver, output)?;
+            let callee = evaluate(&call_expr.callee, environment.clone(), resolver, context)?;
+            // `object?.method()` skips the call along with the lookup.
+            if call_expr.callee.is_optional_get() && matches!(callee, Value::Nil) {
+                return Ok(callee);
+            }
             let mut arguments = Vec::new();
         
             for arg in &call_expr.arguments {
-                let value = match evaluate(arg, environment.clone(), resolver, output)? {
-                    Expr::Literal(literal) => literal,
-                    _ => return Err(EvalError::TypeError("Invalid argument type".to_string())),
-                };
-                arguments.push(value);
+                arguments.push(evaluate(arg, environment.clone(), resolver, context)?);
+            }
+            if call_expr.spread {
+                match arguments.pop() {
+                    Some(Value::List(list)) => arguments.extend(list.borrow().iter().cloned()),
+                    Some(other) => {
+                        return Err(EvalError::TypeError(format!("Can only spread a list, not {}", other.type_name())).into())
+                    }
+                    None => {}
+                }
             }
         
             match callee {
-                Expr::Literal(LiteralExpr::Callable(callable)) => {
-                    if arguments.len() != callable.arity() {
-                        return Err(EvalError::ArityError(callable.arity(), arguments.len()));
+                Value::Callable(callable) => {
+                    if callable.variadic() && arguments.len() < callable.arity() {
+                        return Err(EvalError::TooFewArguments(callable.arity(), arguments.len()).into());
+                    }
+                    if !callable.variadic() && arguments.len() != callable.arity() {
+                        return Err(EvalError::ArityError(callable.arity(), arguments.len()).into());
                     }
-                    callable.call(arguments, environment.clone(), resolver, output)
+                    context.stats.calls += 1;
+                    context.frames.push(CallFrame { function: callable.name().to_string(), line: call_expr.paren.line });
+                    let result = callable.call(arguments, environment.clone(), resolver, context);
+                    context.frames.pop();
+                    Ok(result?)
                 },
-                _ => Err(EvalError::TypeError("Can only call functions and classes".to_string())),
+                _ => Err(EvalError::TypeError("Can only call functions and classes".to_string()).into()),
+            }
+        }
+        Expr::Get(get_expr) => match evaluate(&get_expr.object, environment, resolver, context)? {
+            Value::Nil if get_expr.optional => Ok(Value::Nil),
+            // Reading a key a map does not have gives nil.
+            Value::Map(map) => {
+                Ok(map.borrow().get(&get_expr.name.lexeme).cloned().unwrap_or(Value::Nil))
+            }
+            Value::Instance(instance) => Ok(LoxInstance::get(&instance, &get_expr.name.lexeme)?),
+            object => Ok(get_method(&object, &get_expr.name.lexeme)?),
+        },
+        Expr::Set(set_expr) => {
+            let Value::Instance(instance) = evaluate(&set_expr.object, environment.clone(), resolver, context)? else {
+                return Err(EvalError::TypeError("Only instances have fields".to_string()).into());
+            };
+            let value = evaluate(&set_expr.value, environment, resolver, context)?;
+            instance.set(&set_expr.name.lexeme, value.clone());
+            Ok(value)
+        }
+        Expr::Index(index_expr) => {
+            let object = evaluate(&index_expr.object, environment.clone(), resolver, context)?;
+            let index = evaluate(&index_expr.index, environment, resolver, context)?;
+            match (object, index) {
+                // Like reading a property, a missing key gives nil.
+                (Value::Map(map), Value::String(key)) => Ok(map.borrow().get(&key).cloned().unwrap_or(Value::Nil)),
+                (Value::Map(_), index) => Err(EvalError::TypeError(format!("Map keys must be strings, not {}", index.type_name())).into()),
+                (Value::List(list), index) => {
+                    let list = list.borrow();
+                    Ok(list[list_position(&index, list.len())?].clone())
+                }
+                (object, _) => Err(EvalError::TypeError(format!("Can only index maps and lists, not {}", object.type_name())).into()),
+            }
+        }
+        Expr::IndexSet(set_expr) => {
+            let object = evaluate(&set_expr.object, environment.clone(), resolver, context)?;
+            let index = evaluate(&set_expr.index, environment.clone(), resolver, context)?;
+            let value = evaluate(&set_expr.value, environment, resolver, context)?;
+            match (object, index) {
+                (Value::Map(map), Value::String(key)) => {
+                    map.borrow_mut().insert(key, value.clone());
+                }
+                (Value::Map(_), index) => {
+                    return Err(EvalError::TypeError(format!("Map keys must be strings, not {}", index.type_name())).into())
+                }
+                // Lists do not grow by assignment; `push` adds to the end.
+                (Value::List(list), index) => {
+                    let mut list = list.borrow_mut();
+                    let position = list_position(&index, list.len())?;
+                    list[position] = value.clone();
+                }
+                (object, _) => {
+                    return Err(EvalError::TypeError(format!("Can only index maps and lists, not {}", object.type_name())).into())
+                }
+            }
+            Ok(value)
+        }
+        Expr::Map(map_expr) => {
+            let mut map = BTreeMap::new();
+            for (key, value) in &map_expr.entries {
+                let Some(Literal::String(key)) = &key.literal else {
+                    return Err(EvalError::TypeError("Map keys must be strings".to_string()).into());
+                };
+                map.insert(key.clone(), evaluate(value, environment.clone(), resolver, context)?);
+            }
+            Ok(Value::map(map))
+        }
+        Expr::If(if_expr) => {
+            let condition_value = evaluate(&if_expr.condition, environment.clone(), resolver, context)?;
+            if test_condition("If", &condition_value, context)? {
+                evaluate(&if_expr.then_branch, environment, resolver, context)
+            } else {
+                evaluate(&if_expr.else_branch, environment, resolver, context)
+            }
+        }
+        Expr::Block(block) => {
+            let block_env = Environment::new_enclosed(environment);
+            context.stats.record_environment(&block_env);
+            let block_env = Rc::new(RefCell::new(block_env));
+            match execute_all(&block.statements, block_env.clone(), resolver, context)? {
+                ControlFlow::Normal => (),
+                flow => return Err(Unwind::Jump(flow)),
+            }
+            match &block.value {
+                Some(value) => evaluate(value, block_env, resolver, context),
+                None => Ok(Value::Nil),
+            }
+        }
+        Expr::Lambda(lambda) => {
+            let function = LoxFunction::new("lambda".to_string(), lambda.params.clone(), lambda.body.clone(), environment);
+            Ok(Value::Callable(Rc::new(function)))
+        }
+    }
+}
+
+/// Assigns `value` to the variable the resolver found for the target `id`
+/// of an assignment.
+fn assign_variable(
+    name: &Token,
+    id: ExprId,
+    value: Value,
+    environment: &Rc<RefCell<Environment>>,
+    resolver: &Resolver,
+    context: &mut Context,
+) -> Result<(), EvalError> {
+    match resolver.resolve_local(id) {
+        Some(distance) => Environment::assign_at(environment, distance, name, value.clone())?,
+        None if resolver.is_global(id) => Environment::globals(environment).borrow_mut().assign(name, value.clone())?,
+        None => environment.borrow_mut().assign(name, value.clone())?,
+    }
+    notify_binding(context, BindingEvent::Assign, &name.lexeme, &value);
+    Ok(())
+}
+
+/// The longest string, in bytes, that `*` may build. Anything longer is
+/// almost certainly a mistake, and asking for it would abort the process
+/// when the allocation fails.
+pub const MAX_REPEAT_LENGTH: usize = 1 << 28;
+
+/// `s * count`: `s` written `count` times in a row.
+fn repeat(s: &str, count: f64) -> Result<Value, EvalError> {
+    if count < 0.0 || count.fract() != 0.0 {
+        return Err(EvalError::TypeError(format!("A string can only be repeated a whole number of times, not {}", count)));
+    }
+    if (s.len() as f64) * count > MAX_REPEAT_LENGTH as f64 {
+        return Err(EvalError::TypeError("Repeated string is too long".to_string()));
+    }
+    Ok(Value::String(s.repeat(count as usize)))
+}
+
+/// Applies a bitwise operator to two numbers, as 64-bit integers.
+fn bitwise(operator: &TokenType, l: f64, r: f64) -> Result<Value, EvalError> {
+    let (l, r) = (whole_number(l)?, whole_number(r)?);
+    let result = match operator {
+        TokenType::Ampersand => l & r,
+        TokenType::Pipe => l | r,
+        TokenType::Caret => l ^ r,
+        TokenType::LessLess | TokenType::GreaterGreater => {
+            let Some(shift) = u32::try_from(r).ok().filter(|shift| *shift < i64::BITS) else {
+                return Err(EvalError::TypeError(format!("Shift amount must be from 0 to 63, not {}", r)));
+            };
+            if *operator == TokenType::LessLess { l << shift } else { l >> shift }
+        }
+        _ => return Err(EvalError::SyntaxError("Unknown binary operator".to_string())),
+    };
+    Ok(Value::Number(result as f64))
+}
+
+/// The position in a list of `len` items that `index` names. It must be a
+/// whole number within the list.
+fn list_position(index: &Value, len: usize) -> Result<usize, EvalError> {
+    let position = match index {
+        Value::Number(n) => *n,
+        #[cfg(feature = "bignum")]
+        Value::BigNumber(n) => crate::bignum::to_f64(n),
+        index => return Err(EvalError::TypeError(format!("List indices must be numbers, not {}", index.type_name()))),
+    };
+    if position.fract() != 0.0 || position < 0.0 || position as usize >= len {
+        return Err(EvalError::TypeError(format!("List index {} is out of range for {} items", position, len)));
+    }
+    Ok(position as usize)
+}
+
+/// An operand of a bitwise operator as an integer. It must be a whole
+/// number that fits in 64 bits.
+fn whole_number(n: f64) -> Result<i64, EvalError> {
+    // i64::MAX rounds up to 2^63 as an f64, which is out of range.
+    if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        Ok(n as i64)
+    } else {
+        Err(EvalError::TypeError(format!("Bitwise operands must be whole numbers, not {}", n)))
+    }
+}
+
+/// Renders a value the way `print` shows it.
+pub(crate) fn stringify(value: &Value) -> String {
+    stringify_within(value, &mut Vec::new())
+}
+
+/// [`stringify`] for a value nested in the maps and lists in `open`, which
+/// are being rendered around it. One that holds itself shows as `{...}` or
+/// `[...]` there.
+fn stringify_within(value: &Value, open: &mut Vec<*const ()>) -> String {
+    match value {
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.clone(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Callable(callable) => format!("{:?}", callable),
+        Value::Map(map) => {
+            let id = Rc::as_ptr(map) as *const ();
+            if open.contains(&id) {
+                return "{...}".to_string();
+            }
+            open.push(id);
+            let entries = map
+                .borrow()
+                .iter()
+                .map(|(key, value)| format!("{}: {}", key, format_element_within(value, open)))
+                .collect::<Vec<_>>();
+            open.pop();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Value::List(list) => {
+            let id = Rc::as_ptr(list) as *const ();
+            if open.contains(&id) {
+                return "[...]".to_string();
             }
-        }        
+            open.push(id);
+            let items = list.borrow().iter().map(|item| format_element_within(item, open)).collect::<Vec<_>>();
+            open.pop();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Nil => "nil".to_string(),
+        Value::Instance(instance) => format!("{:?}", instance),
+        #[cfg(feature = "bignum")]
+        Value::BigNumber(n) => crate::bignum::format(n),
     }
 }
 
-fn is_truthy(expr: &Expr) -> bool {
-    match expr {
-        Expr::Literal(LiteralExpr::Nil) => false,
-        Expr::Literal(LiteralExpr::Boolean(b)) => *b,
+/// Renders a value held inside a map or list, quoting strings.
+pub(crate) fn format_element(value: &Value) -> String {
+    format_element_within(value, &mut Vec::new())
+}
+
+fn format_element_within(value: &Value, open: &mut Vec<*const ()>) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s),
+        other => stringify_within(other, open),
+    }
+}
+
+/// Whether two values are equal: maps and lists when their contents are,
+/// functions and instances only when they are the same one.
+pub(crate) fn values_equal(left: &Value, right: &Value) -> bool {
+    values_equal_within(left, right, &mut Vec::new())
+}
+
+/// [`values_equal`] for values nested in the pairs of maps or lists in
+/// `open`, which are being compared around them. Meeting one of those pairs
+/// again means the two hold themselves the same way, so that part is equal.
+fn values_equal_within(left: &Value, right: &Value, open: &mut Vec<(*const (), *const ())>) -> bool {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => l == r,
+        (Value::String(l), Value::String(r)) => l == r,
+        (Value::Boolean(l), Value::Boolean(r)) => l == r,
+        (Value::Nil, Value::Nil) => true,
+        (Value::Map(l), Value::Map(r)) => {
+            let pair = (Rc::as_ptr(l) as *const (), Rc::as_ptr(r) as *const ());
+            if open.contains(&pair) {
+                return true;
+            }
+            let (l, r) = (l.borrow(), r.borrow());
+            open.push(pair);
+            let equal = l.len() == r.len()
+                && l.iter().zip(r.iter()).all(|((lk, lv), (rk, rv))| lk == rk && values_equal_within(lv, rv, open));
+            open.pop();
+            equal
+        }
+        (Value::List(l), Value::List(r)) => {
+            let pair = (Rc::as_ptr(l) as *const (), Rc::as_ptr(r) as *const ());
+            if open.contains(&pair) {
+                return true;
+            }
+            let (l, r) = (l.borrow(), r.borrow());
+            open.push(pair);
+            let equal = l.len() == r.len() && l.iter().zip(r.iter()).all(|(l, r)| values_equal_within(l, r, open));
+            open.pop();
+            equal
+        }
+        (Value::Callable(l), Value::Callable(r)) => Rc::ptr_eq(l, r),
+        (Value::Instance(l), Value::Instance(r)) => Rc::ptr_eq(l, r),
+        #[cfg(feature = "bignum")]
+        (Value::BigNumber(l), Value::BigNumber(r)) => l == r,
+        _ => false,
+    }
+}
+
+/// Whether the condition of an `if` or `while`, named by `kind` in errors,
+/// holds: whether it is truthy, or with strict conditions whether it is
+/// `true`.
+fn test_condition(kind: &str, value: &Value, context: &Context) -> Result<bool, EvalError> {
+    match value {
+        Value::Boolean(b) => Ok(*b),
+        _ if context.options.strict_conditions => Err(EvalError::TypeError(format!("{} condition must be a boolean", kind))),
+        _ => Ok(is_truthy(value)),
+    }
+}
+
+/// The items a `for (var x in ...)` loop visits: the elements of a list or
+/// the keys of a map, in order. They are taken up front, so changing the
+/// collection in the body does not change the loop.
+fn iteration_items(value: &Value) -> Result<Vec<Value>, EvalError> {
+    match value {
+        Value::List(list) => Ok(list.borrow().clone()),
+        Value::Map(map) => Ok(map.borrow().keys().map(|key| Value::String(key.clone())).collect()),
+        other => Err(EvalError::TypeError(format!("Can only iterate over lists and maps, not {}", other.type_name()))),
+    }
+}
+
+pub(crate) fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Nil => false,
+        Value::Boolean(b) => *b,
         _ => true,
     }
 }
\ No newline at end of file
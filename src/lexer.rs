@@ -1,4 +1,7 @@
-use crate::token::{Token, TokenType};
+use std::ops::Range;
+
+use crate::error::LexError;
+use crate::token::{Literal, Span, Token, TokenType, Trivia};
 
 /// A `Lexer` tokenizes the source code into a sequence of tokens.
 pub struct Lexer {
@@ -7,35 +10,163 @@ pub struct Lexer {
     start: usize,
     current: usize,
     line: usize,
+    /// Line the current token started on; differs from `line` for multi-line strings.
+    start_line: usize,
+    /// Column of `current`, counting characters from 1.
+    column: usize,
+    /// Column the current token started at.
+    start_column: usize,
+    keep_trivia: bool,
+    /// Trivia seen since the last token, attached to the next one.
+    pending_trivia: Vec<Trivia>,
+    /// Whether iteration has already yielded the EOF token.
+    finished: bool,
 }
 
 impl Lexer {
-    /// Creates a new `Lexer` instance with the given source code.
+    /// Creates a new `Lexer` instance with the given source code. A leading
+    /// UTF-8 byte order mark is dropped.
     pub fn new(source: String) -> Self {
+        let source = match source.strip_prefix('\u{feff}') {
+            Some(stripped) => stripped.to_string(),
+            None => source,
+        };
         Lexer {
             source,
             tokens: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            start_line: 1,
+            column: 1,
+            start_column: 1,
+            keep_trivia: false,
+            pending_trivia: Vec::new(),
+            finished: false,
         }
     }
 
-    /// Tokenizes the source code and returns a vector of tokens.
-    pub fn tokenize(&mut self) -> &Vec<Token> {
+    /// Attach the whitespace and comments before each token to it as
+    /// `leading_trivia`, so tools like a formatter can reproduce the source.
+    pub fn with_trivia(mut self, enabled: bool) -> Self {
+        self.keep_trivia = enabled;
+        self
+    }
+
+    /// Tokenizes the source code, returning the tokens and, apart from
+    /// them, an error for each piece of the source that is not a token.
+    pub fn tokenize(&mut self) -> (Vec<Token>, Vec<LexError>) {
+        while !self.is_at_end() {
+            self.scan_next();
+        }
+
+        self.add_eof_token();
+        let mut errors = Vec::new();
+        let tokens = self.tokens.iter().filter_map(|token| match &token.token_type {
+            TokenType::Error(message) => {
+                errors.push(LexError { line: token.line, column: token.column, span: token.span, message: message.clone() });
+                None
+            }
+            _ => Some(token.clone()),
+        });
+        (tokens.collect(), errors)
+    }
+
+    /// Scans from the current position, recording what was skipped as trivia
+    /// when it produced no token.
+    fn scan_next(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column;
+        let token_count = self.tokens.len();
+        self.scan_token();
+        if self.keep_trivia && self.tokens.len() == token_count {
+            let text = &self.source[self.start..self.current];
+            match (text.starts_with("//") || text.starts_with("/*"), self.pending_trivia.last_mut()) {
+                (true, _) => self.pending_trivia.push(Trivia::Comment(text.to_string())),
+                (false, Some(Trivia::Whitespace(whitespace))) => whitespace.push_str(text),
+                (false, _) => self.pending_trivia.push(Trivia::Whitespace(text.to_string())),
+            }
+        }
+    }
+
+    /// Replaces the bytes in `range` with `new_text` and updates the token
+    /// list, re-scanning only from the last token before the edit until the
+    /// scan lines up with an old token again. Tokens after that point are kept
+    /// and shifted. Returns the updated tokens.
+    pub fn relex(&mut self, range: Range<usize>, new_text: &str) -> &Vec<Token> {
+        let mut old_tokens = std::mem::take(&mut self.tokens);
+        let old_trailing_trivia = match old_tokens.pop() {
+            Some(eof) => eof.leading_trivia,
+            None => Vec::new(),
+        };
+        let (old_line_count, old_end_column) = (self.line, self.column);
+        self.source.replace_range(range.clone(), new_text);
+        let delta = new_text.len() as isize - range.len() as isize;
+        let edit_end = range.start + new_text.len();
+
+        // Tokens ending before the edit are unaffected. The last of them is
+        // scanned again so a comment between it and the edit is picked up.
+        let resume = old_tokens.iter().rposition(|token| token.span.end < range.start);
+        (self.current, self.line, self.column, self.pending_trivia) = match resume {
+            Some(i) => (old_tokens[i].span.start, old_tokens[i].line, old_tokens[i].column, old_tokens[i].leading_trivia.clone()),
+            None => (0, 1, 1, Vec::new()),
+        };
+        let mut old_rest = old_tokens.split_off(resume.unwrap_or(0));
+        self.tokens = old_tokens;
+
         while !self.is_at_end() {
-            self.start = self.current;
-            self.scan_token();
+            // Past the edit, the lexer only depends on its position and line,
+            // so once it reaches an old token's (shifted) start the remaining
+            // old tokens are still valid.
+            if self.current >= edit_end {
+                let current = self.current;
+                let position = old_rest.partition_point(|token| token.span.start < range.end
+                    || (token.span.start as isize + delta) < current as isize);
+                if let Some(token) = old_rest.get(position) {
+                    if token.span.start as isize + delta == current as isize {
+                        let line_delta = self.line as isize - token.line as isize;
+                        // Only the rest of the line the scan stopped on moved sideways.
+                        let (moved_line, column_delta) = (token.line, self.column as isize - token.column as isize);
+                        old_rest[position].leading_trivia = std::mem::take(&mut self.pending_trivia);
+                        for mut token in old_rest.drain(position..) {
+                            token.span = shift(&token.span, delta);
+                            if token.line == moved_line {
+                                token.column = (token.column as isize + column_delta) as usize;
+                            }
+                            token.line = (token.line as isize + line_delta) as usize;
+                            self.tokens.push(token);
+                        }
+                        self.current = self.source.len();
+                        self.column = if old_line_count == moved_line {
+                            (old_end_column as isize + column_delta) as usize
+                        } else {
+                            old_end_column
+                        };
+                        self.line = (old_line_count as isize + line_delta) as usize;
+                        self.pending_trivia = old_trailing_trivia;
+                        break;
+                    }
+                }
+            }
+            self.scan_next();
         }
 
-        // Add EOF token to signify the end of the file.
+        self.add_eof_token();
+        &self.tokens
+    }
+
+    /// Adds the EOF token that terminates every token list.
+    fn add_eof_token(&mut self) {
         self.tokens.push(Token {
             token_type: TokenType::Eof,
             lexeme: String::new(),
+            literal: None,
             line: self.line,
+            column: self.column,
+            span: Span::new(self.source.len(), self.source.len()),
+            leading_trivia: std::mem::take(&mut self.pending_trivia),
         });
-
-        &self.tokens
     }
 
     /// Checks if the current position has reached the end of the source code.
@@ -51,13 +182,32 @@ impl Lexer {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
+            '.' if self.peek() == '.' && self.peek_next() == '.' => {
+                self.advance();
+                self.advance();
+                self.add_token(TokenType::DotDotDot);
+            }
             '.' => self.add_token(TokenType::Dot),
-            '-' => self.add_token(TokenType::Minus),
+            '-' => {
+                let token_type = if self.match_next('>') {
+                    TokenType::Arrow
+                } else {
+                    TokenType::Minus
+                };
+                self.add_token(token_type);
+            },
             '+' => self.add_token(TokenType::Plus),
             ';' => self.add_token(TokenType::Semicolon),
             '*' => self.add_token(TokenType::Star),
             '%' => self.add_token(TokenType::Percent),
+            ':' => self.add_token(TokenType::Colon),
+            '&' => self.add_token(TokenType::Ampersand),
+            '|' => self.add_token(TokenType::Pipe),
+            '^' => self.add_token(TokenType::Caret),
+            '~' => self.add_token(TokenType::Tilde),
             '!' => {
                 let token_type = if self.match_next('=') {
                     TokenType::BangEqual
@@ -77,6 +227,8 @@ impl Lexer {
             '>' => {
                 let token_type = if self.match_next('=') {
                     TokenType::GreaterEqual
+                } else if self.match_next('>') {
+                    TokenType::GreaterGreater
                 } else {
                     TokenType::Greater
                 };
@@ -85,6 +237,8 @@ impl Lexer {
             '<' => {
                 let token_type = if self.match_next('=') {
                     TokenType::LessEqual
+                } else if self.match_next('<') {
+                    TokenType::LessLess
                 } else {
                     TokenType::Less
                 };
@@ -96,23 +250,30 @@ impl Lexer {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                } else if self.match_next('*') {
+                    self.block_comment();
                 } else {
                     self.add_token(TokenType::Slash);
                 }
             }
+            '?' if self.match_next('?') => self.add_token(TokenType::QuestionQuestion),
+            '?' if self.match_next('.') => self.add_token(TokenType::QuestionDot),
             '"' => self.handle_string(),
             '0'..='9' => self.handle_number(),
             'a'..='z' | 'A'..='Z' | '_' => self.handle_identifier(),
-            ' ' | '\r' | '\t' => {} // Ignore whitespace
-            '\n' => self.line += 1,
+            ' ' | '\t' => {} // Ignore whitespace
+            '\r' | '\n' => self.newline(c),
             _ => self.handle_unknown_token(c),
         }
     }
 
     /// Advances to the next character and returns the current character.
+    /// Positions are byte offsets, so this steps over the whole character.
     fn advance(&mut self) -> char {
-        self.current += 1;
-        self.source.chars().nth(self.current - 1).unwrap()
+        let c = self.peek();
+        self.current += c.len_utf8();
+        self.column += 1;
+        c
     }
 
     /// Checks if the next character matches the expected one and advances.
@@ -120,10 +281,11 @@ impl Lexer {
         if self.is_at_end() {
             return false;
         }
-        if self.source[self.current..].chars().next().unwrap() != expected {
+        if self.peek() != expected {
             return false;
         }
         self.current += 1;
+        self.column += 1;
         true
     }
 
@@ -134,69 +296,120 @@ impl Lexer {
 
     /// Adds a token of the specified type to the token list.
     fn add_token(&mut self, token_type: TokenType) {
+        self.add_literal_token(token_type, None);
+    }
+
+    /// Adds a token of the specified type carrying a decoded literal value.
+    fn add_literal_token(&mut self, token_type: TokenType, literal: Option<Literal>) {
         let text = &self.source[self.start..self.current];
         self.tokens.push(Token {
             token_type,
             lexeme: text.to_string(),
-            line: self.line,
+            literal,
+            line: self.start_line,
+            column: self.start_column,
+            span: Span::new(self.start, self.current),
+            leading_trivia: std::mem::take(&mut self.pending_trivia),
         });
     }
 
-    /// Adds a token of the specified type to the token list with specified text.
-    fn add_string_token(&mut self, token_type: TokenType, text: String) {
-        self.tokens.push(Token {
-            token_type,
-            lexeme: text.to_string(),
-            line: self.line,
-        });
+    /// Counts a line break. `\r\n` is a single break: its `\r` is skipped and
+    /// the `\n` that follows is counted, while a lone `\r` counts by itself.
+    fn newline(&mut self, c: char) {
+        if c == '\n' || self.peek() != '\n' {
+            self.line += 1;
+            self.column = 1;
+        }
     }
 
-    /// Handles string literals.
+    /// Handles string literals, decoding the escape sequences `\n`, `\t`,
+    /// `\r`, `\"` and `\\` in their value.
     fn handle_string(&mut self) {
+        let mut value = String::new();
+        let mut invalid_escape = None;
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            let c = self.advance();
+            if c == '\r' || c == '\n' {
+                self.newline(c);
+            }
+            if c != '\\' || self.is_at_end() {
+                value.push(c);
+                continue;
+            }
+            let escaped = self.advance();
+            match escaped {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                '"' => value.push('"'),
+                '\\' => value.push('\\'),
+                _ => {
+                    if escaped == '\r' || escaped == '\n' {
+                        self.newline(escaped);
+                    }
+                    invalid_escape.get_or_insert(escaped);
+                }
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            eprintln!("Unterminated string on line {}", self.line);
+            self.add_token(TokenType::Error("Unterminated string.".to_string()));
             return;
         }
 
         // Consume the closing quote
         self.advance();
 
-        let text = &self.source[self.start + 1..self.current - 1];
-        self.add_string_token(TokenType::String, text.to_string());
+        if let Some(c) = invalid_escape {
+            self.add_token(TokenType::Error(format!("Invalid escape sequence '\\{}' in string.", c.escape_default())));
+            return;
+        }
+        self.add_literal_token(TokenType::String, Some(Literal::String(value)));
+    }
+
+    /// Skips a `/* ... */` comment whose opening has been consumed. Comments
+    /// nest, so each `/*` inside needs a `*/` of its own.
+    fn block_comment(&mut self) {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                self.add_token(TokenType::Error("Unterminated block comment.".to_string()));
+                return;
+            }
+            let c = self.advance();
+            match c {
+                '/' if self.match_next('*') => depth += 1,
+                '*' if self.match_next('/') => depth -= 1,
+                '\r' | '\n' => self.newline(c),
+                _ => {}
+            }
+        }
     }
 
     /// Handles numeric literals.
     fn handle_number(&mut self) {
-        while self.peek().is_digit(10) {
+        while self.peek().is_ascii_digit() {
             self.advance();
         }
 
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             self.advance();
 
-            while self.peek().is_digit(10) {
+            while self.peek().is_ascii_digit() {
                 self.advance();
             }
         }
 
-        // let text = &self.source[self.start..self.current];
-        self.add_token(TokenType::Number);
+        // A literal that fails to parse is left without a value for the parser to report.
+        let value = self.source[self.start..self.current].parse().ok().map(Literal::Number);
+        self.add_literal_token(TokenType::Number, value);
     }
 
     /// Returns the next character without advancing.
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            '\0'
-        } else {
-            self.source[self.current + 1..].chars().next().unwrap_or('\0')
-        }
+        let mut chars = self.source[self.current..].chars();
+        chars.next();
+        chars.next().unwrap_or('\0')
     }
 
     /// Handles identifiers and keywords.
@@ -225,14 +438,41 @@ impl Lexer {
             "while" => TokenType::While,
             "break" => TokenType::Break,
             "continue" => TokenType::Continue,
+            "in" => TokenType::In,
+            "const" => TokenType::Const,
             _ => TokenType::Identifier,
         };
 
         self.add_token(token_type);
     }
 
-    /// Handles unexpected characters and reports an error.
+    /// Handles unexpected characters by emitting an error token for the parser to report.
     fn handle_unknown_token(&mut self, c: char) {
-        eprintln!("Unexpected character '{}' on line {}", c, self.line);
+        self.add_token(TokenType::Error(format!("Unexpected character '{}'.", c)));
+    }
+}
+
+/// Lexes one token at a time, ending with the EOF token. Tokens yielded this
+/// way are not kept in the lexer.
+impl Iterator for Lexer {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        while self.tokens.is_empty() && !self.is_at_end() {
+            self.scan_next();
+        }
+        if self.tokens.is_empty() {
+            if self.finished {
+                return None;
+            }
+            self.finished = true;
+            self.add_eof_token();
+        }
+        self.tokens.pop()
     }
-}
\ No newline at end of file
+}
+
+/// Moves a byte range by `delta` bytes.
+fn shift(span: &Span, delta: isize) -> Span {
+    Span::new((span.start as isize + delta) as usize, (span.end as isize + delta) as usize)
+}
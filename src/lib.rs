@@ -1,9 +1,23 @@
+use environ::Environment;
+use error::{Diagnostic, ErrorFormat, ParserError, RunError, Warning};
+use interpreter::{Context, ExecutionStats, LanguageOptions, DEFAULT_MAX_CALL_DEPTH};
+use manifest::{Manifest, MANIFEST_FILE};
+use natives::{define_native_functions, define_native_functions_where, Capabilities};
+use stmt::Stmt;
 use resolver::Resolver;
 use wasm_bindgen::prelude::*;
 use lexer::Lexer;
-use parser::Parser;
+use parser::{Parser, DEFAULT_MAX_ERRORS};
+use random::Random;
+use value::Value;
+use input::{InputProvider, LinesInput};
+use std::cell::RefCell;
 use std::fs;
 use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub mod lexer;
 pub mod token;
@@ -14,53 +28,329 @@ pub mod stmt;
 pub mod environ;
 pub mod interpreter;
 pub mod callable;
+pub mod class;
+pub mod value;
 pub mod natives;
+pub mod random;
+pub mod input;
+pub mod methods;
 pub mod resolver;
+pub mod transpile;
+pub mod manifest;
+pub mod import;
+pub mod ast_format;
+pub mod ast_diff;
+pub mod golden;
+pub mod difftest;
+pub mod conformance;
+pub mod selftest;
+pub mod session;
+pub mod printer;
+pub mod typecheck;
+pub mod batch;
+#[cfg(feature = "bignum")]
+pub mod bignum;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+pub mod event_loop;
+pub mod explain;
+#[cfg(not(target_arch = "wasm32"))]
+mod tasks;
+#[cfg(feature = "proptest")]
+pub mod strategy;
 
+/// Lists the tokens of a program one per line, followed by an error for
+/// each piece of it that is not a token.
 #[wasm_bindgen]
 pub fn tokenize(file_contents: &str) -> String {
-    let mut lexer = Lexer::new(file_contents.to_string());
-    let tokens = lexer.tokenize();
-    tokens.iter().map(|t| format!("{:?}", t)).collect::<Vec<_>>().join("\n")
+    let (tokens, errors) = Lexer::new(file_contents.to_string()).tokenize();
+    tokens.iter()
+        .map(|t| format!("{:?}", t))
+        .chain(errors.iter().map(|e| e.to_string()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Settings shared by the CLI entry points.
+#[derive(Debug, Clone, Copy)]
+pub struct RunOptions {
+    pub error_format: ErrorFormat,
+    /// Parsing stops after this many syntax errors.
+    pub max_errors: usize,
+    /// Accept a missing semicolon at the very end of the input (REPL and `-e`).
+    pub implicit_final_semicolon: bool,
+    pub language: LanguageOptions,
+    /// Privileged natives the program may use.
+    pub capabilities: Capabilities,
+    /// Stop the program after this many statements.
+    pub fuel: Option<u64>,
+    /// Stop the program when more Lox functions than this are active at once.
+    pub max_call_depth: usize,
+    /// Stop the program when one run of a loop goes round more times than this.
+    pub max_loop_iterations: Option<u64>,
+    /// Seed for `random()` and `randomInt()`, so runs can be repeated.
+    pub seed: Option<u64>,
+    /// Print [`ExecutionStats`] to stderr after the run.
+    pub stats: bool,
+    /// Narrate each evaluation step in the output, see [`explain::Explain`].
+    pub explain: bool,
+    /// Write the output to stdout whenever the program waits for input,
+    /// instead of only once it ends. Only for runs whose output goes to
+    /// stdout.
+    pub stream_output: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            error_format: ErrorFormat::Human,
+            max_errors: DEFAULT_MAX_ERRORS,
+            implicit_final_semicolon: false,
+            language: LanguageOptions::default(),
+            capabilities: Capabilities::default(),
+            fuel: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_loop_iterations: None,
+            seed: None,
+            stats: false,
+            explain: false,
+            stream_output: false,
+        }
+    }
 }
 
 #[wasm_bindgen]
 pub fn parse(file_contents: &str) -> String {
-    let mut lexer = Lexer::new(file_contents.to_string());
-    let tokens = lexer.tokenize();
-    let mut parser = Parser::new(tokens.to_vec());
-    let (statements, errors) = parser.parse();
-
-    if !errors.is_empty() {
-        errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")
-    } else {
-        format!("Parsed statements: {:?}", statements)
+    match parse_program(file_contents) {
+        Ok(statements) => format!("Parsed statements: {:?}", statements),
+        Err(errors) => RunError::Parse(errors).render(ErrorFormat::Human),
     }
 }
 
 #[wasm_bindgen]
 pub fn interpret(file_contents: &str) -> String {
-    let mut lexer = Lexer::new(file_contents.to_string());
-    let tokens = lexer.tokenize();
-    let mut parser = Parser::new(tokens.to_vec());
+    let globals = Rc::new(RefCell::new(Environment::new()));
+    define_native_functions(&mut globals.borrow_mut());
+    match interpret_source(file_contents, globals, &RunOptions::default()) {
+        Ok(output) => output,
+        Err(e) => e.render(ErrorFormat::Human),
+    }
+}
+
+/// Like [`interpret`], but stops any loop that goes round more than
+/// `max_loop_iterations` times in one run, naming its line, instead of
+/// hanging the page.
+#[wasm_bindgen]
+pub fn interpret_with_loop_limit(file_contents: &str, max_loop_iterations: u32) -> String {
+    let options = RunOptions { max_loop_iterations: Some(max_loop_iterations.into()), ..RunOptions::default() };
+    match interpret_source(file_contents, new_globals(options.capabilities), &options) {
+        Ok(output) => output,
+        Err(e) => e.render(ErrorFormat::Human),
+    }
+}
+
+/// Like [`interpret`], with `input` as the lines `input()` and
+/// `readLine()` return, since the page has no standard input to read.
+#[wasm_bindgen]
+pub fn interpret_with_input(file_contents: &str, input: &str) -> String {
+    let options = RunOptions::default();
+    let statements = match parse_program_with_options(file_contents, &options) {
+        Ok(statements) => statements,
+        Err(errors) => return RunError::Parse(errors).render(ErrorFormat::Human),
+    };
+    let input = Box::new(LinesInput::new(input));
+    match run_statements_with_input(&statements, new_globals(options.capabilities), &options, input) {
+        (context, Ok(())) => context.output,
+        (context, Err(e)) => context.output + &e.render(ErrorFormat::Human),
+    }
+}
+
+/// Runs the programs built into the binary and reports which produced the
+/// wrong output, to check that a build, the wasm one especially, works.
+#[wasm_bindgen]
+pub fn selftest() -> String {
+    selftest::run_selftest().to_string()
+}
+
+/// Lexes and parses a whole program, returning its statements or every syntax error found.
+pub fn parse_program(file_contents: &str) -> Result<Vec<Stmt>, Vec<ParserError>> {
+    parse_program_with_options(file_contents, &RunOptions::default())
+}
+
+/// Like [`parse_program`], honoring the parser settings in `options`.
+pub fn parse_program_with_options(file_contents: &str, options: &RunOptions) -> Result<Vec<Stmt>, Vec<ParserError>> {
+    let mut parser = Parser::new(Lexer::new(file_contents.to_string()))
+        .with_max_errors(options.max_errors)
+        .with_implicit_final_semicolon(options.implicit_final_semicolon);
     let (statements, errors) = parser.parse();
+
+    if errors.is_empty() {
+        Ok(statements)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parses, resolves and runs `file_contents` in `globals`, returning everything it printed.
+pub fn interpret_source(file_contents: &str, globals: Rc<RefCell<Environment>>, options: &RunOptions) -> Result<String, RunError> {
+    let statements = parse_program_with_options(file_contents, options).map_err(RunError::Parse)?;
+    match run_statements(&statements, globals, options) {
+        (context, Ok(())) => Ok(context.output),
+        (_, Err(e)) => Err(e),
+    }
+}
+
+/// Resolves and runs parsed statements, returning the run's context, with
+/// what they printed up to the error if they stop with one, next to how
+/// they ended. Statements the resolver rejects do not run at all.
+pub(crate) fn run_statements(statements: &[Stmt], globals: Rc<RefCell<Environment>>, options: &RunOptions) -> (Context, Result<(), RunError>) {
+    run_statements_with_input(statements, globals, options, input::default_input())
+}
+
+/// Like [`run_statements`], reading lines for `input()` from `input`.
+pub(crate) fn run_statements_with_input(
+    statements: &[Stmt],
+    globals: Rc<RefCell<Environment>>,
+    options: &RunOptions,
+    input: Box<dyn InputProvider>,
+) -> (Context, Result<(), RunError>) {
     let mut resolver = Resolver::new();
-    resolver.resolve(&statements);
+    let errors = resolver.resolve(statements);
+    let mut context = Context::new(options.language);
+    context.input = input;
+    context.stream_output = options.stream_output;
+    context.fuel = options.fuel;
+    context.max_call_depth = options.max_call_depth;
+    context.max_loop_iterations = options.max_loop_iterations;
+    if let Some(seed) = options.seed {
+        context.random = Random::new(seed);
+    }
+    if !errors.is_empty() {
+        return (context, Err(RunError::Resolve(errors)));
+    }
+    if options.explain {
+        context.hook = Some(Box::new(explain::Explain::default()));
+    }
+    let result = interpreter::interpret_with_env(statements, Some(globals), &resolver, &mut context);
+    (context, result.map(|_| ()).map_err(RunError::Runtime))
+}
 
+/// How a run through [`run_with_sinks`] went.
+#[derive(Debug, Default)]
+pub struct RunReport {
+    /// The program parsed and ran without errors.
+    pub succeeded: bool,
+    /// What the program did; all zero when it failed to parse.
+    pub stats: ExecutionStats,
+}
+
+/// Runs `file_contents` in `globals`, writing what it prints to `out` and
+/// any errors, in `options.error_format`, to `diagnostics`. Output printed
+/// before a runtime error is kept.
+pub fn run_with_sinks(
+    file_contents: &str,
+    globals: Rc<RefCell<Environment>>,
+    options: &RunOptions,
+    out: &mut dyn Write,
+    diagnostics: &mut dyn Write,
+) -> io::Result<RunReport> {
+    let statements = match parse_program_with_options(file_contents, options) {
+        Ok(statements) => statements,
+        Err(errors) => {
+            writeln!(diagnostics, "{}", RunError::Parse(errors).render_with_source(options.error_format, file_contents))?;
+            return Ok(RunReport::default());
+        }
+    };
+    run_parsed_with_sinks(file_contents, &statements, globals, options, out, diagnostics)
+}
+
+fn run_parsed_with_sinks(
+    file_contents: &str,
+    statements: &[Stmt],
+    globals: Rc<RefCell<Environment>>,
+    options: &RunOptions,
+    out: &mut dyn Write,
+    diagnostics: &mut dyn Write,
+) -> io::Result<RunReport> {
+    let started = Instant::now();
+    let (context, result) = run_statements(statements, globals, options);
+    let stats = ExecutionStats { wall_time: started.elapsed(), ..context.stats };
+    // Streamed runs have written part of their output already.
+    out.write_all(&context.output.as_bytes()[context.flushed..])?;
+    let succeeded = result.is_ok();
+    match result {
+        Ok(()) => {}
+        // Like syntax errors, these come before anything runs, so they
+        // point into the source.
+        Err(e @ RunError::Resolve(_)) => writeln!(diagnostics, "{}", e.render_with_source(options.error_format, file_contents))?,
+        Err(e) => writeln!(diagnostics, "{}", e.render(options.error_format))?,
+    }
+    Ok(RunReport { succeeded, stats })
+}
+
+/// Parses and resolves a program without running it, returning warnings
+/// about code that always fails alongside its syntax and scope errors or,
+/// with `types`, the places it disagrees with its type annotations.
+pub fn check_source(file_contents: &str, options: &RunOptions, types: bool) -> (Vec<Warning>, Result<(), RunError>) {
+    let statements = match parse_program_with_options(file_contents, options) {
+        Ok(statements) => statements,
+        Err(errors) => return (Vec::new(), Err(RunError::Parse(errors))),
+    };
+    let errors = Resolver::new().resolve(&statements);
     if !errors.is_empty() {
-        errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")
+        return (Vec::new(), Err(RunError::Resolve(errors)));
+    }
+    let report = typecheck::typecheck(file_contents, &statements);
+    if types && !report.errors.is_empty() {
+        return (report.warnings, Err(RunError::Type(report.errors)));
+    }
+    (report.warnings, Ok(()))
+}
+
+pub fn transpile_js(file_contents: &str) -> Result<String, RunError> {
+    let statements = parse_program(file_contents).map_err(RunError::Parse)?;
+    Ok(transpile::to_javascript(&statements))
+}
+
+pub fn transpile_js_with_source_map(file_contents: &str, source_name: &str, file_name: &str) -> Result<(String, String), RunError> {
+    let statements = parse_program(file_contents).map_err(RunError::Parse)?;
+    Ok(transpile::to_javascript_with_source_map(&statements, source_name, file_name))
+}
+
+/// Reformats a program from its syntax tree, either laid out like
+/// [`printer::to_source`] or squeezed onto one line.
+pub fn format_source(file_contents: &str, minify: bool) -> Result<String, RunError> {
+    let statements = parse_program(file_contents).map_err(RunError::Parse)?;
+    if minify {
+        Ok(format!("{}\n", printer::to_minified_source(&statements)))
     } else {
-        match interpreter::interpret(&statements, &resolver) {
-            Ok(output) => output,
-            Err(e) => e.to_string(),
-        }
+        Ok(printer::to_source(&statements))
+    }
+}
+
+/// The smallest source that parses to the same program, for embedding
+/// scripts in playground links.
+#[wasm_bindgen]
+pub fn minify(file_contents: &str) -> String {
+    match parse_program(file_contents) {
+        Ok(statements) => printer::to_minified_source(&statements),
+        Err(errors) => RunError::Parse(errors).render(ErrorFormat::Human),
     }
 }
 
 // CLI functions, which call the above functions
-pub fn run_tokenize(filename: &str) {
+/// Prints the tokens of the file to stdout and the lexer errors, in
+/// `format`, to stderr. Returns whether there were none.
+pub fn run_tokenize(filename: &str, format: ErrorFormat) -> bool {
     let file_contents = read_file(filename);
-    println!("{}", tokenize(&file_contents));
+    let (tokens, errors) = Lexer::new(file_contents.clone()).tokenize();
+    for token in &tokens {
+        println!("{:?}", token);
+    }
+    for error in &errors {
+        eprintln!("{}", Diagnostic::from(error).render(format, Some(&file_contents)));
+    }
+    errors.is_empty()
 }
 
 pub fn run_parse(filename: &str) {
@@ -68,14 +358,248 @@ pub fn run_parse(filename: &str) {
     println!("{}", parse(&file_contents));
 }
 
-pub fn run_interpret(filename: &str) {
+pub fn run_interpret(filename: &str, options: &RunOptions) {
+    run_source(&read_file(filename), options);
+}
+
+/// Like [`run_with_sinks`], collecting the output and the diagnostics as strings.
+pub fn run_captured(file_contents: &str, globals: Rc<RefCell<Environment>>, options: &RunOptions) -> (String, String) {
+    let (mut output, mut diagnostics) = (Vec::new(), Vec::new());
+    // Writing to a `Vec` cannot fail.
+    let _ = run_with_sinks(file_contents, globals, options, &mut output, &mut diagnostics);
+    (String::from_utf8_lossy(&output).into_owned(), String::from_utf8_lossy(&diagnostics).into_owned())
+}
+
+/// What `:help` prints in the REPL.
+pub const REPL_HELP: &str = "\
+:help         list these commands
+:env          show the variables defined so far
+:load <file>  run a file, keeping what it defines
+:reset        forget everything defined so far
+:quit         leave the REPL, as does exit
+";
+
+/// Runs one input typed into the REPL in `globals`, like [`run_captured`].
+/// Inputs starting with `:` are commands, listed in [`REPL_HELP`], except
+/// `:quit`, which is left to the REPL loop.
+pub fn run_repl_input(input: &str, globals: Rc<RefCell<Environment>>, options: &RunOptions) -> (String, String) {
+    let Some(command) = input.trim().strip_prefix(':') else {
+        return run_captured(input, globals, options);
+    };
+    let (name, argument) = command.split_once(char::is_whitespace).map_or((command, ""), |(name, argument)| (name, argument.trim()));
+    match name {
+        "help" => (REPL_HELP.to_string(), String::new()),
+        "env" => (format_bindings(&globals.borrow()), String::new()),
+        "load" if argument.is_empty() => (String::new(), "Usage: :load <file>\n".to_string()),
+        "load" => match fs::read_to_string(argument) {
+            Ok(file_contents) => run_captured(&file_contents, globals, options),
+            Err(_) => (String::new(), format!("Failed to read file {}\n", argument)),
+        },
+        "reset" => {
+            // Replaced in place, so functions defined earlier that still
+            // hold on to the globals see the fresh ones too.
+            let fresh = new_globals(options.capabilities).borrow().clone();
+            *globals.borrow_mut() = fresh;
+            (String::new(), String::new())
+        }
+        _ => (String::new(), format!("Unknown command :{}. Type :help for the list.\n", name)),
+    }
+}
+
+/// Lists the variables in `environment` one per line, sorted by name, as
+/// `name = value`. Natives are left out.
+fn format_bindings(environment: &Environment) -> String {
+    let mut bindings: Vec<_> = environment
+        .variables()
+        .filter(|(_, value)| !matches!(value, Value::Callable(callable) if callable.as_function().is_none() && callable.as_class().is_none()))
+        .collect();
+    bindings.sort_by_key(|(name, _)| *name);
+    bindings.iter().map(|(name, value)| format!("{} = {}\n", name, interpreter::format_element(value))).collect()
+}
+
+/// Creates a global environment holding the natives `capabilities` grants.
+pub fn new_globals(capabilities: Capabilities) -> Rc<RefCell<Environment>> {
+    let globals = Rc::new(RefCell::new(Environment::new()));
+    define_native_functions_where(&mut globals.borrow_mut(), capabilities, |_| true);
+    globals
+}
+
+/// Runs a program given as a string with fresh globals, printing its output
+/// to stdout and its errors to stderr.
+pub fn run_source(file_contents: &str, options: &RunOptions) {
+    let globals = new_globals(options.capabilities);
+    run_to_std_streams(file_contents, globals, options);
+}
+
+/// Runs a script like [`run_interpret`], with the natives of the plugins
+/// at `plugins` defined as well.
+#[cfg(feature = "plugins")]
+pub fn run_interpret_with_plugins(filename: &str, plugins: &[&str], options: &RunOptions) {
+    let globals = new_globals(options.capabilities);
+    for path in plugins {
+        if let Err(e) = plugin::load_plugin(path, &mut globals.borrow_mut()) {
+            eprintln!("{}", e);
+            return;
+        }
+    }
+    run_to_std_streams(&read_file(filename), globals, options);
+}
+
+/// Finds and loads the manifest of the project at `path`, which is a
+/// directory, a `lox.toml` or, when empty, the working directory.
+pub fn find_project(path: &str) -> Result<Manifest, String> {
+    let start = if path.is_empty() { Path::new(".") } else { Path::new(path) };
+    let manifest_path = Manifest::find(start).ok_or_else(|| format!("No {} found", MANIFEST_FILE))?;
+    Manifest::load(&manifest_path)
+}
+
+/// Whether a command given `path` works on a whole project rather than on
+/// a single script.
+pub fn is_project_path(path: &str) -> bool {
+    path.is_empty() || Path::new(path).is_dir() || Path::new(path).ends_with(MANIFEST_FILE)
+}
+
+/// Globals for the scripts of a project: the natives its manifest allows
+/// and `import`, searching the manifest's paths.
+pub fn project_globals(manifest: &Manifest, options: &RunOptions) -> Rc<RefCell<Environment>> {
+    let globals = Rc::new(RefCell::new(Environment::new()));
+    define_native_functions_where(&mut globals.borrow_mut(), options.capabilities, |name| manifest.allows_native(name));
+    if manifest.allows_native("import") {
+        let import = import::Import::new(manifest.search_paths());
+        globals.borrow_mut().define("import".to_string(), Value::Callable(Rc::new(import)));
+    }
+    globals
+}
+
+/// Runs the entry script of the project described by `manifest`, with
+/// `options` already carrying its settings, see [`Manifest::apply`].
+pub fn run_project(manifest: &Manifest, options: &RunOptions) {
+    let file_contents = read_file(&manifest.entry_path().to_string_lossy());
+    run_to_std_streams(&file_contents, project_globals(manifest, options), options);
+}
+
+/// Runs the golden fixtures in the project's tests directory, see
+/// [`golden::run_golden`], each in fresh [`project_globals`].
+pub fn test_project(manifest: &Manifest, options: &RunOptions) -> io::Result<golden::GoldenReport> {
+    golden::run_golden_with(&manifest.tests_path(), |source| {
+        golden::run_fixture_in(source, project_globals(manifest, options), options)
+    })
+}
+
+/// Program output goes to stdout and diagnostics to stderr, so the two never mix.
+fn run_to_std_streams(file_contents: &str, globals: Rc<RefCell<Environment>>, options: &RunOptions) {
+    let options = &RunOptions { stream_output: true, ..*options };
+    match run_with_sinks(file_contents, globals, options, &mut io::stdout(), &mut io::stderr()) {
+        Ok(report) if options.stats => eprintln!("{}", report.stats),
+        Ok(_) => (),
+        Err(e) => eprintln!("Failed to write output: {}", e),
+    }
+}
+
+/// How often `--watch` checks whether the script has changed.
+const WATCH_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Runs a changed script again in the globals of its earlier runs, like
+/// [`run_with_sinks`]. Its functions are defined afresh and its other
+/// statements run again, but global variables that already exist keep
+/// their values instead of being declared again, so the state built up so
+/// far survives.
+pub fn reload_with_sinks(
+    file_contents: &str,
+    globals: Rc<RefCell<Environment>>,
+    options: &RunOptions,
+    out: &mut dyn Write,
+    diagnostics: &mut dyn Write,
+) -> io::Result<RunReport> {
+    let statements = match parse_program_with_options(file_contents, options) {
+        Ok(statements) => statements,
+        Err(errors) => {
+            writeln!(diagnostics, "{}", RunError::Parse(errors).render_with_source(options.error_format, file_contents))?;
+            return Ok(RunReport::default());
+        }
+    };
+    let defined = |name: &str| globals.borrow().variables().any(|(global, _)| global == name);
+    let statements: Vec<Stmt> = statements
+        .into_iter()
+        .filter(|stmt| !matches!(stmt, Stmt::Var(name, ..) if defined(name)))
+        .collect();
+    run_parsed_with_sinks(file_contents, &statements, globals, options, out, diagnostics)
+}
+
+/// Runs a script, then keeps watching it and reloads it with
+/// [`reload_with_sinks`] each time it is saved. Runs until interrupted.
+pub fn run_watch(filename: &str, options: &RunOptions) {
+    let modified = || fs::metadata(filename).and_then(|metadata| metadata.modified()).ok();
+    let globals = new_globals(options.capabilities);
+    run_to_std_streams(&read_file(filename), globals.clone(), options);
+    let mut last_modified = modified();
+    loop {
+        thread::sleep(WATCH_INTERVAL);
+        let current = modified();
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+        eprintln!("Reloading {}", filename);
+        let options = &RunOptions { stream_output: true, ..*options };
+        if let Err(e) = reload_with_sinks(&read_file(filename), globals.clone(), options, &mut io::stdout(), &mut io::stderr()) {
+            eprintln!("Failed to write output: {}", e);
+        }
+    }
+}
+
+/// Transpiles a script to `target`, writing the code to `out_file` or
+/// stdout and any syntax errors, in `format`, to stderr.
+pub fn run_transpile(filename: &str, target: &str, out_file: Option<&str>, source_map_file: Option<&str>, format: ErrorFormat) {
+    if target != "js" {
+        eprintln!("Unknown transpile target: {}", target);
+        return;
+    }
+    let file_contents = read_file(filename);
+    let result = match source_map_file {
+        None => transpile_js(&file_contents).map(|code| (code, None)),
+        Some(map_path) => {
+            let generated = out_file.unwrap_or("out.js");
+            transpile_js_with_source_map(&file_contents, filename, generated).map(|(code, source_map)| (code, Some((map_path, source_map))))
+        }
+    };
+    let code = match result {
+        Ok((code, None)) => code,
+        Ok((code, Some((map_path, source_map)))) => {
+            if fs::write(map_path, source_map).is_err() {
+                eprintln!("Failed to write file {}", map_path);
+                return;
+            }
+            format!("{}//# sourceMappingURL={}\n", code, map_path)
+        }
+        Err(e) => {
+            eprintln!("{}", e.render_with_source(format, &file_contents));
+            return;
+        }
+    };
+
+    match out_file {
+        Some(path) => {
+            if fs::write(path, code).is_err() {
+                eprintln!("Failed to write file {}", path);
+            }
+        }
+        None => print!("{}", code),
+    }
+}
+
+/// Prints a script reformatted, or its syntax errors in `format` to stderr.
+pub fn run_fmt(filename: &str, minify: bool, format: ErrorFormat) {
     let file_contents = read_file(filename);
-    println!("{}", interpret(&file_contents));
+    match format_source(&file_contents, minify) {
+        Ok(source) => print!("{}", source),
+        Err(e) => eprintln!("{}", e.render_with_source(format, &file_contents)),
+    }
 }
 
 pub fn read_file(filename: &str) -> String {
     fs::read_to_string(filename).unwrap_or_else(|_| {
-        writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
+        eprintln!("Failed to read file {}", filename);
         String::new()
     })
 }
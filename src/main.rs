@@ -1,87 +1,428 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::{env, string};
-use std::io::{self, Write};
-use rustylox::environ::Environment;
-use rustylox::resolver::Resolver;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use rustylox::error::ErrorFormat;
 use rustylox::stmt::pretty_print_program;
-use rustylox::{run_interpret, read_file, run_tokenize, lexer::Lexer, parser, parser::Parser};
-use rustylox::interpreter;
-use rustylox::natives::define_native_functions;
+use rustylox::{new_globals, run_repl_input, RunOptions, run_interpret, run_watch, run_source, run_project, test_project, find_project, is_project_path, read_file, run_tokenize, run_transpile, run_fmt, check_source, lexer::Lexer, parser::Parser};
+use rustylox::ast_diff::diff_sources;
+use rustylox::ast_format::AstFormat;
+use rustylox::batch::run_batch_dir;
+use rustylox::conformance::run_conformance;
+use rustylox::difftest::run_difftest;
+use rustylox::selftest::run_selftest;
+use rustylox::session::{Session, SessionEntry};
+use rustylox::manifest::Manifest;
 
 const TOKENIZE: &str = "tokenize";
 const PARSE: &str = "parse";
 const INTERPRET: &str = "interpret";
 const CLI: &str = "cli";
+const TRANSPILE: &str = "transpile";
+const FMT: &str = "fmt";
+const CHECK: &str = "check";
+const TEST: &str = "test";
+const DIFF: &str = "diff";
+const RUN_ALL: &str = "run-all";
+const DIFFTEST: &str = "difftest";
+const CONFORMANCE: &str = "conformance";
+const SELFTEST: &str = "selftest";
+const REPLAY: &str = "replay";
+
+/// Flags that consume the argument following them.
+const FLAGS_WITH_VALUES: &[&str] = &["--target", "-o", "--source-map", "--error-format", "--max-errors", "--max-call-depth", "--max-loop-iterations", "--seed", "-e", "--allow", "--reference", "--ast-format", "--record", "--jobs", "--preload", "--plugin"];
+
+/// Returns the value following `flag` in the argument list, if present.
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(|value| value.as_str())
+}
+
+/// Returns the arguments that are neither flags nor flag values.
+fn positional_args(args: &[String]) -> Vec<&str> {
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if FLAGS_WITH_VALUES.contains(&arg.as_str()) {
+            iter.next();
+        } else if !arg.starts_with('-') {
+            positional.push(arg.as_str());
+        }
+    }
+    positional
+}
+
+/// Returns the flags that affect how code runs, leaving out the command, file,
+/// `--record` and `--preload`, so a recorded session can be replayed with the
+/// same settings. Preloading is recorded as a `:load` input instead.
+fn run_flags(args: &[String]) -> Vec<String> {
+    let mut flags = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--record" || arg == "--preload" {
+            iter.next();
+        } else if FLAGS_WITH_VALUES.contains(&arg.as_str()) {
+            flags.push(arg.clone());
+            flags.extend(iter.next().cloned());
+        } else if arg.starts_with('-') {
+            flags.push(arg.clone());
+        }
+    }
+    flags
+}
+
+fn parse_run_options(args: &[String]) -> Result<RunOptions, String> {
+    let mut options = RunOptions::default();
+    if let Some(format) = flag_value(args, "--error-format") {
+        options.error_format = format.parse::<ErrorFormat>()?;
+    }
+    if let Some(max_errors) = flag_value(args, "--max-errors") {
+        options.max_errors = max_errors
+            .parse()
+            .map_err(|_| format!("Invalid value for --max-errors: {}", max_errors))?;
+    }
+    if let Some(max_call_depth) = flag_value(args, "--max-call-depth") {
+        options.max_call_depth = max_call_depth
+            .parse()
+            .map_err(|_| format!("Invalid value for --max-call-depth: {}", max_call_depth))?;
+    }
+    if let Some(max_loop_iterations) = flag_value(args, "--max-loop-iterations") {
+        let limit = max_loop_iterations
+            .parse()
+            .map_err(|_| format!("Invalid value for --max-loop-iterations: {}", max_loop_iterations))?;
+        options.max_loop_iterations = Some(limit);
+    }
+    if let Some(seed) = flag_value(args, "--seed") {
+        options.seed = Some(seed.parse().map_err(|_| format!("Invalid value for --seed: {}", seed))?);
+    }
+    options.stats = args.iter().any(|arg| arg == "--stats");
+    options.explain = args.iter().any(|arg| arg == "--explain");
+    options.language.integer_division = args.iter().any(|arg| arg == "--integer-division");
+    options.language.implicit_return = args.iter().any(|arg| arg == "--implicit-return");
+    options.language.strict_conditions = args.iter().any(|arg| arg == "--strict-conditions");
+    if args.iter().any(|arg| arg == "--bignum") {
+        #[cfg(feature = "bignum")]
+        {
+            options.language.bignum = true;
+        }
+        #[cfg(not(feature = "bignum"))]
+        return Err("--bignum needs a build with --features bignum".to_string());
+    }
+    if let Some(capabilities) = flag_value(args, "--allow") {
+        for name in capabilities.split(',') {
+            options.capabilities.grant(name.trim())?;
+        }
+    }
+    Ok(options)
+}
+
+/// Loads the project at `path`, see [`find_project`], and returns its
+/// manifest with `options` updated by its settings, reporting why not if
+/// it cannot.
+fn load_project(path: &str, options: &RunOptions) -> Option<(Manifest, RunOptions)> {
+    let mut options = *options;
+    match find_project(path).and_then(|manifest| manifest.apply(&mut options).map(|_| manifest)) {
+        Ok(manifest) => Some((manifest, options)),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    }
+}
+
+/// Where the REPL keeps the lines typed into it, across sessions.
+fn history_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("rustylox").join("history"))
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        writeln!(io::stderr(), "Usage: {} <command> <filename>", args[0]).unwrap();
-        writeln!(io::stderr(), "Commands: {TOKENIZE} {PARSE} {INTERPRET} {CLI}").unwrap();
+    let positional = positional_args(&args[1..]);
+    if positional.is_empty() {
+        eprintln!("Usage: {} <command> <filename> [-e code] [--error-format human|json] [--max-errors N] [--max-call-depth N] [--max-loop-iterations N] [--seed N] [--stats] [--explain] [--integer-division] [--implicit-return] [--strict-conditions] [--bignum] [--allow exec,net,fs] [--ast-format v1|v2] [--record session.json] [--preload file.lox] [--minify] [--types] [--jobs N] [--watch] [--plugin lib.so]", args[0]);
+        eprintln!("Commands: {TOKENIZE} {PARSE} {INTERPRET} {TRANSPILE} {FMT} {CHECK} {TEST} {DIFF} {CLI} {RUN_ALL} {DIFFTEST} {CONFORMANCE} {SELFTEST} {REPLAY}");
         return;
     }
 
-    let command = &args[1];
-    let mut filename = "";
+    let command = positional[0];
+    let filename = positional.get(1).copied().unwrap_or("");
+    let options = match parse_run_options(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let format = options.error_format;
     if command == CLI {
         println!("🚀 Welcome to the Lox programming language REPL!");
-    } else {
-        filename = &args[2];
     }
 
-    match command.as_str() {
-        TOKENIZE => run_tokenize(filename),
+    match command {
+        TOKENIZE => {
+            if !run_tokenize(filename, format) {
+                std::process::exit(1);
+            }
+        }
         PARSE => {
             let file_contents = read_file(filename);
-            let mut lexer = Lexer::new(file_contents.to_string());
-            let tokens = lexer.tokenize();
-            let mut parser = Parser::new(tokens.to_vec());
-            let (statements, errors) = parser.parse();
-            let parsed = pretty_print_program((statements, errors));
-            print!("{}", parsed);
-        }
-        INTERPRET => run_interpret(filename),
+            let mut parser = Parser::new(Lexer::new(file_contents.to_string())).with_max_errors(options.max_errors);
+            let (statements, mut errors) = parser.parse();
+            if format == ErrorFormat::Json {
+                for error in &errors {
+                    eprintln!("{}", error.to_json());
+                }
+                errors.clear();
+            }
+            match flag_value(&args, "--ast-format").map(str::parse::<AstFormat>) {
+                Some(Ok(ast_format)) => print!("{}", ast_format.emit(statements, errors)),
+                Some(Err(e)) => eprintln!("{}", e),
+                None => print!("{}", pretty_print_program((statements, errors))),
+            }
+        }
+        INTERPRET if flag_value(&args, "-e").is_some() => {
+            let code = flag_value(&args, "-e").unwrap_or_default();
+            let options = RunOptions { implicit_final_semicolon: true, ..options };
+            run_source(code, &options);
+        }
+        INTERPRET => {
+            // Without a script, or when pointed at a directory or manifest, run the project.
+            if is_project_path(filename) {
+                if let Some((manifest, options)) = load_project(filename, &options) {
+                    run_project(&manifest, &options);
+                }
+            } else if args.iter().any(|arg| arg == "--plugin") {
+                let plugins: Vec<&str> = args
+                    .windows(2)
+                    .filter(|pair| pair[0] == "--plugin")
+                    .map(|pair| pair[1].as_str())
+                    .collect();
+                #[cfg(feature = "plugins")]
+                rustylox::run_interpret_with_plugins(filename, &plugins, &options);
+                #[cfg(not(feature = "plugins"))]
+                eprintln!("Cannot load {}: plugins need a build with --features plugins", plugins.join(", "));
+            } else if args.iter().any(|arg| arg == "--watch") {
+                run_watch(filename, &options);
+            } else {
+                run_interpret(filename, &options);
+            }
+        }
+        TRANSPILE => {
+            let target = flag_value(&args, "--target").unwrap_or("js");
+            run_transpile(filename, target, flag_value(&args, "-o"), flag_value(&args, "--source-map"), format);
+        }
+        FMT => {
+            let minify = args.iter().any(|arg| arg == "--minify");
+            if !is_project_path(filename) {
+                run_fmt(filename, minify, format);
+            } else if let Some((manifest, _)) = load_project(filename, &options) {
+                run_fmt(&manifest.entry_path().to_string_lossy(), minify, format);
+            }
+        }
+        CHECK => {
+            let mut types = args.iter().any(|arg| arg == "--types");
+            // Like `interpret`, a project is checked through its entry script.
+            let (filename, options) = if is_project_path(filename) {
+                let Some((manifest, options)) = load_project(filename, &options) else {
+                    std::process::exit(1);
+                };
+                types |= manifest.strict.types;
+                (manifest.entry_path().to_string_lossy().into_owned(), options)
+            } else {
+                (filename.to_string(), options)
+            };
+            let file_contents = read_file(&filename);
+            let (warnings, result) = check_source(&file_contents, &options, types);
+            for warning in &warnings {
+                eprintln!("{}", warning.render(format));
+            }
+            if let Err(e) = result {
+                eprintln!("{}", e.render_with_source(format, &file_contents));
+                std::process::exit(1);
+            }
+        }
+        TEST => {
+            let Some((manifest, options)) = load_project(filename, &options) else {
+                std::process::exit(1);
+            };
+            match test_project(&manifest, &options) {
+                Ok(report) => {
+                    for failure in &report.failures {
+                        println!("{}", failure);
+                    }
+                    println!("{} of {} fixtures passed", report.passed, report.passed + report.failures.len());
+                    if !report.failures.is_empty() {
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Test run failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        DIFF => {
+            let new_filename = positional.get(2).copied().unwrap_or("");
+            match diff_sources(&read_file(filename), &read_file(new_filename)) {
+                Ok(changes) => {
+                    for change in &changes {
+                        println!("{}", change);
+                    }
+                    if !changes.is_empty() {
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", e.render(format));
+                    std::process::exit(2);
+                }
+            }
+        }
         CLI => {
             println!("✨ Program logs will be displayed here. Stay tuned!");
 
+            let options = RunOptions { implicit_final_semicolon: true, ..options };
+            let mut editor = match DefaultEditor::new() {
+                Ok(editor) => editor,
+                Err(e) => {
+                    eprintln!("Failed to start the REPL: {}", e);
+                    return;
+                }
+            };
+            let history = history_path();
+            if let Some(path) = &history {
+                // There is no history yet on the first run.
+                let _ = editor.load_history(path);
+            }
             let mut input = String::new();
-            let cli_environ = Rc::new(RefCell::new(Environment::new()));
-            define_native_functions(&mut cli_environ.borrow_mut());
+            let cli_environ = new_globals(options.capabilities);
+            let record = flag_value(&args, "--record").map(Path::new);
+            let mut session = Session::new(run_flags(&args[1..]));
+            if let Some(path) = flag_value(&args, "--preload") {
+                input = format!(":load {}\n", path);
+            }
             loop {
-                print!("> ");
-                io::stdout().flush().unwrap();
-                io::stdin().read_line(&mut input).unwrap();
-                if input.trim() == "exit" {
-                    break;
+                // A preloaded file runs first, as if loaded at the prompt.
+                if input.is_empty() {
+                    match editor.readline("> ") {
+                        Ok(line) => {
+                            let _ = editor.add_history_entry(line.as_str());
+                            input = line;
+                        }
+                        // Ctrl-C drops the line being typed.
+                        Err(ReadlineError::Interrupted) => continue,
+                        // Stop on end of input or an unreadable stdin instead of spinning.
+                        Err(_) => break,
+                    }
+                    if matches!(input.trim(), "exit" | ":quit") {
+                        break;
+                    }
                 }
 
-                let mut lexer = Lexer::new(input.clone());
-                let tokens = lexer.tokenize();
-                let mut parser = parser::Parser::new(tokens.to_vec());
-                let (statements, errors) = parser.parse();
-                let mut resolver = Resolver::new();
-                resolver.resolve(&statements);
-
-                let output = if !errors.is_empty() {
-                    errors.into_iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")
-                } else {
-                    match interpreter::interpret_with_env(&statements, Some(cli_environ.clone()), &resolver, &mut string::String::new()) {
-                        Ok(output) => output,
-                        Err(e) => e.to_string(),
+                let (output, diagnostics) = run_repl_input(&input, cli_environ.clone(), &options);
+                print!("{}", output);
+                eprint!("{}", diagnostics);
+                if let Some(path) = record {
+                    session.record(&input, &output, &diagnostics);
+                    if let Err(e) = session.save(path) {
+                        eprintln!("{}", e);
                     }
-                };
-
-                if !output.is_empty() {
-                    writeln!(io::stderr(), "{}", output).unwrap();
                 }
 
                 input.clear();
             }
+            if let Some(path) = &history {
+                let saved = path.parent().map_or(Ok(()), fs::create_dir_all).map_err(ReadlineError::from);
+                if let Err(e) = saved.and_then(|_| editor.save_history(path)) {
+                    eprintln!("Failed to save the REPL history: {}", e);
+                }
+            }
+        }
+        RUN_ALL => {
+            let jobs = match flag_value(&args, "--jobs") {
+                Some(jobs) => match jobs.parse() {
+                    Ok(jobs) => jobs,
+                    Err(_) => {
+                        eprintln!("Invalid value for --jobs: {}", jobs);
+                        return;
+                    }
+                },
+                None => std::thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+            };
+            match run_batch_dir(Path::new(filename), &options, jobs) {
+                Ok(report) => {
+                    println!("{}", report);
+                    if report.succeeded() < report.results.len() {
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => eprintln!("Batch run failed: {}", e),
+            }
+        }
+        DIFFTEST => {
+            let Some(reference) = flag_value(&args, "--reference") else {
+                eprintln!("Usage: {} {DIFFTEST} <dir> --reference <command>", args[0]);
+                return;
+            };
+            match run_difftest(Path::new(filename), reference) {
+                Ok(report) => {
+                    for divergence in &report.divergences {
+                        println!("{}", divergence);
+                    }
+                    let total = report.matched + report.divergences.len();
+                    println!("{} of {} scripts diverged", report.divergences.len(), total);
+                }
+                Err(e) => eprintln!("Difftest failed: {}", e),
+            }
+        }
+        CONFORMANCE => match run_conformance(Path::new(filename)) {
+            Ok(report) => println!("{}", report),
+            Err(e) => eprintln!("Conformance run failed: {}", e),
+        },
+        SELFTEST => {
+            let report = run_selftest();
+            println!("{}", report);
+            if !report.success() {
+                std::process::exit(1);
+            }
+        }
+        REPLAY => {
+            let session = match Session::load(Path::new(filename)) {
+                Ok(session) => session,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            let options = match parse_run_options(&session.arguments) {
+                Ok(options) => RunOptions { implicit_final_semicolon: true, ..options },
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            let replayed = session.replay(&options);
+            let mut reproduced = 0;
+            let shown = |entry: &SessionEntry| format!("{}{}", entry.output, entry.diagnostics).trim_end().to_string();
+            for (recorded, entry) in session.entries.iter().zip(&replayed.entries) {
+                print!("> {}", entry.input);
+                if !shown(entry).is_empty() {
+                    println!("{}", shown(entry));
+                }
+                if recorded.same_result(entry) {
+                    reproduced += 1;
+                } else {
+                    println!("! recorded instead:\n{}", shown(recorded));
+                }
+            }
+            println!("{} of {} inputs reproduced the recorded result", reproduced, session.entries.len());
         }
         _ => {
-            writeln!(io::stderr(), "Unknown command: {}", command).unwrap();
-            writeln!(io::stderr(), "Commands: {TOKENIZE} {PARSE} {INTERPRET} {CLI}").unwrap();
+            eprintln!("Unknown command: {}", command);
+            eprintln!("Commands: {TOKENIZE} {PARSE} {INTERPRET} {TRANSPILE} {FMT} {CHECK} {TEST} {DIFF} {CLI} {RUN_ALL} {DIFFTEST} {CONFORMANCE} {SELFTEST} {REPLAY}");
         }
     }
 }
@@ -0,0 +1,129 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::RunOptions;
+
+pub const MANIFEST_FILE: &str = "lox.toml";
+
+/// A `lox.toml` project manifest.
+///
+/// ```toml
+/// entry = "src/main.lox"
+/// paths = ["lib"]
+/// tests = "tests"
+///
+/// [strict]
+/// conditions = true
+/// types = true
+///
+/// [natives]
+/// allow = ["clock"]
+///
+/// [sandbox]
+/// allow = ["exec"]
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Script executed by `interpret`, relative to the manifest.
+    pub entry: PathBuf,
+    /// Directories `import` searches, relative to the manifest, after the
+    /// manifest's own directory.
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+    /// Directory of golden fixtures run by `test`, relative to the manifest.
+    #[serde(default = "default_tests")]
+    pub tests: PathBuf,
+    #[serde(default)]
+    pub strict: StrictPolicy,
+    #[serde(default)]
+    pub natives: NativesPolicy,
+    #[serde(default)]
+    pub sandbox: SandboxPolicy,
+    /// Directory containing the manifest.
+    #[serde(skip)]
+    pub root: PathBuf,
+}
+
+fn default_tests() -> PathBuf {
+    PathBuf::from("tests")
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StrictPolicy {
+    /// `if` and `while` conditions must be booleans, as with `--strict-conditions`.
+    #[serde(default)]
+    pub conditions: bool,
+    /// `check` reports type errors, as with `--types`.
+    #[serde(default)]
+    pub types: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NativesPolicy {
+    /// Natives made available to the program; all of them when absent.
+    pub allow: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SandboxPolicy {
+    /// Capabilities granted to the program, such as `"exec"`; none by default.
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl Manifest {
+    /// Reads and validates the manifest at `path`.
+    pub fn load(path: &Path) -> Result<Manifest, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|_| format!("Failed to read manifest {}", path.display()))?;
+        let mut manifest: Manifest = toml::from_str(&contents)
+            .map_err(|e| format!("Invalid manifest {}: {}", path.display(), e))?;
+        manifest.root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(manifest)
+    }
+
+    /// Looks for a manifest in `start` and each of its ancestors.
+    pub fn find(start: &Path) -> Option<PathBuf> {
+        let start = start.canonicalize().unwrap_or_else(|_| start.to_path_buf());
+        start
+            .ancestors()
+            .map(|dir| dir.join(MANIFEST_FILE))
+            .find(|candidate| candidate.is_file())
+    }
+
+    pub fn entry_path(&self) -> PathBuf {
+        self.root.join(&self.entry)
+    }
+
+    pub fn tests_path(&self) -> PathBuf {
+        self.root.join(&self.tests)
+    }
+
+    /// Directories `import` searches, in order.
+    pub fn search_paths(&self) -> Vec<PathBuf> {
+        std::iter::once(self.root.clone()).chain(self.paths.iter().map(|path| self.root.join(path))).collect()
+    }
+
+    /// Grants the sandbox's capabilities and turns on the strictness
+    /// settings in `options`, on top of those given on the command line.
+    pub fn apply(&self, options: &mut RunOptions) -> Result<(), String> {
+        for name in &self.sandbox.allow {
+            options.capabilities.grant(name)?;
+        }
+        options.language.strict_conditions |= self.strict.conditions;
+        Ok(())
+    }
+
+    pub fn allows_native(&self, name: &str) -> bool {
+        match &self.natives.allow {
+            Some(allowed) => allowed.iter().any(|n| n == name),
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{callable::{BoundMethod, NativeFunction}, error::EvalError, value::Value, natives::NativeFn};
+
+/// Methods on strings as `(name, arity, implementation)`. Implementations
+/// receive the string itself as their first argument.
+const STRING_METHODS: &[(&str, usize, NativeFn)] = &[
+    ("len", 0, string_len),
+    ("upper", 0, string_upper),
+    ("lower", 0, string_lower),
+    ("trim", 0, string_trim),
+    ("contains", 1, string_contains),
+];
+
+/// Methods on numbers, in the same form as [`STRING_METHODS`].
+const NUMBER_METHODS: &[(&str, usize, NativeFn)] = &[
+    ("floor", 0, number_floor),
+    ("ceil", 0, number_ceil),
+    ("round", 0, number_round),
+    ("abs", 0, number_abs),
+];
+
+/// Methods on lists, in the same form as [`STRING_METHODS`].
+const LIST_METHODS: &[(&str, usize, NativeFn)] = &[
+    ("len", 0, list_len),
+    ("get", 1, list_get),
+    ("push", 1, list_push),
+];
+
+/// Looks up `name` among the methods of `receiver`'s type and binds it to
+/// `receiver`.
+pub fn get_method(receiver: &Value, name: &str) -> Result<Value, EvalError> {
+    let methods = match receiver {
+        Value::String(_) => STRING_METHODS,
+        Value::Number(_) => NUMBER_METHODS,
+        #[cfg(feature = "bignum")]
+        Value::BigNumber(_) => NUMBER_METHODS,
+        Value::List(_) => LIST_METHODS,
+        _ => &[],
+    };
+    match methods.iter().find(|(method_name, _, _)| *method_name == name) {
+        Some((name, arity, function)) => {
+            let method = NativeFunction::new(name, *arity, *function);
+            Ok(Value::Callable(Rc::new(BoundMethod::new(receiver.clone(), method))))
+        }
+        None => Err(EvalError::TypeError(format!("{} has no method '{}'", receiver.type_name(), name))),
+    }
+}
+
+fn string_arg(args: &[Value], index: usize) -> Result<&str, EvalError> {
+    match &args[index] {
+        Value::String(s) => Ok(s),
+        other => Err(EvalError::TypeError(format!("Expected a string but got {}", other.type_name()))),
+    }
+}
+
+fn number_arg(args: &[Value], index: usize) -> Result<f64, EvalError> {
+    match &args[index] {
+        Value::Number(n) => Ok(*n),
+        other => Err(EvalError::TypeError(format!("Expected a number but got {}", other.type_name()))),
+    }
+}
+
+fn list_arg(args: &[Value], index: usize) -> Result<&RefCell<Vec<Value>>, EvalError> {
+    match &args[index] {
+        Value::List(list) => Ok(list),
+        other => Err(EvalError::TypeError(format!("Expected a list but got {}", other.type_name()))),
+    }
+}
+
+fn string_len(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Number(string_arg(&args, 0)?.chars().count() as f64))
+}
+
+fn string_upper(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::String(string_arg(&args, 0)?.to_uppercase()))
+}
+
+fn string_lower(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::String(string_arg(&args, 0)?.to_lowercase()))
+}
+
+fn string_trim(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::String(string_arg(&args, 0)?.trim().to_string()))
+}
+
+fn string_contains(args: Vec<Value>) -> Result<Value, EvalError> {
+    let needle = string_arg(&args, 1)?;
+    Ok(Value::Boolean(string_arg(&args, 0)?.contains(needle)))
+}
+
+fn number_floor(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Number(number_arg(&args, 0)?.floor()))
+}
+
+fn number_ceil(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Number(number_arg(&args, 0)?.ceil()))
+}
+
+fn number_round(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Number(number_arg(&args, 0)?.round()))
+}
+
+fn number_abs(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Number(number_arg(&args, 0)?.abs()))
+}
+
+fn list_len(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Number(list_arg(&args, 0)?.borrow().len() as f64))
+}
+
+/// `list.get(index)` returns the item at `index`, or nil when out of range.
+fn list_get(args: Vec<Value>) -> Result<Value, EvalError> {
+    let index = number_arg(&args, 1)?;
+    let list = list_arg(&args, 0)?;
+    if index < 0.0 || index.fract() != 0.0 {
+        return Ok(Value::Nil);
+    }
+    Ok(list.borrow().get(index as usize).cloned().unwrap_or(Value::Nil))
+}
+
+/// `list.push(item)` adds `item` to the end of the list and returns the
+/// new length.
+fn list_push(mut args: Vec<Value>) -> Result<Value, EvalError> {
+    let item = args.pop().unwrap_or(Value::Nil);
+    let mut list = list_arg(&args, 0)?.borrow_mut();
+    list.push(item);
+    Ok(Value::Number(list.len() as f64))
+}
@@ -1,15 +1,799 @@
-use std::{rc::Rc, time::{SystemTime, UNIX_EPOCH}};
+use std::{collections::BTreeMap, process::Command, rc::Rc};
 
-use crate::{callable::NativeFunction, environ::Environment, error::EvalError, expr::LiteralExpr};
+use crate::{callable::{ContextNativeFn, NativeFunction}, environ::Environment, error::EvalError, value::Value, interpreter::Context};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::tasks;
 
-pub fn clock(_args: Vec<LiteralExpr>) -> Result<LiteralExpr, EvalError> {
-    let start = SystemTime::now();
-    let since_the_epoch = start.duration_since(UNIX_EPOCH).expect("Time went backwards");
-    let seconds = since_the_epoch.as_secs_f64();
-    Ok(LiteralExpr::Number(seconds))
+pub(crate) type NativeFn = fn(Vec<Value>) -> Result<Value, EvalError>;
+
+/// Every native function as `(name, arity, implementation)`.
+const NATIVES: &[(&str, usize, NativeFn)] = &[
+    ("clock", 0, clock),
+    ("sha256", 1, hash::sha256),
+    ("md5", 1, hash::md5),
+    ("hmac_sha256", 2, hash::hmac_sha256),
+    ("read_all_stdin", 0, stream::read_all_stdin),
+    ("len", 1, strings::len),
+    ("substring", 3, strings::substring),
+    ("indexOf", 2, strings::index_of),
+    ("split", 2, strings::split),
+    ("toUpper", 1, strings::to_upper),
+    ("toLower", 1, strings::to_lower),
+    ("trim", 1, strings::trim),
+    ("replace", 3, strings::replace),
+    ("str", 1, convert::str),
+    ("num", 1, convert::num),
+    ("bool", 1, convert::bool),
+    ("type", 1, convert::type_of),
+    ("sqrt", 1, math::sqrt),
+    ("abs", 1, math::abs),
+    ("floor", 1, math::floor),
+    ("ceil", 1, math::ceil),
+    ("pow", 2, math::pow),
+    ("min", 2, math::min),
+    ("max", 2, math::max),
+    ("sin", 1, math::sin),
+    ("cos", 1, math::cos),
+    ("log", 1, math::log),
+    ("range", 2, math::range),
+    #[cfg(feature = "time")]
+    ("now_iso", 0, time::now_iso),
+    #[cfg(feature = "time")]
+    ("date_format", 2, time::date_format),
+    #[cfg(feature = "time")]
+    ("date_parse", 2, time::date_parse),
+    #[cfg(not(target_arch = "wasm32"))]
+    ("chan", 0, tasks::chan),
+    #[cfg(not(target_arch = "wasm32"))]
+    ("send", 2, tasks::send),
+    #[cfg(not(target_arch = "wasm32"))]
+    ("recv", 1, tasks::recv),
+];
+
+/// Natives that work with the run context, as `(name, arity, implementation)`.
+const CONTEXT_NATIVES: &[(&str, usize, ContextNativeFn)] = &[
+    ("write_stdout", 1, stream::write_stdout),
+    ("eprint", 1, stream::eprint),
+    ("stacktrace", 0, stacktrace),
+    ("input", 0, stream::input),
+    ("readLine", 0, stream::input),
+    ("random", 0, random::random),
+    ("randomInt", 2, random::random_int),
+    ("seedRandom", 1, random::seed_random),
+    #[cfg(not(target_arch = "wasm32"))]
+    ("spawn", 1, tasks::spawn),
+    #[cfg(not(target_arch = "wasm32"))]
+    ("join", 1, tasks::join),
+];
+
+/// Natives that reach outside the interpreter, as `(name, arity,
+/// implementation, required capability)`.
+const PRIVILEGED_NATIVES: &[(&str, usize, NativeFn, Capability)] = &[
+    ("exec", 2, exec, Capability::Exec),
+    ("path_join", 2, fs::path_join, Capability::Fs),
+    ("path_exists", 1, fs::path_exists, Capability::Fs),
+    ("list_dir", 1, fs::list_dir, Capability::Fs),
+    ("mkdir", 1, fs::mkdir, Capability::Fs),
+    ("remove_file", 1, fs::remove_file, Capability::Fs),
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    ("tcp_connect", 2, net::tcp_connect, Capability::Net),
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    ("tcp_listen", 2, net::tcp_listen, Capability::Net),
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    ("tcp_accept", 1, net::tcp_accept, Capability::Net),
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    ("tcp_send", 2, net::tcp_send, Capability::Net),
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    ("tcp_recv", 2, net::tcp_recv, Capability::Net),
+    #[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+    ("tcp_close", 1, net::tcp_close, Capability::Net),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capability {
+    Exec,
+    Net,
+    Fs,
+}
+
+/// What a program may do beyond pure computation. Everything is denied by
+/// default; natives needing a capability are only defined when it is granted.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Capabilities {
+    /// Run subprocesses with `exec`.
+    pub exec: bool,
+    /// Open network connections with the `tcp_*` natives.
+    pub net: bool,
+    /// Inspect and change the filesystem with the path natives.
+    pub fs: bool,
+}
+
+impl Capabilities {
+    /// Grants the capability called `name`.
+    pub fn grant(&mut self, name: &str) -> Result<(), String> {
+        match name {
+            "exec" => self.exec = true,
+            "net" => self.net = true,
+            "fs" => self.fs = true,
+            _ => return Err(format!("Unknown capability: {}", name)),
+        }
+        Ok(())
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Exec => self.exec,
+            Capability::Net => self.net,
+            Capability::Fs => self.fs,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn clock(_args: Vec<Value>) -> Result<Value, EvalError> {
+    let since_the_epoch = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|_| EvalError::NativeError("System clock is set before 1970".to_string()))?;
+    Ok(Value::Number(since_the_epoch.as_secs_f64()))
+}
+
+/// In the browser `SystemTime` is unavailable, so the time comes from JavaScript.
+#[cfg(target_arch = "wasm32")]
+pub fn clock(_args: Vec<Value>) -> Result<Value, EvalError> {
+    #[wasm_bindgen::prelude::wasm_bindgen]
+    extern "C" {
+        #[wasm_bindgen(js_namespace = Date)]
+        fn now() -> f64;
+    }
+    Ok(Value::Number(now() / 1000.0))
+}
+
+/// `stacktrace()` returns the Lox call stack, innermost call first, as a list
+/// of `{function, line}` maps. Each line is where that function currently is;
+/// the outermost entry is the top level of the script.
+pub fn stacktrace(_args: Vec<Value>, context: &mut Context) -> Result<Value, EvalError> {
+    // Each frame records where its function was called from, which is the
+    // current line of the frame before it. The last frame is the call to
+    // stacktrace itself.
+    let mut frames = Vec::new();
+    for (index, call) in context.frames.iter().enumerate().rev() {
+        let function = match index {
+            0 => "script",
+            _ => &context.frames[index - 1].function,
+        };
+        let mut frame = BTreeMap::new();
+        frame.insert("function".to_string(), Value::String(function.to_string()));
+        frame.insert("line".to_string(), Value::Number(call.line as f64));
+        frames.push(Value::map(frame));
+    }
+    Ok(Value::list(frames))
+}
+
+/// Raw standard stream natives, so scripts can act as filters in a pipeline.
+mod stream {
+    use std::io::{self, Read};
+
+    use crate::{error::EvalError, value::Value, interpreter::{stringify, Context}};
+
+    /// `read_all_stdin()` reads standard input to its end.
+    pub fn read_all_stdin(_args: Vec<Value>) -> Result<Value, EvalError> {
+        let mut input = String::new();
+        io::stdin()
+            .read_to_string(&mut input)
+            .map_err(|e| EvalError::NativeError(format!("Failed to read stdin: {}", e)))?;
+        Ok(Value::String(input))
+    }
+
+    /// `input()`, also called `readLine()`, reads the next line of input
+    /// without its line ending, or gives nil at the end of the input.
+    pub fn input(_args: Vec<Value>, context: &mut Context) -> Result<Value, EvalError> {
+        context.flush_output()?;
+        match context.input.read_line() {
+            Ok(line) => Ok(line.map_or(Value::Nil, Value::String)),
+            Err(message) => Err(EvalError::NativeError(message)),
+        }
+    }
+
+    /// `eprint(value)` prints a line to standard error straight away,
+    /// after the output printed before it.
+    pub fn eprint(args: Vec<Value>, context: &mut Context) -> Result<Value, EvalError> {
+        context.flush_output()?;
+        eprintln!("{}", stringify(&args[0]));
+        Ok(Value::Nil)
+    }
+
+    /// `write_stdout(value)` adds to the program output like `print`, without
+    /// the trailing newline.
+    pub fn write_stdout(args: Vec<Value>, context: &mut Context) -> Result<Value, EvalError> {
+        context.output.push_str(&stringify(&args[0]));
+        Ok(Value::Nil)
+    }
+}
+
+/// Conversions between types, and `type(x)` to tell them apart.
+mod convert {
+    use crate::{error::EvalError, interpreter::{is_truthy, stringify}, value::Value};
+
+    /// `str(x)` renders any value the way `print` shows it.
+    pub fn str(args: Vec<Value>) -> Result<Value, EvalError> {
+        Ok(Value::String(stringify(&args[0])))
+    }
+
+    /// `num(x)` reads a number from a string, ignoring surrounding
+    /// whitespace, and gives nil when the string does not hold one.
+    pub fn num(args: Vec<Value>) -> Result<Value, EvalError> {
+        match &args[0] {
+            Value::String(s) => Ok(s.trim().parse().map_or(Value::Nil, Value::Number)),
+            Value::Number(_) => Ok(args[0].clone()),
+            #[cfg(feature = "bignum")]
+            Value::BigNumber(_) => Ok(args[0].clone()),
+            other => Err(EvalError::TypeError(format!("num expects a string or number but got {}", other.type_name()))),
+        }
+    }
+
+    /// `bool(x)` is whether `x` counts as true in a condition.
+    pub fn bool(args: Vec<Value>) -> Result<Value, EvalError> {
+        Ok(Value::Boolean(is_truthy(&args[0])))
+    }
+
+    /// `type(x)` names the type of `x`, e.g. "number" or "function".
+    pub fn type_of(args: Vec<Value>) -> Result<Value, EvalError> {
+        Ok(Value::String(args[0].type_name().to_string()))
+    }
+}
+
+/// String natives. Positions and lengths count characters, not bytes.
+mod strings {
+    use crate::{error::EvalError, value::Value};
+
+    fn string_arg<'a>(args: &'a [Value], index: usize, native: &str) -> Result<&'a str, EvalError> {
+        match &args[index] {
+            Value::String(s) => Ok(s),
+            other => Err(EvalError::TypeError(format!("{} expects a string but got {}", native, other.type_name()))),
+        }
+    }
+
+    fn position_arg(args: &[Value], index: usize, native: &str) -> Result<usize, EvalError> {
+        let n = match &args[index] {
+            Value::Number(n) => *n,
+            #[cfg(feature = "bignum")]
+            Value::BigNumber(n) => crate::bignum::to_f64(n),
+            other => return Err(EvalError::TypeError(format!("{} expects a position but got {}", native, other.type_name()))),
+        };
+        if n < 0.0 || n.fract() != 0.0 {
+            return Err(EvalError::TypeError(format!("{} expects a whole, non-negative position but got {}", native, n)));
+        }
+        Ok(n as usize)
+    }
+
+    /// `len(value)` counts the characters of a string or the items of a list or map.
+    pub fn len(args: Vec<Value>) -> Result<Value, EvalError> {
+        let len = match &args[0] {
+            Value::String(s) => s.chars().count(),
+            Value::List(list) => list.borrow().len(),
+            Value::Map(map) => map.borrow().len(),
+            other => return Err(EvalError::TypeError(format!("len expects a string, list or map but got {}", other.type_name()))),
+        };
+        Ok(Value::Number(len as f64))
+    }
+
+    /// `substring(s, start, end)` returns the characters from `start` up to but not including `end`.
+    pub fn substring(args: Vec<Value>) -> Result<Value, EvalError> {
+        let s = string_arg(&args, 0, "substring")?;
+        let start = position_arg(&args, 1, "substring")?;
+        let end = position_arg(&args, 2, "substring")?;
+        let len = s.chars().count();
+        if start > end || end > len {
+            return Err(EvalError::TypeError(format!("substring range {}..{} is out of bounds for length {}", start, end, len)));
+        }
+        Ok(Value::String(s.chars().skip(start).take(end - start).collect()))
+    }
+
+    /// `indexOf(s, needle)` returns the position of the first `needle` in `s`, or -1.
+    pub fn index_of(args: Vec<Value>) -> Result<Value, EvalError> {
+        let s = string_arg(&args, 0, "indexOf")?;
+        let needle = string_arg(&args, 1, "indexOf")?;
+        let position = s.find(needle).map_or(-1.0, |byte| s[..byte].chars().count() as f64);
+        Ok(Value::Number(position))
+    }
+
+    /// `split(s, separator)` returns the list of pieces between separators.
+    /// An empty separator splits `s` into its characters.
+    pub fn split(args: Vec<Value>) -> Result<Value, EvalError> {
+        let s = string_arg(&args, 0, "split")?;
+        let separator = string_arg(&args, 1, "split")?;
+        let pieces: Vec<Value> = if separator.is_empty() {
+            s.chars().map(|c| Value::String(c.to_string())).collect()
+        } else {
+            s.split(separator).map(|piece| Value::String(piece.to_string())).collect()
+        };
+        Ok(Value::list(pieces))
+    }
+
+    pub fn to_upper(args: Vec<Value>) -> Result<Value, EvalError> {
+        Ok(Value::String(string_arg(&args, 0, "toUpper")?.to_uppercase()))
+    }
+
+    pub fn to_lower(args: Vec<Value>) -> Result<Value, EvalError> {
+        Ok(Value::String(string_arg(&args, 0, "toLower")?.to_lowercase()))
+    }
+
+    /// `trim(s)` drops leading and trailing whitespace.
+    pub fn trim(args: Vec<Value>) -> Result<Value, EvalError> {
+        Ok(Value::String(string_arg(&args, 0, "trim")?.trim().to_string()))
+    }
+
+    /// `replace(s, from, to)` replaces every `from` in `s` with `to`.
+    pub fn replace(args: Vec<Value>) -> Result<Value, EvalError> {
+        let s = string_arg(&args, 0, "replace")?;
+        let from = string_arg(&args, 1, "replace")?;
+        let to = string_arg(&args, 2, "replace")?;
+        if from.is_empty() {
+            return Err(EvalError::TypeError("replace expects a non-empty string to replace".to_string()));
+        }
+        Ok(Value::String(s.replace(from, to)))
+    }
+}
+
+/// Random number natives, drawing from the run's generator. `seedRandom(n)`
+/// or the `--seed` option makes what they return the same on every run.
+mod random {
+    use crate::{error::EvalError, interpreter::Context, random::Random, value::Value};
+
+    fn whole_arg(args: &[Value], index: usize, native: &str) -> Result<i64, EvalError> {
+        let n = match &args[index] {
+            Value::Number(n) => *n,
+            #[cfg(feature = "bignum")]
+            Value::BigNumber(n) => crate::bignum::to_f64(n),
+            other => return Err(EvalError::TypeError(format!("{} expects a number but got {}", native, other.type_name()))),
+        };
+        if n.fract() != 0.0 {
+            return Err(EvalError::TypeError(format!("{} expects a whole number but got {}", native, n)));
+        }
+        Ok(n as i64)
+    }
+
+    /// `random()` returns a number from 0 up to but not including 1.
+    pub fn random(_args: Vec<Value>, context: &mut Context) -> Result<Value, EvalError> {
+        Ok(Value::Number(context.random.next_f64()))
+    }
+
+    /// `randomInt(low, high)` returns a whole number from `low` to `high`, both included.
+    pub fn random_int(args: Vec<Value>, context: &mut Context) -> Result<Value, EvalError> {
+        let low = whole_arg(&args, 0, "randomInt")?;
+        let high = whole_arg(&args, 1, "randomInt")?;
+        if low > high {
+            return Err(EvalError::TypeError(format!("randomInt expects low <= high but got {} and {}", low, high)));
+        }
+        Ok(Value::Number(context.random.int_between(low, high) as f64))
+    }
+
+    /// `seedRandom(n)` restarts the generator from seed `n`.
+    pub fn seed_random(args: Vec<Value>, context: &mut Context) -> Result<Value, EvalError> {
+        context.random = Random::new(whole_arg(&args, 0, "seedRandom")? as u64);
+        Ok(Value::Nil)
+    }
+}
+
+/// Math natives, working on floating-point numbers. Angles are in radians.
+mod math {
+    use crate::{error::EvalError, value::Value};
+
+    fn number_arg(args: &[Value], index: usize, native: &str) -> Result<f64, EvalError> {
+        match &args[index] {
+            Value::Number(n) => Ok(*n),
+            #[cfg(feature = "bignum")]
+            Value::BigNumber(n) => Ok(crate::bignum::to_f64(n)),
+            other => Err(EvalError::TypeError(format!("{} expects a number but got {}", native, other.type_name()))),
+        }
+    }
+
+    /// Applies `f` to the single number argument of `native`.
+    fn unary(args: &[Value], native: &str, f: fn(f64) -> f64) -> Result<Value, EvalError> {
+        Ok(Value::Number(f(number_arg(args, 0, native)?)))
+    }
+
+    /// `sqrt(n)` fails for negative numbers rather than returning NaN.
+    pub fn sqrt(args: Vec<Value>) -> Result<Value, EvalError> {
+        let n = number_arg(&args, 0, "sqrt")?;
+        if n < 0.0 {
+            return Err(EvalError::TypeError(format!("sqrt expects a non-negative number but got {}", n)));
+        }
+        Ok(Value::Number(n.sqrt()))
+    }
+
+    pub fn abs(args: Vec<Value>) -> Result<Value, EvalError> {
+        unary(&args, "abs", f64::abs)
+    }
+
+    pub fn floor(args: Vec<Value>) -> Result<Value, EvalError> {
+        unary(&args, "floor", f64::floor)
+    }
+
+    pub fn ceil(args: Vec<Value>) -> Result<Value, EvalError> {
+        unary(&args, "ceil", f64::ceil)
+    }
+
+    /// `pow(base, exponent)`.
+    pub fn pow(args: Vec<Value>) -> Result<Value, EvalError> {
+        Ok(Value::Number(number_arg(&args, 0, "pow")?.powf(number_arg(&args, 1, "pow")?)))
+    }
+
+    pub fn min(args: Vec<Value>) -> Result<Value, EvalError> {
+        Ok(Value::Number(number_arg(&args, 0, "min")?.min(number_arg(&args, 1, "min")?)))
+    }
+
+    pub fn max(args: Vec<Value>) -> Result<Value, EvalError> {
+        Ok(Value::Number(number_arg(&args, 0, "max")?.max(number_arg(&args, 1, "max")?)))
+    }
+
+    pub fn sin(args: Vec<Value>) -> Result<Value, EvalError> {
+        unary(&args, "sin", f64::sin)
+    }
+
+    pub fn cos(args: Vec<Value>) -> Result<Value, EvalError> {
+        unary(&args, "cos", f64::cos)
+    }
+
+    /// `log(n)` is the natural logarithm, and fails for numbers that are not positive.
+    pub fn log(args: Vec<Value>) -> Result<Value, EvalError> {
+        let n = number_arg(&args, 0, "log")?;
+        if n <= 0.0 {
+            return Err(EvalError::TypeError(format!("log expects a positive number but got {}", n)));
+        }
+        Ok(Value::Number(n.ln()))
+    }
+
+    /// The largest whole number an f64 counts up to one at a time.
+    const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+    /// The most numbers one call to `range` returns, so a huge span fails
+    /// with an error instead of running out of memory.
+    const MAX_RANGE_LENGTH: usize = 1 << 24;
+
+    /// `range(start, end)` returns the list of numbers from `start` up to but
+    /// not including `end`, one apart.
+    pub fn range(args: Vec<Value>) -> Result<Value, EvalError> {
+        let start = number_arg(&args, 0, "range")?;
+        let end = number_arg(&args, 1, "range")?;
+        // Past 2^53 adding one no longer changes an f64, so the numbers
+        // would stop counting up.
+        if ![start, end].iter().all(|bound| bound.is_finite() && bound.abs() <= MAX_SAFE_INTEGER) {
+            return Err(EvalError::TypeError(format!("range expects bounds from -2^53 to 2^53 but got {} and {}", start, end)));
+        }
+        let length = (end - start).ceil().max(0.0);
+        if length > MAX_RANGE_LENGTH as f64 {
+            return Err(EvalError::TypeError(format!("range would hold {} numbers, more than the limit of {}", length, MAX_RANGE_LENGTH)));
+        }
+        let numbers = (0..length as usize).map(|i| Value::Number(start + i as f64)).collect();
+        Ok(Value::list(numbers))
+    }
+}
+
+/// Checksum natives. Each takes strings and returns the lowercase hex digest.
+mod hash {
+    use hmac::{Hmac, Mac};
+    use md5::Md5;
+    use sha2::{Digest, Sha256};
+
+    use crate::{error::EvalError, value::Value};
+
+    fn string_arg<'a>(args: &'a [Value], index: usize, native: &str) -> Result<&'a str, EvalError> {
+        match &args[index] {
+            Value::String(s) => Ok(s),
+            other => Err(EvalError::TypeError(format!("{} expects a string but got {}", native, other.type_name()))),
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> Value {
+        Value::String(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+
+    pub fn sha256(args: Vec<Value>) -> Result<Value, EvalError> {
+        Ok(hex(&Sha256::digest(string_arg(&args, 0, "sha256")?)))
+    }
+
+    pub fn md5(args: Vec<Value>) -> Result<Value, EvalError> {
+        Ok(hex(&Md5::digest(string_arg(&args, 0, "md5")?)))
+    }
+
+    /// `hmac_sha256(key, message)`.
+    pub fn hmac_sha256(args: Vec<Value>) -> Result<Value, EvalError> {
+        let key = string_arg(&args, 0, "hmac_sha256")?;
+        let message = string_arg(&args, 1, "hmac_sha256")?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(message.as_bytes());
+        Ok(hex(&mac.finalize().into_bytes()))
+    }
+}
+
+/// Calendar natives. Timestamps are seconds since the Unix epoch, as returned
+/// by `clock()`, and are interpreted in UTC. Formats use strftime syntax.
+#[cfg(feature = "time")]
+mod time {
+    use chrono::format::{Item, StrftimeItems};
+    use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, Utc};
+
+    use crate::{error::EvalError, value::Value};
+
+    /// The current time as an ISO 8601 string, e.g. `2024-05-01T12:30:00Z`.
+    pub fn now_iso(_args: Vec<Value>) -> Result<Value, EvalError> {
+        Ok(Value::String(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)))
+    }
+
+    /// `date_format(timestamp, format)` renders a timestamp.
+    pub fn date_format(args: Vec<Value>) -> Result<Value, EvalError> {
+        let (timestamp, format) = match (&args[0], &args[1]) {
+            (Value::Number(timestamp), Value::String(format)) => (*timestamp, format),
+            _ => return Err(type_error("date_format expects a number and a format string")),
+        };
+        let items = strftime_items(format)?;
+        let seconds = timestamp.floor();
+        let nanos = ((timestamp - seconds) * 1e9) as u32;
+        let date = DateTime::from_timestamp(seconds as i64, nanos)
+            .ok_or_else(|| type_error("Timestamp out of range"))?;
+        Ok(Value::String(date.format_with_items(items.into_iter()).to_string()))
+    }
+
+    /// `date_parse(text, format)` reads a date or date and time into a timestamp.
+    pub fn date_parse(args: Vec<Value>) -> Result<Value, EvalError> {
+        let (text, format) = match (&args[0], &args[1]) {
+            (Value::String(text), Value::String(format)) => (text, format),
+            _ => return Err(type_error("date_parse expects a string and a format string")),
+        };
+        strftime_items(format)?;
+        let date_time = NaiveDateTime::parse_from_str(text, format)
+            .or_else(|_| NaiveDate::parse_from_str(text, format).map(|date| date.and_time(Default::default())))
+            .map_err(|e| type_error(&format!("Cannot parse '{}' as '{}': {}", text, format, e)))?;
+        Ok(Value::Number(date_time.and_utc().timestamp() as f64))
+    }
+
+    /// Parses a strftime format up front, since chrono reports bad
+    /// specifiers only when the date is finally written out.
+    fn strftime_items(format: &str) -> Result<Vec<Item<'_>>, EvalError> {
+        let items: Vec<Item> = StrftimeItems::new(format).collect();
+        if items.iter().any(|item| matches!(item, Item::Error)) {
+            return Err(type_error(&format!("Invalid date format '{}'", format)));
+        }
+        Ok(items)
+    }
+
+    fn type_error(message: &str) -> EvalError {
+        EvalError::TypeError(message.to_string())
+    }
+}
+
+/// `exec(command, args)` runs `command` with the list of strings `args`, each
+/// passed as one argument, and returns a map of its exit `status`, `stdout`
+/// and `stderr`.
+pub fn exec(args: Vec<Value>) -> Result<Value, EvalError> {
+    let usage = || EvalError::TypeError("exec expects a command string and a list of argument strings".to_string());
+    let (command, arguments) = match (&args[0], &args[1]) {
+        (Value::String(command), Value::List(arguments)) => (command, arguments),
+        _ => return Err(usage()),
+    };
+    let arguments = arguments.borrow();
+    let arguments = arguments
+        .iter()
+        .map(|argument| match argument {
+            Value::String(argument) => Ok(argument.as_str()),
+            _ => Err(usage()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let output = Command::new(command)
+        .args(arguments)
+        .output()
+        .map_err(|e| EvalError::NativeError(format!("Failed to run '{}': {}", command, e)))?;
+
+    let mut result = BTreeMap::new();
+    // A process killed by a signal has no exit code.
+    let status = output.status.code().map_or(-1.0, f64::from);
+    result.insert("status".to_string(), Value::Number(status));
+    result.insert("stdout".to_string(), Value::String(String::from_utf8_lossy(&output.stdout).into_owned()));
+    result.insert("stderr".to_string(), Value::String(String::from_utf8_lossy(&output.stderr).into_owned()));
+    Ok(Value::map(result))
+}
+
+/// Path and directory natives. Paths are plain strings, relative to the
+/// working directory of the interpreter.
+mod fs {
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::path::Path;
+
+    use crate::{error::EvalError, value::Value};
+
+    fn path_arg<'a>(args: &'a [Value], index: usize, native: &str) -> Result<&'a str, EvalError> {
+        match &args[index] {
+            Value::String(path) => Ok(path),
+            other => Err(EvalError::TypeError(format!("{} expects a path string but got {}", native, other.type_name()))),
+        }
+    }
+
+    fn io_error(action: &str, path: &str, e: std::io::Error) -> EvalError {
+        EvalError::NativeError(format!("Failed to {} '{}': {}", action, path, e))
+    }
+
+    /// `path_join(base, path)` appends `path` to `base`, or returns `path` if it is absolute.
+    pub fn path_join(args: Vec<Value>) -> Result<Value, EvalError> {
+        let base = path_arg(&args, 0, "path_join")?;
+        let path = path_arg(&args, 1, "path_join")?;
+        Ok(Value::String(Path::new(base).join(path).to_string_lossy().into_owned()))
+    }
+
+    pub fn path_exists(args: Vec<Value>) -> Result<Value, EvalError> {
+        Ok(Value::Boolean(Path::new(path_arg(&args, 0, "path_exists")?).exists()))
+    }
+
+    /// `list_dir(path)` returns a map from each entry name to `"file"` or `"dir"`.
+    pub fn list_dir(args: Vec<Value>) -> Result<Value, EvalError> {
+        let path = path_arg(&args, 0, "list_dir")?;
+        let mut entries = BTreeMap::new();
+        for entry in fs::read_dir(path).map_err(|e| io_error("list", path, e))? {
+            let entry = entry.map_err(|e| io_error("list", path, e))?;
+            let kind = if entry.path().is_dir() { "dir" } else { "file" };
+            entries.insert(entry.file_name().to_string_lossy().into_owned(), Value::String(kind.to_string()));
+        }
+        Ok(Value::map(entries))
+    }
+
+    /// `mkdir(path)` creates a directory along with any missing parents.
+    pub fn mkdir(args: Vec<Value>) -> Result<Value, EvalError> {
+        let path = path_arg(&args, 0, "mkdir")?;
+        fs::create_dir_all(path).map_err(|e| io_error("create", path, e))?;
+        Ok(Value::Nil)
+    }
+
+    pub fn remove_file(args: Vec<Value>) -> Result<Value, EvalError> {
+        let path = path_arg(&args, 0, "remove_file")?;
+        fs::remove_file(path).map_err(|e| io_error("remove", path, e))?;
+        Ok(Value::Nil)
+    }
+}
+
+/// Blocking TCP sockets for small client/server exercises. Sockets are
+/// referred to by numeric handles returned from `tcp_connect`, `tcp_listen`
+/// and `tcp_accept`.
+#[cfg(all(feature = "net", not(target_arch = "wasm32")))]
+mod net {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    use crate::{error::EvalError, value::Value};
+
+    enum Socket {
+        Stream(TcpStream),
+        Listener(TcpListener),
+    }
+
+    thread_local! {
+        static SOCKETS: RefCell<HashMap<usize, Socket>> = RefCell::new(HashMap::new());
+        static NEXT_HANDLE: RefCell<usize> = const { RefCell::new(1) };
+    }
+
+    fn register(socket: Socket) -> Value {
+        let handle = NEXT_HANDLE.with(|next| {
+            let handle = *next.borrow();
+            *next.borrow_mut() += 1;
+            handle
+        });
+        SOCKETS.with(|sockets| sockets.borrow_mut().insert(handle, socket));
+        Value::Number(handle as f64)
+    }
+
+    fn with_socket<T>(handle: &Value, f: impl FnOnce(&mut Socket) -> Result<T, EvalError>) -> Result<T, EvalError> {
+        let handle = match handle {
+            Value::Number(n) => *n as usize,
+            other => return Err(EvalError::TypeError(format!("Expected a socket handle but got {}", other.type_name()))),
+        };
+        SOCKETS.with(|sockets| match sockets.borrow_mut().get_mut(&handle) {
+            Some(socket) => f(socket),
+            None => Err(EvalError::NativeError(format!("No open socket with handle {}", handle))),
+        })
+    }
+
+    fn address(args: &[Value]) -> Result<String, EvalError> {
+        match (&args[0], &args[1]) {
+            (Value::String(host), Value::Number(port)) => Ok(format!("{}:{}", host, port)),
+            _ => Err(EvalError::TypeError("Expected a host string and a port number".to_string())),
+        }
+    }
+
+    fn io_error(action: &str, e: std::io::Error) -> EvalError {
+        EvalError::NativeError(format!("Failed to {}: {}", action, e))
+    }
+
+    /// `tcp_connect(host, port)` opens a connection.
+    pub fn tcp_connect(args: Vec<Value>) -> Result<Value, EvalError> {
+        let address = address(&args)?;
+        let stream = TcpStream::connect(&address).map_err(|e| io_error(&format!("connect to {}", address), e))?;
+        Ok(register(Socket::Stream(stream)))
+    }
+
+    /// `tcp_listen(host, port)` binds a listening socket.
+    pub fn tcp_listen(args: Vec<Value>) -> Result<Value, EvalError> {
+        let address = address(&args)?;
+        let listener = TcpListener::bind(&address).map_err(|e| io_error(&format!("listen on {}", address), e))?;
+        Ok(register(Socket::Listener(listener)))
+    }
+
+    /// `tcp_accept(listener)` waits for the next client and returns its connection.
+    pub fn tcp_accept(args: Vec<Value>) -> Result<Value, EvalError> {
+        let stream = with_socket(&args[0], |socket| match socket {
+            Socket::Listener(listener) => listener.accept().map(|(stream, _)| stream).map_err(|e| io_error("accept", e)),
+            Socket::Stream(_) => Err(EvalError::TypeError("tcp_accept expects a listening socket".to_string())),
+        })?;
+        Ok(register(Socket::Stream(stream)))
+    }
+
+    /// `tcp_send(connection, text)` writes all of `text` and returns the byte count.
+    pub fn tcp_send(args: Vec<Value>) -> Result<Value, EvalError> {
+        let text = match &args[1] {
+            Value::String(text) => text.clone(),
+            other => return Err(EvalError::TypeError(format!("tcp_send expects a string but got {}", other.type_name()))),
+        };
+        with_socket(&args[0], |socket| match socket {
+            Socket::Stream(stream) => stream.write_all(text.as_bytes()).map_err(|e| io_error("send", e)),
+            Socket::Listener(_) => Err(EvalError::TypeError("tcp_send expects a connection".to_string())),
+        })?;
+        Ok(Value::Number(text.len() as f64))
+    }
+
+    /// The most bytes one `tcp_recv` reads, whatever count it is given.
+    const MAX_RECV_BYTES: usize = 1 << 20;
+
+    /// `tcp_recv(connection, max_bytes)` waits for data and returns it as a
+    /// string, which is empty once the peer has closed the connection. At
+    /// most 1 MiB is read at a time.
+    pub fn tcp_recv(args: Vec<Value>) -> Result<Value, EvalError> {
+        // A read returns what has arrived so far anyway, so a bigger buffer
+        // than this only costs memory.
+        let max_bytes = match &args[1] {
+            Value::Number(n) if *n >= 1.0 => (*n as usize).min(MAX_RECV_BYTES),
+            _ => return Err(EvalError::TypeError("tcp_recv expects a positive byte count".to_string())),
+        };
+        let mut buffer = vec![0; max_bytes];
+        let read = with_socket(&args[0], |socket| match socket {
+            Socket::Stream(stream) => stream.read(&mut buffer).map_err(|e| io_error("receive", e)),
+            Socket::Listener(_) => Err(EvalError::TypeError("tcp_recv expects a connection".to_string())),
+        })?;
+        Ok(Value::String(String::from_utf8_lossy(&buffer[..read]).into_owned()))
+    }
+
+    /// `tcp_close(socket)` closes a connection or listener.
+    pub fn tcp_close(args: Vec<Value>) -> Result<Value, EvalError> {
+        with_socket(&args[0], |_| Ok(()))?;
+        if let Value::Number(handle) = args[0] {
+            SOCKETS.with(|sockets| sockets.borrow_mut().remove(&(handle as usize)));
+        }
+        Ok(Value::Nil)
+    }
 }
 
 pub fn define_native_functions(environment: &mut Environment) {
-    let clock_function = NativeFunction::new("clock", 0, clock);
-    environment.define("clock".to_string(), LiteralExpr::Callable(Rc::new(clock_function)));
+    define_native_functions_where(environment, Capabilities::default(), |_| true);
+}
+
+/// Defines the natives whose name passes `allowed`, including privileged
+/// ones only when `capabilities` grants them.
+pub fn define_native_functions_where(
+    environment: &mut Environment,
+    capabilities: Capabilities,
+    allowed: impl Fn(&str) -> bool,
+) {
+    let privileged = PRIVILEGED_NATIVES
+        .iter()
+        .filter(|(_, _, _, capability)| capabilities.allows(*capability))
+        .map(|(name, arity, function, _)| (name, arity, function));
+    let natives = NATIVES.iter().map(|(name, arity, function)| (name, arity, function));
+    for (name, arity, function) in natives.chain(privileged).filter(|(name, _, _)| allowed(name)) {
+        let native = NativeFunction::new(name, *arity, *function);
+        environment.define(name.to_string(), Value::Callable(Rc::new(native)));
+    }
+    for (name, arity, function) in CONTEXT_NATIVES.iter().filter(|(name, _, _)| allowed(name)) {
+        let native = NativeFunction::with_context(name, *arity, *function);
+        environment.define(name.to_string(), Value::Callable(Rc::new(native)));
+    }
 }
@@ -1,52 +1,202 @@
-use crate::token::{Token, TokenType};
-use crate::expr::{BinaryExpr, CallExpr, Expr, LiteralExpr, LogicalExpr, UnaryExpr};
+use std::collections::VecDeque;
+
+use crate::token::{Span, Token, TokenType};
+use crate::expr::{BinaryExpr, BlockExpr, CallExpr, Expr, ExprId, GetExpr, IfExpr, IndexExpr, IndexSetExpr, LambdaExpr, LiteralExpr, LogicalExpr, MapExpr, SetExpr, UnaryExpr};
 use crate::error::ParserError;
-use crate::stmt::Stmt;
+use crate::stmt::{ForInStmt, ForStmt, Signature, Stmt};
+
+/// Number of errors after which the parser gives up by default.
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+
+/// How deeply statements and expressions may nest before parsing stops,
+/// so pathological input cannot overflow the stack of the parser or of
+/// the passes that walk the tree afterwards.
+pub const MAX_NESTING: usize = 128;
+
+/// Tokens of lookahead the parser keeps past the current one; `at_lambda`
+/// reads further ahead when it needs to.
+const LOOKAHEAD: usize = 2;
 
 // The Parser struct, responsible for converting a series of tokens into an AST.
 pub struct Parser {
-    tokens: Vec<Token>,
+    /// Tokens not read yet, pulled one at a time as parsing goes on.
+    source: Box<dyn Iterator<Item = Token>>,
+    /// The previous token, once there is one, the current token and the
+    /// lookahead read so far. Ends with EOF once the source runs out.
+    tokens: VecDeque<Token>,
     current: usize,
     errors: Vec<ParserError>, // Collects all parsing errors
+    max_errors: usize,
+    panic_mode: bool, // An error was reported and the parser has not synchronized since
+    /// Where the lexer rejected characters that the statement being parsed
+    /// may reach. The parser never sees them, so it may stumble there.
+    bad_characters: Vec<usize>,
+    block_depth: usize, // Number of blocks currently open
+    nesting: usize, // Statements and expressions currently being parsed
+    loop_depth: usize, // Loops around the current statement in this function
+    function_depth: usize, // Functions around the current statement
+    implicit_final_semicolon: bool, // REPL grammar: end of input may stand in for ';'
 }
 
 impl Parser {
-    /// Create a new Parser instance.
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser {
-            tokens,
+    /// Create a new Parser instance reading `tokens`, such as a
+    /// [`Lexer`](crate::lexer::Lexer), which is only asked for tokens as the
+    /// parser gets to them. An EOF token is added if `tokens` does not end
+    /// with one.
+    pub fn new<I>(tokens: I) -> Self
+    where
+        I: IntoIterator<Item = Token>,
+        I::IntoIter: 'static,
+    {
+        let mut parser = Parser {
+            source: Box::new(tokens.into_iter()),
+            tokens: VecDeque::new(),
             current: 0,
             errors: Vec::new(),
-        }
+            max_errors: DEFAULT_MAX_ERRORS,
+            panic_mode: false,
+            bad_characters: Vec::new(),
+            block_depth: 0,
+            nesting: 0,
+            loop_depth: 0,
+            function_depth: 0,
+            implicit_final_semicolon: false,
+        };
+        parser.fill(LOOKAHEAD);
+        parser
+    }
+
+    /// Stop parsing once `max_errors` errors have been reported.
+    pub fn with_max_errors(mut self, max_errors: usize) -> Self {
+        self.max_errors = max_errors.max(1);
+        self
+    }
+
+    /// Treat the end of input as a semicolon, so `print 1 + 2` is accepted
+    /// as a complete program. Used by the REPL and `interpret -e`.
+    pub fn with_implicit_final_semicolon(mut self, enabled: bool) -> Self {
+        self.implicit_final_semicolon = enabled;
+        self
     }
 
     /// Parse the input tokens and produce an AST. Returns a vector of statements and any errors found.
     pub fn parse(&mut self) -> (Vec<Stmt>, Vec<ParserError>) {
         let mut statements = Vec::new();
         while !self.is_at_end() {
+            if self.errors.len() >= self.max_errors {
+                let line = self.peek().line;
+                self.errors.push(ParserError::new(
+                    line,
+                    format!("Too many errors, stopping after {}.", self.max_errors),
+                ));
+                break;
+            }
             if let Some(stmt) = self.declaration() {
                 statements.push(stmt);
             }
         }
+        self.errors.sort_by_key(|error| (error.line, error.column));
         (statements, self.errors.clone())
     }
 
+    /// Reads tokens from the source until `ahead` tokens past the current
+    /// one are buffered or it has run out. Error tokens from the lexer are
+    /// reported and dropped, so the rest of the program is still parsed and
+    /// checked.
+    fn fill(&mut self, ahead: usize) {
+        while self.tokens.len() <= self.current + ahead {
+            if self.tokens.back().is_some_and(|token| token.token_type == TokenType::Eof) {
+                return;
+            }
+            match self.source.next() {
+                Some(token) => match &token.token_type {
+                    TokenType::Error(message) => {
+                        let error = ParserError::at(&token, message.clone());
+                        self.report_in_place(error);
+                        self.bad_characters.push(token.span.start);
+                    }
+                    _ => self.tokens.push_back(token),
+                },
+                None => {
+                    let eof = self.end_of_input();
+                    self.tokens.push_back(eof);
+                }
+            }
+        }
+    }
+
+    /// An EOF token just past the last token read.
+    fn end_of_input(&self) -> Token {
+        let (line, column, end) = self.tokens.back().map_or((1, 1, 0), |token| {
+            (token.line, token.column + token.lexeme.chars().count(), token.span.end)
+        });
+        Token {
+            token_type: TokenType::Eof,
+            lexeme: String::new(),
+            literal: None,
+            line,
+            column,
+            span: Span::new(end, end),
+            leading_trivia: Vec::new(),
+        }
+    }
+
+    /// The buffered token at `index`, or the last one buffered past the end.
+    fn token_at(&self, index: usize) -> &Token {
+        &self.tokens[index.min(self.tokens.len() - 1)]
+    }
+
     /// Parse a declaration, catching errors and continuing to parse.
     fn declaration(&mut self) -> Option<Stmt> {
-        match self.try_declaration() {
+        let stmt = match self.try_declaration() {
             Ok(stmt) => Some(stmt),
             Err(err) => {
-                self.errors.push(err);
+                self.report(err);
                 self.synchronize();
                 None
             }
+        };
+        self.forget_bad_characters_behind();
+        stmt
+    }
+
+    /// Forgets the rejected characters before the current token once the
+    /// statement holding them is over.
+    fn forget_bad_characters_behind(&mut self) {
+        let position = self.peek().span.start;
+        self.bad_characters.retain(|&at| at >= position);
+    }
+
+    /// Record an error the parser could not get past. Until the next
+    /// [`Parser::synchronize`], further errors are almost always a cascade
+    /// from this one, so they are dropped.
+    ///
+    /// An error past a character the lexer rejected is taken to be caused
+    /// by that character, already reported, and dropped too.
+    fn report(&mut self, error: ParserError) {
+        let position = self.peek().span.start;
+        if self.panic_mode || self.bad_characters.iter().any(|&at| at < position) {
+            self.panic_mode = true;
+            return;
         }
+        self.panic_mode = true;
+        self.errors.push(error);
+    }
+
+    /// Record an error that leaves the parser where it was, such as a bad
+    /// token or a `break` outside a loop, so parsing goes on as if the code
+    /// were right.
+    fn report_in_place(&mut self, error: ParserError) {
+        self.errors.push(error);
     }
 
     fn try_declaration(&mut self) -> Result<Stmt, ParserError> {
         if self.match_token(&[TokenType::Var]) {
             self.var_declaration()
-        } else if self.match_token(&[TokenType::Fun]) {
+        } else if self.match_token(&[TokenType::Const]) {
+            self.const_declaration()
+        } else if self.check(TokenType::Fun) && self.check_next(TokenType::Identifier) {
+            self.advance();
             self.function_declaration()
         } else {
             self.statement()
@@ -54,8 +204,32 @@ impl Parser {
     }
     
 
+    /// Runs `parse` one nesting level deeper, failing once [`MAX_NESTING`] is reached.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, ParserError>) -> Result<T, ParserError> {
+        let nesting = self.nesting;
+        self.deepen()?;
+        let result = parse(self);
+        self.nesting = nesting;
+        result
+    }
+
+    /// Counts one more level of nesting until the enclosing [`Parser::nested`]
+    /// call returns. Loops building left-nested trees, like `a + b + c`, call
+    /// this for every operator.
+    fn deepen(&mut self) -> Result<(), ParserError> {
+        if self.nesting >= MAX_NESTING {
+            return Err(self.error(self.peek(), "Too much nesting."));
+        }
+        self.nesting += 1;
+        Ok(())
+    }
+
     /// Parse a single statement.
     fn statement(&mut self) -> Result<Stmt, ParserError> {
+        self.nested(Self::nested_statement)
+    }
+
+    fn nested_statement(&mut self) -> Result<Stmt, ParserError> {
         if self.match_token(&[TokenType::For]) {
             self.for_statement()
         } else if self.match_token(&[TokenType::If]) {
@@ -66,18 +240,34 @@ impl Parser {
             self.return_statement()
         } else if self.match_token(&[TokenType::Fun]) {
             self.function_declaration()
+        } else if self.match_token(&[TokenType::Class]) {
+            self.class_declaration()
         } else if self.match_token(&[TokenType::While]) { 
             self.while_statement()
         } else if self.match_token(&[TokenType::LeftBrace]) {
             self.block()
         } else if self.match_token(&[TokenType::Var]) {
             self.var_declaration()
+        } else if self.match_token(&[TokenType::Const]) {
+            self.const_declaration()
+        } else if self.check(TokenType::Identifier) && self.check_next(TokenType::Comma) {
+            self.parallel_assignment()
         } else if self.match_token(&[TokenType::Break]) {
-            self.consume(TokenType::Semicolon, "Expect ';' after 'break'.")?;
-            Ok(Stmt::Break)
+            let start = self.previous().span;
+            if self.loop_depth == 0 {
+                let error = self.error(self.previous(), "Can't use 'break' outside of a loop.");
+                self.report_in_place(error);
+            }
+            self.consume_semicolon("Expect ';' after 'break'.")?;
+            Ok(Stmt::Break(self.span_from(start)))
         } else if self.match_token(&[TokenType::Continue]) {
-            self.consume(TokenType::Semicolon, "Expect ';' after 'continue'.")?;
-            Ok(Stmt::Continue)
+            let start = self.previous().span;
+            if self.loop_depth == 0 {
+                let error = self.error(self.previous(), "Can't use 'continue' outside of a loop.");
+                self.report_in_place(error);
+            }
+            self.consume_semicolon("Expect ';' after 'continue'.")?;
+            Ok(Stmt::Continue(self.span_from(start)))
         } else {
             self.expression_statement()
         }
@@ -85,7 +275,12 @@ impl Parser {
 
     /// Parse a for statement.
     fn for_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+
+        if self.check(TokenType::Var) && self.check_next(TokenType::Identifier) && self.peek_after_next().token_type == TokenType::In {
+            return self.for_in_statement(keyword);
+        }
     
         let initializer = if self.match_token(&[TokenType::Semicolon]) {
             None
@@ -96,9 +291,9 @@ impl Parser {
         };
     
         let condition = if !self.check(TokenType::Semicolon) {
-            self.expression()?
+            Some(self.expression()?)
         } else {
-            Expr::Literal(LiteralExpr::Boolean(true))
+            None
         };
         self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
     
@@ -109,26 +304,27 @@ impl Parser {
         };
         self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
     
-        let mut body = self.statement()?;
+        let body = self.loop_body()?;
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::For(Box::new(ForStmt { keyword, initializer, condition, increment, body, span })))
+    }    
 
-        if let Some(increment) = increment {
-            body = Stmt::Block(vec![
-                body,
-                Stmt::Expression(increment.clone()),
-            ]);
-        }
+    /// Parse the rest of a `for (var name in iterable)` loop, from `var`.
+    fn for_in_statement(&mut self, keyword: Token) -> Result<Stmt, ParserError> {
+        self.advance();
+        let name = self.advance().clone();
+        self.advance();
+        let iterable = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after for-in iterable.")?;
 
-        body = Stmt::While(condition, Box::new(body));
-    
-        if let Some(initializer) = initializer {
-            body = Stmt::Block(vec![initializer, body]);
-        }
-    
-        Ok(body)
-    }    
+        let body = self.loop_body()?;
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::ForIn(Box::new(ForInStmt { keyword, name, iterable, body, span })))
+    }
 
     /// Parse an if statement.
     fn if_statement(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.previous().span;
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
@@ -140,93 +336,183 @@ impl Parser {
             None
         };
     
-        Ok(Stmt::If(condition, then_branch, else_branch))
+        Ok(Stmt::If(condition, then_branch, else_branch, self.span_from(start)))
     }
 
     /// Parse a print statement.
     fn print_statement(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.previous().span;
         let value = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::Print(value))
+        self.consume_semicolon("Expect ';' after value.")?;
+        Ok(Stmt::Print(value, self.span_from(start)))
     }
 
     fn return_statement(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.previous().span;
+        if self.function_depth == 0 {
+            let error = self.error(self.previous(), "Can't return from top-level code.");
+            self.report_in_place(error);
+        }
         let value = if !self.check(TokenType::Semicolon) {
             Some(self.expression()?)
         } else {
             None
         };
     
-        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
-        Ok(Stmt::Return(value))
+        self.consume_semicolon("Expect ';' after return value.")?;
+        Ok(Stmt::Return(value, self.span_from(start)))
     }    
 
     fn function_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.previous().span;
+        self.function("function", start)
+    }
+
+    /// Parse a class declaration after its `class` keyword: the name, the
+    /// superclass after `<` if there is one, and a body of method
+    /// declarations, written like functions without `fun`.
+    fn class_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.previous().span;
+        let name = self.consume_identifier("class name")?.lexeme.clone();
+        let superclass = if self.match_token(&[TokenType::Less]) {
+            Some(self.consume_identifier("superclass name")?.clone())
+        } else {
+            None
+        };
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            let method_start = self.peek().span;
+            methods.push(self.function("method", method_start)?);
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        Ok(Stmt::Class(name, superclass, methods, self.span_from(start)))
+    }
+
+    /// Parse a function or method from its name onwards. `kind` names it in
+    /// error messages and `start` is where its declaration began.
+    fn function(&mut self, kind: &str, start: Span) -> Result<Stmt, ParserError> {
         // Expect function name
-        let name_token = self.consume(TokenType::Identifier, "Expect function name.")?;
+        let name_token = self.consume_identifier(&format!("{} name", kind))?;
         let name = name_token.lexeme.clone();
     
         // Parse the parameter list
-        self.consume(TokenType::LeftParen, "Expect '(' after function name.")?;
-        let mut parameters = Vec::new();
+        self.consume(TokenType::LeftParen, &format!("Expect '(' after {} name.", kind))?;
+        let (parameters, mut signature) = self.parameters()?;
+        signature.returns = self.type_annotation()?;
+    
+        // Parse the function body
+        self.consume(TokenType::LeftBrace, &format!("Expect '{{' before {} body.", kind))?;
+        let body = self.function_body(|parser| parser.block())?;
     
+        // Return the function statement
+        Ok(Stmt::Function(name, parameters, match body {
+            Stmt::Block(statements, _) => statements,
+            _ => vec![body],  // Should be a block, but safeguard just in case
+        }, signature, self.span_from(start)))
+    }
+
+    /// Parse a parameter list after its opening parenthesis, up to and
+    /// including the closing one. Returns the names and a signature with
+    /// each parameter's type annotation, but not yet the return type.
+    fn parameters(&mut self) -> Result<(Vec<String>, Signature), ParserError> {
+        let mut parameters = Vec::new();
+        let mut types = Vec::new();
+        let mut rest = false;
+
         if !self.check(TokenType::RightParen) {
             loop {
                 if parameters.len() >= 255 {
                     return Err(self.error(self.peek(), "Cannot have more than 255 parameters."));
                 }
+                if rest {
+                    return Err(self.error(self.peek(), "Rest parameter must be last."));
+                }
+                rest = self.match_token(&[TokenType::DotDotDot]);
     
-                let param = self.consume(TokenType::Identifier, "Expect parameter name.")?;
-                parameters.push(param.lexeme.clone());
+                match self.consume_identifier("parameter name") {
+                    // A rest parameter is always a list, so it takes no annotation.
+                    Ok(param) if rest => {
+                        parameters.push(param.lexeme.clone());
+                        types.push(None);
+                    }
+                    Ok(param) => {
+                        parameters.push(param.lexeme.clone());
+                        types.push(self.type_annotation()?);
+                    }
+                    Err(err) => {
+                        self.report(err);
+                        self.skip_list_item();
+                    }
+                }
     
-                if !self.match_token(&[TokenType::Comma]) {
+                // A trailing comma before the closing parenthesis is allowed.
+                if !self.match_token(&[TokenType::Comma]) || self.check(TokenType::RightParen) {
                     break;
                 }
             }
         }
     
         self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
-    
-        // Parse the function body
-        self.consume(TokenType::LeftBrace, "Expect '{' before function body.")?;
-        let body = self.block()?; // Parses the block of statements
-    
-        // Return the function statement
-        Ok(Stmt::Function(name, parameters, match body {
-            Stmt::Block(statements) => statements,
-            _ => vec![body],  // Should be a block, but safeguard just in case
-        }))
+        Ok((parameters, Signature { params: types, returns: None, rest }))
     }   
 
     /// Parse a while statement.
     fn while_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
         self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
     
-        let body = Box::new(self.statement()?);
+        let body = Box::new(self.loop_body()?);
     
-        Ok(Stmt::While(condition, body))
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::While(keyword, condition, body, span))
+    }
+
+    /// Parse the body of a loop, where `break` and `continue` are allowed.
+    fn loop_body(&mut self) -> Result<Stmt, ParserError> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
+
+    /// Parse the body of a function with `parse`. `return` is allowed there,
+    /// and loops outside the function cannot be left from inside it.
+    fn function_body<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, ParserError>) -> Result<T, ParserError> {
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        self.function_depth += 1;
+        let body = parse(self);
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+        body
     }
 
     /// Parse a block of statements.
     fn block(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.previous().span;
         let mut statements = Vec::new();
     
+        self.block_depth += 1;
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
             if let Some(stmt) = self.declaration() {
                 statements.push(stmt);
             }
         }
+        self.block_depth -= 1;
     
         self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
-        return Ok(Stmt::Block(statements));
+        Ok(Stmt::Block(statements, self.span_from(start)))
     }
 
     /// Parse a variable declaration.
     fn var_declaration(&mut self) -> Result<Stmt, ParserError> {
-        let name_token = self.consume(TokenType::Identifier, "Expect variable name.")?;
+        let start = self.previous().span;
+        let name_token = self.consume_identifier("variable name")?;
         let name = name_token.lexeme.clone();
+        let annotation = self.type_annotation()?;
     
         let initializer = if self.match_token(&[TokenType::Equal]) {
             Some(self.expression()?)
@@ -234,17 +520,67 @@ impl Parser {
             None
         };
     
-        self.consume(TokenType::Semicolon, "Expect ';' after variable declaration.")?;
+        self.consume_semicolon("Expect ';' after variable declaration.")?;
     
-        Ok(Stmt::Var(name, initializer))
+        Ok(Stmt::Var(name, annotation, initializer, self.span_from(start)))
+    }
+
+    /// Parse a constant declaration, whose value is required.
+    fn const_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.previous().span;
+        let name = self.consume_identifier("constant name")?.lexeme.clone();
+        let annotation = self.type_annotation()?;
+        self.consume(TokenType::Equal, "Expect '=' after constant name.")?;
+        let value = self.expression()?;
+        self.consume_semicolon("Expect ';' after constant declaration.")?;
+        Ok(Stmt::Const(name, annotation, value, self.span_from(start)))
     }
     
 
+    /// Parse the `: Type` that may follow a declared name.
+    fn type_annotation(&mut self) -> Result<Option<Token>, ParserError> {
+        if self.match_token(&[TokenType::Colon]) {
+            Ok(Some(self.consume_identifier("type name")?.clone()))
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Parse an expression statement.
     fn expression_statement(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.peek().span;
         let expr = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
-        Ok(Stmt::Expression(expr))
+        self.consume_semicolon("Expect ';' after expression.")?;
+        Ok(Stmt::Expression(expr, self.span_from(start)))
+    }
+
+    /// Parse `a, b = x, y;`, which needs as many values as targets.
+    fn parallel_assignment(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.peek().span;
+        let mut names = vec![(self.advance().clone(), ExprId::fresh())];
+        while self.match_token(&[TokenType::Comma]) {
+            names.push((self.consume_identifier("variable name")?.clone(), ExprId::fresh()));
+        }
+        let equals = self.consume(TokenType::Equal, "Expect '=' after assignment targets.")?.clone();
+
+        let mut values = vec![self.expression()?];
+        while self.match_token(&[TokenType::Comma]) {
+            values.push(self.expression()?);
+        }
+        if values.len() != names.len() {
+            return Err(self.error(
+                &equals,
+                &format!("Expected {} values to assign but got {}.", names.len(), values.len()),
+            ));
+        }
+
+        self.consume_semicolon("Expect ';' after assignment.")?;
+        Ok(Stmt::ParallelAssign(names, values, self.span_from(start)))
+    }
+
+    /// The span from `start` to the end of the last consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous().span)
     }
 
     /// Check if the parser has reached the end of the input tokens.
@@ -254,15 +590,37 @@ impl Parser {
 
     /// Get the current token without consuming it.
     fn peek(&self) -> &Token {
-        &self.tokens[self.current]
+        self.token_at(self.current)
+    }
+
+    /// Get the token after the current one without consuming anything. Gives
+    /// the grammar a second token of lookahead where one is not enough.
+    fn peek_next(&self) -> &Token {
+        self.token_at(self.current + 1)
+    }
+
+    /// The token two past the current one.
+    fn peek_after_next(&self) -> &Token {
+        self.token_at(self.current + 2)
+    }
+
+    /// Check if the token after the current one matches the given type.
+    fn check_next(&self, token_type: TokenType) -> bool {
+        self.peek_next().token_type == token_type
     }
 
     /// Advance the current position and return the previous token.
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1;
+            // Only the token before the current one is looked back at.
+            if self.current > 1 {
+                self.tokens.pop_front();
+                self.current -= 1;
+            }
+            self.fill(LOOKAHEAD);
         }
-        &self.tokens[self.current - 1]
+        self.previous()
     }
 
     /// Check if the current token matches any of the given types and consume it if it does.
@@ -286,20 +644,30 @@ impl Parser {
 
     /// Handle errors when an unexpected token is encountered.
     fn error(&self, token: &Token, message: &str) -> ParserError {
-        ParserError::new(token.line, format!("Error at '{}': {}", token.lexeme, message))
+        ParserError::at(token, format!("Error at '{}': {}", token.lexeme, message))
     }
 
     /// Helper function to synchronize the parser after an error.
     fn synchronize(&mut self) {
+        self.panic_mode = false;
+        // Leave a closing brace to the enclosing block instead of swallowing it,
+        // so one bad statement does not take the rest of the block down with it.
+        if self.block_depth > 0 && self.check(TokenType::RightBrace) {
+            return;
+        }
         self.advance();
         while !self.is_at_end() {
             if self.previous().token_type == TokenType::Semicolon {
                 return;
             }
+            if self.block_depth > 0 && self.check(TokenType::RightBrace) {
+                return;
+            }
             match self.peek().token_type {
                 TokenType::Class
                 | TokenType::Fun
                 | TokenType::Var
+                | TokenType::Const
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
@@ -311,6 +679,23 @@ impl Parser {
         }
     }
 
+    /// Skip the rest of a malformed argument or parameter, stopping before the
+    /// `,` or `)` that ends it so the rest of the list can still be parsed.
+    fn skip_list_item(&mut self) {
+        let mut depth = 0;
+        while !self.is_at_end() {
+            match self.peek().token_type {
+                TokenType::LeftParen => depth += 1,
+                TokenType::RightParen if depth == 0 => return,
+                TokenType::RightParen => depth -= 1,
+                TokenType::Comma if depth == 0 => return,
+                TokenType::Semicolon | TokenType::LeftBrace | TokenType::RightBrace => return,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
     /// Get the previous token.
     fn previous(&self) -> &Token {
         &self.tokens[self.current - 1]
@@ -318,19 +703,28 @@ impl Parser {
 
     /// Parse an expression. Currently handles equality expressions.
     fn expression(&mut self) -> Result<Expr, ParserError> {
-        self.assignment()
+        self.nested(Self::assignment)
     }
 
     /// Parse assignment expressions.
     fn assignment(&mut self) -> Result<Expr, ParserError> {
-        let expr = self.or()?;
+        let expr = self.nil_coalesce()?;
 
         if self.match_token(&[TokenType::Equal]) {
             let equals = self.previous().clone();
-            let value = self.assignment()?;
+            let value = self.expression()?;
 
-            if let Expr::Variable(name) = expr {
-                return Ok(Expr::Assign(name, Box::new(value)));
+            match expr {
+                Expr::Variable(name, _) => return Ok(Expr::Assign(name, Box::new(value), ExprId::fresh())),
+                Expr::Get(get) if !get.optional => {
+                    let GetExpr { object, name, .. } = *get;
+                    return Ok(Expr::Set(Box::new(SetExpr { object, name, value })));
+                }
+                Expr::Index(index) => {
+                    let IndexExpr { object, index, bracket } = *index;
+                    return Ok(Expr::IndexSet(Box::new(IndexSetExpr { object, index, bracket, value })));
+                }
+                _ => {}
             }
 
             return Err(self.error(&equals, "Invalid assignment target."));
@@ -339,11 +733,26 @@ impl Parser {
         Ok(expr)
     }
 
+    /// Parse nil-coalescing expressions, `a ?? b`.
+    fn nil_coalesce(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.or()?;
+
+        while self.match_token(&[TokenType::QuestionQuestion]) {
+            self.deepen()?;
+            let operator = self.previous().clone();
+            let right = self.or()?;
+            expr = Expr::Logical(Box::new(LogicalExpr { left: expr, operator, right }));
+        }
+
+        Ok(expr)
+    }
+
     /// Parse logical OR expressions.
     fn or(&mut self) -> Result<Expr, ParserError> {
         let mut expr = self.and()?;
 
         while self.match_token(&[TokenType::Or]) {
+            self.deepen()?;
             let operator = self.previous().clone();
             let right = self.and()?;
             expr = Expr::Logical(Box::new(LogicalExpr { left: expr, operator, right }));
@@ -357,6 +766,7 @@ impl Parser {
         let mut expr = self.equality()?;
 
         while self.match_token(&[TokenType::And]) {
+            self.deepen()?;
             let operator = self.previous().clone();
             let right = self.equality()?;
             expr = Expr::Logical(Box::new(LogicalExpr { left: expr, operator, right }));
@@ -370,6 +780,7 @@ impl Parser {
         let mut expr = self.comparison()?;
 
         while self.match_token(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+            self.deepen()?;
             let operator = self.previous().clone();
             let right = self.comparison()?;
             expr = Expr::Binary(Box::new(BinaryExpr { left: expr, operator, right }));
@@ -380,7 +791,7 @@ impl Parser {
 
     /// Parse comparison expressions, handling `<`, `<=`, `>`, and `>=` operators.
     fn comparison(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.term()?;
+        let mut expr = self.bit_or()?;
 
         while self.match_token(&[
             TokenType::Greater,
@@ -388,6 +799,63 @@ impl Parser {
             TokenType::Less,
             TokenType::LessEqual,
         ]) {
+            self.deepen()?;
+            let operator = self.previous().clone();
+            let right = self.bit_or()?;
+            expr = Expr::Binary(Box::new(BinaryExpr { left: expr, operator, right }));
+        }
+
+        Ok(expr)
+    }
+
+    /// Parse bitwise OR expressions, handling the `|` operator.
+    fn bit_or(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.bit_xor()?;
+
+        while self.match_token(&[TokenType::Pipe]) {
+            self.deepen()?;
+            let operator = self.previous().clone();
+            let right = self.bit_xor()?;
+            expr = Expr::Binary(Box::new(BinaryExpr { left: expr, operator, right }));
+        }
+
+        Ok(expr)
+    }
+
+    /// Parse bitwise XOR expressions, handling the `^` operator.
+    fn bit_xor(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.bit_and()?;
+
+        while self.match_token(&[TokenType::Caret]) {
+            self.deepen()?;
+            let operator = self.previous().clone();
+            let right = self.bit_and()?;
+            expr = Expr::Binary(Box::new(BinaryExpr { left: expr, operator, right }));
+        }
+
+        Ok(expr)
+    }
+
+    /// Parse bitwise AND expressions, handling the `&` operator.
+    fn bit_and(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.shift()?;
+
+        while self.match_token(&[TokenType::Ampersand]) {
+            self.deepen()?;
+            let operator = self.previous().clone();
+            let right = self.shift()?;
+            expr = Expr::Binary(Box::new(BinaryExpr { left: expr, operator, right }));
+        }
+
+        Ok(expr)
+    }
+
+    /// Parse shifts, handling `<<` and `>>` operators.
+    fn shift(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.term()?;
+
+        while self.match_token(&[TokenType::LessLess, TokenType::GreaterGreater]) {
+            self.deepen()?;
             let operator = self.previous().clone();
             let right = self.term()?;
             expr = Expr::Binary(Box::new(BinaryExpr { left: expr, operator, right }));
@@ -401,6 +869,7 @@ impl Parser {
         let mut expr = self.factor()?;
 
         while self.match_token(&[TokenType::Plus, TokenType::Minus]) {
+            self.deepen()?;
             let operator = self.previous().clone();
             let right = self.factor()?;
             expr = Expr::Binary(Box::new(BinaryExpr { left: expr, operator, right }));
@@ -414,6 +883,7 @@ impl Parser {
         let mut expr = self.unary()?;
 
         while self.match_token(&[TokenType::Star, TokenType::Slash, TokenType::Percent]) {
+            self.deepen()?;
             let operator = self.previous().clone();
             let right = self.unary()?;
             expr = Expr::Binary(Box::new(BinaryExpr { left: expr, operator, right }));
@@ -422,11 +892,11 @@ impl Parser {
         Ok(expr)
     }
 
-    /// Parse unary expressions, handling `!` and `-` operators.
+    /// Parse unary expressions, handling `!`, `-` and `~` operators.
     fn unary(&mut self) -> Result<Expr, ParserError> {
-        if self.match_token(&[TokenType::Bang, TokenType::Minus]) {
+        if self.match_token(&[TokenType::Bang, TokenType::Minus, TokenType::Tilde]) {
             let operator = self.previous().clone();
-            let right = self.unary()?;
+            let right = self.nested(Self::unary)?;
             return Ok(Expr::Unary(Box::new(UnaryExpr { operator, right })));
         }
 
@@ -439,7 +909,18 @@ impl Parser {
 
         loop {
             if self.match_token(&[TokenType::LeftParen]) {
+                self.deepen()?;
                 expr = self.finish_call(expr)?;
+            } else if self.match_token(&[TokenType::Dot, TokenType::QuestionDot]) {
+                self.deepen()?;
+                let optional = self.previous().token_type == TokenType::QuestionDot;
+                let name = self.consume(TokenType::Identifier, "Expect property name after '.'.")?.clone();
+                expr = Expr::Get(Box::new(GetExpr { object: expr, name, optional }));
+            } else if self.match_token(&[TokenType::LeftBracket]) {
+                self.deepen()?;
+                let index = self.expression()?;
+                let bracket = self.consume(TokenType::RightBracket, "Expect ']' after index.")?.clone();
+                expr = Expr::Index(Box::new(IndexExpr { object: expr, index, bracket }));
             } else {
                 break;
             }
@@ -451,14 +932,26 @@ impl Parser {
     /// Finish parsing a function call.
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParserError> {
         let mut arguments = Vec::new();
+        let mut spread = false;
 
         if !self.check(TokenType::RightParen) {
             loop {
                 if arguments.len() >= 255 {
                     return Err(self.error(self.peek(), "Cannot have more than 255 arguments."));
                 }
-                arguments.push(self.expression()?);
-                if !self.match_token(&[TokenType::Comma]) {
+                if spread {
+                    return Err(self.error(self.peek(), "Spread argument must be last."));
+                }
+                spread = self.match_token(&[TokenType::DotDotDot]);
+                match self.expression() {
+                    Ok(argument) => arguments.push(argument),
+                    Err(err) => {
+                        self.report(err);
+                        self.skip_list_item();
+                    }
+                }
+                // A trailing comma before the closing parenthesis is allowed.
+                if !self.match_token(&[TokenType::Comma]) || self.check(TokenType::RightParen) {
                     break;
                 }
             }
@@ -466,46 +959,230 @@ impl Parser {
 
         let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?.clone();
 
-        Ok(Expr::Call(Box::new(CallExpr { callee, paren, arguments })))
+        Ok(Expr::Call(Box::new(CallExpr { callee, paren, arguments, spread })))
     }
 
     /// Parse primary expressions, handling literals, grouping, etc.
     fn primary(&mut self) -> Result<Expr, ParserError> {
         if self.match_token(&[TokenType::False]) {
-            return Ok(Expr::Literal(LiteralExpr::Boolean(false)));
+            return Ok(Expr::Constant(LiteralExpr::Boolean(false), self.previous().span));
         }
         if self.match_token(&[TokenType::True]) {
-            return Ok(Expr::Literal(LiteralExpr::Boolean(true)));
+            return Ok(Expr::Constant(LiteralExpr::Boolean(true), self.previous().span));
         }
         if self.match_token(&[TokenType::Nil]) {
-            return Ok(Expr::Literal(LiteralExpr::Nil));
+            return Ok(Expr::Constant(LiteralExpr::Nil, self.previous().span));
         }
 
-        if self.match_token(&[TokenType::Number]) {
-            return Ok(Expr::Literal(LiteralExpr::Number(
-                self.previous().lexeme.parse().unwrap(),
-            )));
+        if self.match_token(&[TokenType::Number, TokenType::String]) {
+            let token = self.previous();
+            return match &token.literal {
+                Some(literal) => Ok(Expr::Constant(literal.into(), token.span)),
+                None => Err(self.error(token, "Invalid literal.")),
+            };
         }
 
-        if self.match_token(&[TokenType::String]) {
-            return Ok(Expr::Literal(LiteralExpr::String(
-                self.previous().lexeme.clone(),
-            )));
+        if self.match_token(&[TokenType::This]) {
+            return Ok(Expr::This(self.previous().clone()));
+        }
+
+        if self.match_token(&[TokenType::Super]) {
+            let keyword = self.previous().clone();
+            self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+            let method = self.consume_identifier("superclass method name")?.clone();
+            return Ok(Expr::Super(keyword, method));
+        }
+
+        if self.at_lambda() {
+            return self.lambda();
         }
 
         if self.match_token(&[TokenType::LeftParen]) {
+            let start = self.previous().span;
             let expr = self.expression()?;
             self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
-            return Ok(Expr::Grouping(Box::new(expr)));
+            return Ok(Expr::Grouping(Box::new(expr), self.span_from(start)));
         }
 
         if self.match_token(&[TokenType::Identifier]) {
-            return Ok(Expr::Variable(self.previous().clone()));
+            return Ok(Expr::Variable(self.previous().clone(), ExprId::fresh()));
+        }
+
+        if self.match_token(&[TokenType::If]) {
+            return self.if_expression();
+        }
+
+        if self.match_token(&[TokenType::LeftBrace]) {
+            // `{}` and a string followed by ':' begin a map rather than a block.
+            if self.check(TokenType::RightBrace) || (self.check(TokenType::String) && self.check_next(TokenType::Colon)) {
+                return self.map_literal();
+            }
+            return Ok(self.block_expression());
+        }
+
+        // Only a reserved word being assigned to is clearly meant as a
+        // name; elsewhere, such as a stray `else`, no expression was written.
+        if self.peek().token_type.is_keyword() && self.check_next(TokenType::Equal) {
+            return Err(self.reserved_word_error(self.peek(), "variable name"));
         }
 
         Err(self.error(self.peek(), "Expect expression."))
     }
 
+    /// Parse the rest of a map literal, `{"key": value, ...}`. A trailing
+    /// comma is allowed.
+    fn map_literal(&mut self) -> Result<Expr, ParserError> {
+        let brace = self.previous().clone();
+        let mut entries = Vec::new();
+        while !self.check(TokenType::RightBrace) {
+            let key = self.consume(TokenType::String, "Expect string key in map literal.")?.clone();
+            self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+            let value = self.expression()?;
+            entries.push((key, value));
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+        let span = self.span_from(brace.span);
+        Ok(Expr::Map(Box::new(MapExpr { brace, entries, span })))
+    }
+
+    /// Whether the parser is at an arrow lambda, `(a, b) -> ...`. Looks past
+    /// the parameter list for the arrow without consuming anything.
+    fn at_lambda(&mut self) -> bool {
+        if !self.check(TokenType::LeftParen) {
+            return false;
+        }
+        let mut i = self.current + 1;
+        loop {
+            self.fill(i + 1 - self.current);
+            match self.token_at(i).token_type {
+                TokenType::RightParen => return self.token_at(i + 1).token_type == TokenType::Arrow,
+                TokenType::Identifier => match self.token_at(i + 1).token_type {
+                    TokenType::Comma => i += 2,
+                    TokenType::RightParen => i += 1,
+                    _ => return false,
+                },
+                _ => return false,
+            }
+        }
+    }
+
+    /// Parse an arrow lambda into an anonymous function returning its body.
+    fn lambda(&mut self) -> Result<Expr, ParserError> {
+        let start = self.peek().span;
+        self.consume(TokenType::LeftParen, "Expect '(' before lambda parameters.")?;
+        // Lambda parameters have no annotations or rest parameter: `at_lambda`
+        // does not look past a ':' or '...'.
+        let (params, _) = self.parameters()?;
+        let arrow = self.consume(TokenType::Arrow, "Expect '->' after lambda parameters.")?.clone();
+        let value = self.function_body(|parser| parser.expression())?;
+        let value_span = value.span();
+        let body = vec![Stmt::Return(Some(value), value_span)];
+        Ok(Expr::Lambda(Box::new(LambdaExpr { arrow, params, body, span: self.span_from(start) })))
+    }
+
+    /// Parse the rest of a block used as an expression. Statements are parsed
+    /// as in a block statement until an expression is followed by '}', which
+    /// becomes the block's value.
+    fn block_expression(&mut self) -> Expr {
+        let brace = self.previous().clone();
+        let mut statements = Vec::new();
+        let mut value = None;
+
+        self.block_depth += 1;
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            self.forget_bad_characters_behind();
+            if self.at_statement_keyword() {
+                statements.extend(self.declaration());
+                continue;
+            }
+            let expr = match self.expression() {
+                Ok(expr) => expr,
+                Err(err) => {
+                    self.report(err);
+                    self.synchronize();
+                    continue;
+                }
+            };
+            if self.check(TokenType::RightBrace) {
+                value = Some(expr);
+            } else if let Err(err) = self.consume_semicolon("Expect ';' after expression.") {
+                self.report(err);
+                self.synchronize();
+            } else {
+                let span = self.span_from(expr.span());
+                statements.push(Stmt::Expression(expr, span));
+            }
+        }
+        self.block_depth -= 1;
+
+        if let Err(err) = self.consume(TokenType::RightBrace, "Expect '}' after block.") {
+            self.report(err);
+        }
+        let span = self.span_from(brace.span);
+        Expr::Block(Box::new(BlockExpr { brace, statements, value, span }))
+    }
+
+    /// Whether the current token begins a statement rather than an expression.
+    fn at_statement_keyword(&self) -> bool {
+        matches!(
+            self.peek().token_type,
+            TokenType::Var | TokenType::Const | TokenType::Fun | TokenType::For | TokenType::If
+                | TokenType::Print | TokenType::Return | TokenType::While
+                | TokenType::LeftBrace | TokenType::Break | TokenType::Continue
+        )
+    }
+
+    /// Parse the rest of an if expression, whose `else` branch is required.
+    fn if_expression(&mut self) -> Result<Expr, ParserError> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+        let then_branch = self.expression()?;
+        self.consume(TokenType::Else, "Expect 'else' branch in if expression.")?;
+        let else_branch = self.expression()?;
+        Ok(Expr::If(Box::new(IfExpr { keyword, condition, then_branch, else_branch })))
+    }
+
+    /// Consume the semicolon ending a statement. In REPL grammar mode a statement
+    /// that runs into the end of input is accepted without one.
+    fn consume_semicolon(&mut self, message: &str) -> Result<(), ParserError> {
+        if self.implicit_final_semicolon && self.is_at_end() {
+            return Ok(());
+        }
+        if self.check(TokenType::Semicolon) {
+            self.advance();
+            return Ok(());
+        }
+
+        // The semicolon belongs right after the previous token, which may be
+        // several lines above whatever token we tripped over.
+        let previous = self.previous();
+        let error = ParserError::new(previous.line, format!("Error after '{}': {}", previous.lexeme, message));
+        Err(ParserError {
+            column: Some(previous.column + previous.lexeme.chars().count()),
+            span: Some(Span::new(previous.span.end, previous.span.end)),
+            ..error
+        }
+        .with_note("insert ';' here"))
+    }
+
+    /// Consume an identifier naming a `what` (e.g. "variable name"), with a
+    /// targeted error when a reserved word is used instead.
+    fn consume_identifier(&mut self, what: &str) -> Result<&Token, ParserError> {
+        if self.peek().token_type.is_keyword() {
+            return Err(self.reserved_word_error(self.peek(), what));
+        }
+        self.consume(TokenType::Identifier, &format!("Expect {}.", what))
+    }
+
+    fn reserved_word_error(&self, token: &Token, what: &str) -> ParserError {
+        ParserError::at(token, format!("'{}' is a reserved word and cannot be used as a {}.", token.lexeme, what))
+    }
+
     /// Consume a token if it matches the expected type, otherwise return an error.
     fn consume(&mut self, token_type: TokenType, message: &str) -> Result<&Token, ParserError> {
         if self.check(token_type) {
@@ -0,0 +1,164 @@
+//! Natives shipped by third parties as dynamic libraries and loaded with
+//! `interpret --plugin path`. The interface is plain C, so a plugin can be
+//! written in any language that can export C functions, and does not need
+//! to be built with the same compiler as the interpreter.
+//!
+//! A plugin exports two functions:
+//!
+//! ```c
+//! uint32_t rustylox_plugin_abi_version(void);   // must return 1
+//! const PluginNative *rustylox_plugin_natives(size_t *count);
+//! ```
+//!
+//! The second returns the plugin's natives, an array that must live as long
+//! as the library does, and stores its length in `count`. Each native gets
+//! its arguments as an array of [`PluginValue`]s and writes what it returns
+//! to `result`, which starts out as nil. It returns `false` to fail, with an
+//! error message as the string in `result`. Strings passed either way are
+//! NUL-terminated UTF-8 and are only borrowed for the call: the interpreter
+//! copies the result's string before calling the plugin again.
+
+use std::ffi::{c_char, CStr, CString};
+use std::rc::Rc;
+
+use libloading::Library;
+
+use crate::callable::NativeFunction;
+use crate::environ::Environment;
+use crate::error::EvalError;
+use crate::value::Value;
+
+/// The version of the interface described above. It changes whenever the
+/// interface does, so an outdated plugin is refused instead of misbehaving.
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+const VERSION_SYMBOL: &[u8] = b"rustylox_plugin_abi_version";
+const NATIVES_SYMBOL: &[u8] = b"rustylox_plugin_natives";
+
+type VersionFn = unsafe extern "C" fn() -> u32;
+type NativesFn = unsafe extern "C" fn(count: *mut usize) -> *const PluginNative;
+
+/// A native implemented by a plugin.
+pub type PluginFn = unsafe extern "C" fn(args: *const PluginValue, count: usize, result: *mut PluginValue) -> bool;
+
+/// Values of [`PluginValue::kind`].
+pub mod kind {
+    pub const NIL: u32 = 0;
+    pub const BOOLEAN: u32 = 1;
+    pub const NUMBER: u32 = 2;
+    pub const STRING: u32 = 3;
+}
+
+/// A value crossing into or out of a plugin, of one of the [`kind`]s. Only
+/// the field matching the kind is meaningful. Maps, lists and functions
+/// cannot be passed.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PluginValue {
+    pub kind: u32,
+    pub boolean: bool,
+    pub number: f64,
+    pub string: *const c_char,
+}
+
+impl PluginValue {
+    const NIL: PluginValue = PluginValue {
+        kind: kind::NIL,
+        boolean: false,
+        number: 0.0,
+        string: std::ptr::null(),
+    };
+}
+
+/// One entry of the array a plugin's `rustylox_plugin_natives` returns.
+#[repr(C)]
+pub struct PluginNative {
+    pub name: *const c_char,
+    pub arity: usize,
+    pub function: PluginFn,
+}
+
+/// Loads the plugin at `path` and defines its natives in `environment`,
+/// returning their names. The library stays loaded until the process
+/// exits, since the natives may be called at any time.
+pub fn load_plugin(path: &str, environment: &mut Environment) -> Result<Vec<String>, String> {
+    let failed = |e: libloading::Error| format!("Failed to load plugin {}: {}", path, e);
+    // SAFETY: loading a library runs its initializers; `--plugin` asks for
+    // the library to be trusted.
+    let library = unsafe { Library::new(path) }.map_err(failed)?;
+    // SAFETY: the symbols are declared with the types the interface gives them.
+    let natives = unsafe {
+        let version = library.get::<VersionFn>(VERSION_SYMBOL).map_err(failed)?;
+        if version() != PLUGIN_ABI_VERSION {
+            return Err(format!(
+                "Plugin {} was built for interface version {}, but this interpreter supports version {}",
+                path,
+                version(),
+                PLUGIN_ABI_VERSION
+            ));
+        }
+        let natives = library.get::<NativesFn>(NATIVES_SYMBOL).map_err(failed)?;
+        let mut count = 0;
+        let first = natives(&mut count);
+        if first.is_null() {
+            &[]
+        } else {
+            std::slice::from_raw_parts(first, count)
+        }
+    };
+
+    let mut names = Vec::new();
+    for native in natives {
+        // SAFETY: names are NUL-terminated strings living as long as the library.
+        let name = unsafe { CStr::from_ptr(native.name) }.to_string_lossy().into_owned();
+        let function = NativeFunction::from_plugin(&name, native.arity, native.function);
+        environment.define(name.clone(), Value::Callable(Rc::new(function)));
+        names.push(name);
+    }
+    std::mem::forget(library);
+    Ok(names)
+}
+
+/// Calls the plugin native `name` with `arguments`.
+pub(crate) fn call(name: &str, function: PluginFn, arguments: Vec<Value>) -> Result<Value, EvalError> {
+    // The strings must outlive the call, so they are kept here.
+    let strings = arguments
+        .iter()
+        .map(|argument| match argument {
+            Value::String(s) => CString::new(s.as_str())
+                .map(Some)
+                .map_err(|_| EvalError::TypeError(format!("Strings passed to '{}' cannot contain NUL", name))),
+            _ => Ok(None),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let values = arguments
+        .iter()
+        .zip(&strings)
+        .map(|(argument, string)| match (argument, string) {
+            (Value::Nil, _) => Ok(PluginValue::NIL),
+            (Value::Boolean(b), _) => Ok(PluginValue { kind: kind::BOOLEAN, boolean: *b, ..PluginValue::NIL }),
+            (Value::Number(n), _) => Ok(PluginValue { kind: kind::NUMBER, number: *n, ..PluginValue::NIL }),
+            (Value::String(_), Some(s)) => Ok(PluginValue { kind: kind::STRING, string: s.as_ptr(), ..PluginValue::NIL }),
+            (other, _) => Err(EvalError::TypeError(format!("'{}' cannot take a {}", name, other.type_name()))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut result = PluginValue::NIL;
+    // SAFETY: `values` and the strings they point to live until the call returns.
+    let succeeded = unsafe { function(values.as_ptr(), values.len(), &mut result) };
+    let string = || {
+        // SAFETY: the plugin keeps the result's string alive until it is called again.
+        (!result.string.is_null()).then(|| unsafe { CStr::from_ptr(result.string) }.to_string_lossy().into_owned())
+    };
+    if !succeeded {
+        let message = if result.kind == kind::STRING { string() } else { None };
+        return Err(EvalError::NativeError(message.unwrap_or_else(|| format!("'{}' failed", name))));
+    }
+    match result.kind {
+        kind::NIL => Ok(Value::Nil),
+        kind::BOOLEAN => Ok(Value::Boolean(result.boolean)),
+        kind::NUMBER => Ok(Value::Number(result.number)),
+        kind::STRING => Ok(string().map_or(Value::Nil, Value::String)),
+        other => Err(EvalError::NativeError(format!("'{}' returned a value of unknown kind {}", name, other))),
+    }
+}
@@ -0,0 +1,359 @@
+use crate::expr::{Expr, LiteralExpr};
+use crate::lexer::Lexer;
+use crate::stmt::{annotated, ForStmt, Stmt};
+use crate::token::TokenType;
+
+/// Binding strength of each expression form, loosest first. A subexpression
+/// is wrapped in parentheses when it binds more loosely than its position
+/// requires.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    /// Assignment and the forms that extend as far right as they can: `if`
+    /// expressions and lambdas.
+    Assignment,
+    NilCoalesce,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
+    Term,
+    Factor,
+    Unary,
+    Call,
+    Primary,
+}
+
+impl Precedence {
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::Assignment => Precedence::NilCoalesce,
+            Precedence::NilCoalesce => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::BitOr,
+            Precedence::BitOr => Precedence::BitXor,
+            Precedence::BitXor => Precedence::BitAnd,
+            Precedence::BitAnd => Precedence::Shift,
+            Precedence::Shift => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call | Precedence::Primary => Precedence::Primary,
+        }
+    }
+
+    fn of_operator(token_type: &TokenType) -> Precedence {
+        match token_type {
+            TokenType::QuestionQuestion => Precedence::NilCoalesce,
+            TokenType::Or => Precedence::Or,
+            TokenType::And => Precedence::And,
+            TokenType::EqualEqual | TokenType::BangEqual => Precedence::Equality,
+            TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+                Precedence::Comparison
+            }
+            TokenType::Pipe => Precedence::BitOr,
+            TokenType::Caret => Precedence::BitXor,
+            TokenType::Ampersand => Precedence::BitAnd,
+            TokenType::LessLess | TokenType::GreaterGreater => Precedence::Shift,
+            TokenType::Plus | TokenType::Minus => Precedence::Term,
+            _ => Precedence::Factor,
+        }
+    }
+
+    fn of(expr: &Expr) -> Precedence {
+        match expr {
+            Expr::Assign(..) | Expr::Set(_) | Expr::IndexSet(_) | Expr::If(_) | Expr::Lambda(_) => Precedence::Assignment,
+            Expr::Binary(binary) => Precedence::of_operator(&binary.operator.token_type),
+            Expr::Logical(logical) => Precedence::of_operator(&logical.operator.token_type),
+            Expr::Unary(_) => Precedence::Unary,
+            Expr::Call(_) | Expr::Get(_) | Expr::Index(_) => Precedence::Call,
+            Expr::Grouping(..)
+            | Expr::Constant(..)
+            | Expr::Variable(..)
+            | Expr::This(_)
+            | Expr::Super(..)
+            | Expr::Block(_)
+            | Expr::Map(_) => Precedence::Primary,
+        }
+    }
+}
+
+/// Renders statements back into Lox source that parses to the same tree.
+/// Layout is normalized, and desugared forms such as `for` loops come out
+/// as what they desugar to.
+pub fn to_source(statements: &[Stmt]) -> String {
+    let mut out = String::new();
+    for stmt in statements {
+        write_stmt(&mut out, stmt, 0);
+    }
+    out
+}
+
+/// Renders statements as the shortest source [`to_source`] can be squeezed
+/// into: a single line, with a space only where two tokens would otherwise
+/// run together. Suited to places short on room, such as a playground link.
+pub fn to_minified_source(statements: &[Stmt]) -> String {
+    let mut out = String::new();
+    for token in Lexer::new(to_source(statements)) {
+        if let (Some(last), Some(first)) = (out.chars().last(), token.lexeme.chars().next()) {
+            if runs_together(last, first) {
+                out.push(' ');
+            }
+        }
+        out.push_str(&token.lexeme);
+    }
+    out
+}
+
+/// Whether a token ending in `last` followed directly by one starting with
+/// `first` would lex differently, as a single word, number or operator.
+fn runs_together(last: char, first: char) -> bool {
+    let word = |c: char| c.is_alphanumeric() || c == '_';
+    (word(last) && word(first))
+        || matches!((last, first), ('=' | '!' | '<' | '>', '=') | ('-', '>') | ('?', '?' | '.') | ('/', '/'))
+}
+
+/// Renders an expression as Lox source.
+pub fn expr_to_source(expr: &Expr) -> String {
+    expr_at(expr, 0)
+}
+
+/// An expression in a position taking any expression, inside blocks
+/// nested `indent` levels deep.
+fn expr_at(expr: &Expr, indent: usize) -> String {
+    write_expr(expr, Precedence::Assignment, indent)
+}
+
+fn write_stmt(out: &mut String, stmt: &Stmt, indent: usize) {
+    out.push_str(&"    ".repeat(indent));
+    out.push_str(&stmt_line(stmt, indent));
+    out.push('\n');
+}
+
+/// The source of `stmt` without leading indentation or a final newline.
+fn stmt_line(stmt: &Stmt, indent: usize) -> String {
+    match stmt {
+        Stmt::Expression(expr, _) => format!("{};", statement_expr(expr, indent)),
+        Stmt::Print(expr, _) => format!("print {};", expr_at(expr, indent)),
+        Stmt::Var(name, annotation, None, _) => format!("var {}{};", name, annotated(annotation)),
+        Stmt::Var(name, annotation, Some(initializer), _) => {
+            format!("var {}{} = {};", name, annotated(annotation), expr_at(initializer, indent))
+        }
+        Stmt::Const(name, annotation, value, _) => format!("const {}{} = {};", name, annotated(annotation), expr_at(value, indent)),
+        Stmt::Block(statements, _) => block(statements, None, indent),
+        Stmt::If(condition, then_branch, else_branch, _) => {
+            // An `if` without `else` at the end of the branch would capture ours.
+            let then_branch = if else_branch.is_some() && ends_in_open_if(then_branch) {
+                block(std::slice::from_ref(then_branch.as_ref()), None, indent)
+            } else {
+                stmt_line(then_branch, indent)
+            };
+            match else_branch {
+                Some(else_branch) => format!(
+                    "if ({}) {} else {}",
+                    expr_at(condition, indent),
+                    then_branch,
+                    stmt_line(else_branch, indent)
+                ),
+                None => format!("if ({}) {}", expr_at(condition, indent), then_branch),
+            }
+        }
+        Stmt::While(_, condition, body, _) => format!("while ({}) {}", expr_at(condition, indent), stmt_line(body, indent)),
+        Stmt::For(for_stmt) => {
+            let ForStmt { initializer, condition, increment, body, .. } = for_stmt.as_ref();
+            // The initializer brings its own semicolon.
+            let initializer = initializer.as_ref().map_or(";".to_string(), |initializer| stmt_line(initializer, indent));
+            let clause = |expr: &Option<Expr>| expr.as_ref().map(|expr| format!(" {}", expr_at(expr, indent))).unwrap_or_default();
+            format!("for ({}{};{}) {}", initializer, clause(condition), clause(increment), stmt_line(body, indent))
+        }
+        Stmt::ForIn(for_in) => format!(
+            "for (var {} in {}) {}",
+            for_in.name.lexeme,
+            expr_at(&for_in.iterable, indent),
+            stmt_line(&for_in.body, indent)
+        ),
+        Stmt::Break(_) => "break;".to_string(),
+        Stmt::Continue(_) => "continue;".to_string(),
+        Stmt::Function(..) => format!("fun {}", function(stmt, indent)),
+        Stmt::Class(name, superclass, methods, _) => {
+            let superclass = superclass.as_ref().map(|superclass| format!(" < {}", superclass.lexeme)).unwrap_or_default();
+            if methods.is_empty() {
+                return format!("class {}{} {{}}", name, superclass);
+            }
+            let mut out = format!("class {}{} {{\n", name, superclass);
+            for method in methods {
+                out.push_str(&"    ".repeat(indent + 1));
+                out.push_str(&function(method, indent + 1));
+                out.push('\n');
+            }
+            out.push_str(&"    ".repeat(indent));
+            out.push('}');
+            out
+        }
+        Stmt::Return(None, _) => "return;".to_string(),
+        Stmt::Return(Some(value), _) => format!("return {};", expr_at(value, indent)),
+        Stmt::ParallelAssign(names, values, _) => format!(
+            "{} = {};",
+            names.iter().map(|(name, _)| name.lexeme.as_str()).collect::<Vec<_>>().join(", "),
+            values.iter().map(|value| expr_at(value, indent)).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+/// A function declaration or method from its name onwards, as methods are
+/// written without `fun`.
+fn function(stmt: &Stmt, indent: usize) -> String {
+    let Stmt::Function(name, params, body, signature, _) = stmt else {
+        return stmt_line(stmt, indent);
+    };
+    let params = signature.written_params(params);
+    format!("{}({}){} {}", name, params.join(", "), annotated(&signature.returns), block(body, None, indent))
+}
+
+/// Whether `stmt` ends in an `if` statement without an `else`.
+fn ends_in_open_if(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::If(_, _, None, _) => true,
+        Stmt::If(_, _, Some(else_branch), _) => ends_in_open_if(else_branch),
+        Stmt::While(_, _, body, _) => ends_in_open_if(body),
+        Stmt::For(for_stmt) => ends_in_open_if(&for_stmt.body),
+        Stmt::ForIn(for_in) => ends_in_open_if(&for_in.body),
+        _ => false,
+    }
+}
+
+/// A braced block, optionally ending in a value expression.
+fn block(statements: &[Stmt], value: Option<&Expr>, indent: usize) -> String {
+    if statements.is_empty() && value.is_none() {
+        return "{}".to_string();
+    }
+    let mut out = String::from("{\n");
+    for stmt in statements {
+        write_stmt(&mut out, stmt, indent + 1);
+    }
+    if let Some(value) = value {
+        out.push_str(&"    ".repeat(indent + 1));
+        out.push_str(&statement_expr(value, indent + 1));
+        out.push('\n');
+    }
+    out.push_str(&"    ".repeat(indent));
+    out.push('}');
+    out
+}
+
+/// An expression in statement position, parenthesized when it would
+/// otherwise read as a block or `if` statement.
+fn statement_expr(expr: &Expr, indent: usize) -> String {
+    let source = expr_at(expr, indent);
+    if source.starts_with('{') || matches!(expr, Expr::If(_)) {
+        format!("({})", source)
+    } else {
+        source
+    }
+}
+
+fn write_expr(expr: &Expr, min: Precedence, indent: usize) -> String {
+    let precedence = Precedence::of(expr);
+    let source = match expr {
+        Expr::Constant(literal, _) => literal_to_source(literal),
+        Expr::Grouping(inner, _) => format!("({})", write_expr(inner, Precedence::Assignment, indent)),
+        Expr::Variable(name, _) | Expr::This(name) => name.lexeme.clone(),
+        Expr::Super(_, method) => format!("super.{}", method.lexeme),
+        Expr::Assign(name, value, _) => format!("{} = {}", name.lexeme, write_expr(value, Precedence::Assignment, indent)),
+        Expr::Unary(unary) => format!("{}{}", unary.operator.lexeme, write_expr(&unary.right, Precedence::Unary, indent)),
+        Expr::Binary(binary) => format!(
+            "{} {} {}",
+            write_expr(&binary.left, precedence, indent),
+            binary.operator.lexeme,
+            write_expr(&binary.right, precedence.next(), indent)
+        ),
+        Expr::Logical(logical) => format!(
+            "{} {} {}",
+            write_expr(&logical.left, precedence, indent),
+            logical.operator.lexeme,
+            write_expr(&logical.right, precedence.next(), indent)
+        ),
+        Expr::Call(call) => {
+            let mut arguments = call.arguments.iter().map(|argument| expr_at(argument, indent)).collect::<Vec<_>>();
+            if let Some(last) = arguments.last_mut().filter(|_| call.spread) {
+                last.insert_str(0, "...");
+            }
+            format!("{}({})", write_expr(&call.callee, Precedence::Call, indent), arguments.join(", "))
+        }
+        Expr::Get(get) => format!(
+            "{}{}{}",
+            write_expr(&get.object, Precedence::Call, indent),
+            if get.optional { "?." } else { "." },
+            get.name.lexeme
+        ),
+        Expr::Set(set) => format!(
+            "{}.{} = {}",
+            write_expr(&set.object, Precedence::Call, indent),
+            set.name.lexeme,
+            write_expr(&set.value, Precedence::Assignment, indent)
+        ),
+        Expr::Index(index) => format!(
+            "{}[{}]",
+            write_expr(&index.object, Precedence::Call, indent),
+            expr_at(&index.index, indent)
+        ),
+        Expr::IndexSet(set) => format!(
+            "{}[{}] = {}",
+            write_expr(&set.object, Precedence::Call, indent),
+            expr_at(&set.index, indent),
+            write_expr(&set.value, Precedence::Assignment, indent)
+        ),
+        Expr::Map(map) => {
+            let entries = map.entries.iter().map(|(key, value)| format!("{}: {}", key.lexeme, expr_at(value, indent)));
+            format!("{{{}}}", entries.collect::<Vec<_>>().join(", "))
+        }
+        Expr::If(if_expr) => format!(
+            "if ({}) {} else {}",
+            expr_at(&if_expr.condition, indent),
+            expr_at(&if_expr.then_branch, indent),
+            expr_at(&if_expr.else_branch, indent)
+        ),
+        Expr::Lambda(lambda) => match lambda.body.as_slice() {
+            [Stmt::Return(Some(body), _)] => format!("({}) -> {}", lambda.params.join(", "), expr_at(body, indent)),
+            // The grammar only has arrow lambdas; a body built some other way
+            // comes out as an arrow returning a block of its statements.
+            body => format!("({}) -> {}", lambda.params.join(", "), block(body, None, indent)),
+        },
+        Expr::Block(block_expr) => block(&block_expr.statements, block_expr.value.as_ref(), indent),
+    };
+    if precedence < min {
+        format!("({})", source)
+    } else {
+        source
+    }
+}
+
+/// Writes `s` back with the escape sequences the lexer decodes.
+fn escape_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn literal_to_source(literal: &LiteralExpr) -> String {
+    match literal {
+        LiteralExpr::Number(n) => n.to_string(),
+        LiteralExpr::String(s) => format!("\"{}\"", escape_string(s)),
+        LiteralExpr::Boolean(b) => b.to_string(),
+        LiteralExpr::Nil => "nil".to_string(),
+    }
+}
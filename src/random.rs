@@ -0,0 +1,51 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// The random number generator behind `random()` and `randomInt()`: a
+/// SplitMix64 generator, small and good enough for scripts, that gives the
+/// same numbers again for the same seed.
+#[derive(Debug, Clone)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random { state: seed }
+    }
+
+    /// A generator seeded differently on every run.
+    pub fn from_entropy() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        // Hash keys are fixed on some targets, so the time is mixed in too.
+        if let Ok(crate::value::Value::Number(now)) = crate::natives::clock(Vec::new()) {
+            hasher.write_u64(now.to_bits());
+        }
+        Random::new(hasher.finish())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        // The top 53 bits fill the mantissa exactly.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A whole number from `low` to `high`, both included. `low` must not be
+    /// above `high`.
+    pub fn int_between(&mut self, low: i64, high: i64) -> i64 {
+        let range = (high.wrapping_sub(low) as u64).wrapping_add(1);
+        if range == 0 {
+            // The whole i64 range.
+            return self.next_u64() as i64;
+        }
+        low.wrapping_add((self.next_u64() % range) as i64)
+    }
+}
@@ -1,36 +1,76 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::{expr::Expr, stmt::Stmt, token::Token};
+use crate::{error::ResolveError, expr::{Expr, ExprId}, stmt::{ForInStmt, ForStmt, Stmt}, token::Token};
 
+/// What kind of class body the code being resolved is in, which decides
+/// whether `this` and `super` may be used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+/// Works out, before a program runs, which scope each variable use refers
+/// to, counted in environments out from the one it is evaluated in.
 pub struct Resolver {
     scopes: Vec<HashMap<String, bool>>,
+    /// The names declared with `const` in the globals, then in each of
+    /// `scopes`.
+    constants: Vec<HashSet<String>>,
+    /// How far out each variable or assignment expression finds a local variable.
+    locals: HashMap<ExprId, usize>,
+    /// The variable and assignment expressions referring to globals.
+    globals: HashSet<ExprId>,
+    /// The class whose body is being resolved, if any.
+    class: ClassType,
+    errors: Vec<ResolveError>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Resolver {
     pub fn new() -> Self {
         Resolver {
             scopes: Vec::new(),
+            constants: vec![HashSet::new()],
+            locals: HashMap::new(),
+            globals: HashSet::new(),
+            class: ClassType::None,
+            errors: Vec::new(),
         }
     }
 
-    pub fn resolve(&mut self, statements: &[Stmt]) {
+    /// Resolves `statements`, returning the mistakes found in them, such as
+    /// `this` outside of a class. The program must not run if there are any.
+    pub fn resolve(&mut self, statements: &[Stmt]) -> Vec<ResolveError> {
         for statement in statements {
             self.resolve_stmt(statement);
         }
+        std::mem::take(&mut self.errors)
     }
 
     /// Begin a new block scope
     fn begin_scope(&mut self) {
         self.scopes.push(HashMap::new());
+        self.constants.push(HashSet::new());
     }
 
     /// End a block scope
     fn end_scope(&mut self) {
         self.scopes.pop();
+        self.constants.pop();
     }
 
     /// Declare a variable in the current scope
     fn declare(&mut self, name: &str) {
+        if let Some(constants) = self.constants.last_mut() {
+            constants.remove(name);
+        }
         if let Some(scope) = self.scopes.last_mut() {
             scope.insert(name.to_string(), false);
         }
@@ -43,14 +83,34 @@ impl Resolver {
         }
     }
 
-    /// Resolve a variable, determining whether it is in the current scope or a global
-    pub fn resolve_local(&self, name: &str) -> Option<usize> {
-        for (i, scope) in self.scopes.iter().rev().enumerate() {
-            if scope.contains_key(name) {
-                return Some(i);
-            }
+    /// How many environments out from where `id` is evaluated its variable
+    /// lives, if it is a local.
+    pub fn resolve_local(&self, id: ExprId) -> Option<usize> {
+        self.locals.get(&id).copied()
+    }
+
+    /// Whether `id` was resolved to a global. Expressions this resolver never
+    /// saw, such as those in a closure from an earlier REPL line, are neither
+    /// local nor global and are looked up by name.
+    pub fn is_global(&self, id: ExprId) -> bool {
+        self.globals.contains(&id)
+    }
+
+    /// The depth of the innermost scope declaring `name`, if any does.
+    fn scope_depth(&self, name: &str) -> Option<usize> {
+        self.scopes.iter().rev().position(|scope| scope.contains_key(name))
+    }
+
+    /// Reports an assignment to `name` if the variable it refers to is a
+    /// constant.
+    fn check_assignable(&mut self, name: &Token) {
+        let scope = match self.scope_depth(&name.lexeme) {
+            Some(depth) => self.scopes.len() - depth,
+            None => 0,
+        };
+        if self.constants[scope].contains(&name.lexeme) {
+            self.errors.push(ResolveError::at(name, &format!("Can't assign to constant '{}'.", name.lexeme)));
         }
-        None
     }
 
     fn resolve_var_declaration(&mut self, name: &str, initializer: Option<&Expr>) {
@@ -69,44 +129,79 @@ impl Resolver {
         self.end_scope();
     }
 
-    fn resolve_variable(&mut self, name: &Token) {
-        if let Some(depth) = self.resolve_local(&name.lexeme) {
-            self.mark_variable(name, depth);
-        } else {
-            self.mark_variable(name, 0);
+    /// Records the depth of the variable `name` for the expression `id`.
+    fn resolve_variable(&mut self, name: &Token, id: ExprId) {
+        match self.scope_depth(&name.lexeme) {
+            Some(depth) => {
+                self.locals.insert(id, depth);
+            }
+            None => {
+                self.globals.insert(id);
+            }
         }
     }
 
-    fn mark_variable(&mut self, name: &Token, depth: usize) {
-        if let Some(scope) = self.scopes.get_mut(depth) {
-            scope.insert(name.lexeme.clone(), true);
-        }
-    }
-    
     fn resolve_function(&mut self, name: &str, params: &[String], body: &[Stmt]) {
         self.declare(name);
         self.define(name);
-    
+        self.resolve_function_body(params, body);
+    }
+
+    fn resolve_class(&mut self, name: &str, superclass: Option<&Token>, methods: &[Stmt]) {
+        self.declare(name);
+        self.define(name);
+        let enclosing = std::mem::replace(&mut self.class, ClassType::Class);
+        if let Some(superclass) = superclass {
+            if superclass.lexeme == name {
+                self.errors.push(ResolveError::at(superclass, "A class can't inherit from itself."));
+            }
+            self.class = ClassType::Subclass;
+            // Methods of a subclass close over an environment holding `super`.
+            self.begin_scope();
+            self.define("super");
+        }
+        // Bound methods run in an environment holding `this` around their closure.
+        self.begin_scope();
+        self.define("this");
+        for method in methods {
+            if let Stmt::Function(_, params, body, _, _) = method {
+                self.resolve_function_body(params, body);
+            }
+        }
+        self.end_scope();
+        if superclass.is_some() {
+            self.end_scope();
+        }
+        self.class = enclosing;
+    }
+
+    fn resolve_function_body(&mut self, params: &[String], body: &[Stmt]) {
         self.begin_scope();
         for param in params {
             self.declare(param);
             self.define(param);
         }
-        self.resolve_block(body);
+        // The body runs in the same environment as the parameters.
+        for statement in body {
+            self.resolve_stmt(statement);
+        }
         self.end_scope();
     }
 
     fn resolve_expr(&mut self, expr: &Expr) {
         match expr {
-            Expr::Variable(name) => {
-                // Resolve the variable
-                self.resolve_variable(name);
+            Expr::Variable(name, id) => {
+                if self.scopes.last().and_then(|scope| scope.get(&name.lexeme)) == Some(&false) {
+                    self.errors.push(ResolveError::at(name, "Can't read local variable in its own initializer."));
+                }
+                self.resolve_variable(name, *id);
             }
-            Expr::Assign(name, value) => {
+            Expr::Assign(name, value, id) => {
                 // Resolve the value being assigned to the variable
                 self.resolve_expr(value);
                 // Resolve the variable itself (find its depth)
-                self.resolve_variable(name);
+                self.resolve_variable(name, *id);
+                self.check_assignable(name);
             }
             Expr::Binary(binary_expr) => {
                 self.resolve_expr(&binary_expr.left);
@@ -118,10 +213,10 @@ impl Resolver {
                     self.resolve_expr(arg);
                 }
             }
-            Expr::Grouping(expr) => {
+            Expr::Grouping(expr, _) => {
                 self.resolve_expr(expr);
             }
-            Expr::Literal(_) => {}
+            Expr::Constant(..) => {}
             Expr::Logical(logical_expr) => {
                 self.resolve_expr(&logical_expr.left);
                 self.resolve_expr(&logical_expr.right);
@@ -129,42 +224,134 @@ impl Resolver {
             Expr::Unary(unary_expr) => {
                 self.resolve_expr(&unary_expr.right);
             }
+            Expr::Get(get_expr) => {
+                self.resolve_expr(&get_expr.object);
+            }
+            Expr::This(keyword) => {
+                if self.class == ClassType::None {
+                    self.errors.push(ResolveError::at(keyword, "Can't use 'this' outside of a class."));
+                }
+            }
+            Expr::Super(keyword, _) => match self.class {
+                ClassType::None => {
+                    self.errors.push(ResolveError::at(keyword, "Can't use 'super' outside of a class."));
+                }
+                ClassType::Class => {
+                    self.errors.push(ResolveError::at(keyword, "Can't use 'super' in a class with no superclass."));
+                }
+                ClassType::Subclass => {}
+            },
+            Expr::Set(set_expr) => {
+                self.resolve_expr(&set_expr.value);
+                self.resolve_expr(&set_expr.object);
+            }
+            Expr::Index(index_expr) => {
+                self.resolve_expr(&index_expr.object);
+                self.resolve_expr(&index_expr.index);
+            }
+            Expr::IndexSet(set_expr) => {
+                self.resolve_expr(&set_expr.object);
+                self.resolve_expr(&set_expr.index);
+                self.resolve_expr(&set_expr.value);
+            }
+            Expr::Map(map_expr) => {
+                for (_, value) in &map_expr.entries {
+                    self.resolve_expr(value);
+                }
+            }
+            Expr::Block(block) => {
+                self.begin_scope();
+                for statement in &block.statements {
+                    self.resolve_stmt(statement);
+                }
+                if let Some(value) = &block.value {
+                    self.resolve_expr(value);
+                }
+                self.end_scope();
+            }
+            Expr::Lambda(lambda) => {
+                self.resolve_function_body(&lambda.params, &lambda.body);
+            }
+            Expr::If(if_expr) => {
+                self.resolve_expr(&if_expr.condition);
+                self.resolve_expr(&if_expr.then_branch);
+                self.resolve_expr(&if_expr.else_branch);
+            }
         }
     }
 
     fn resolve_stmt(&mut self, stmt: &Stmt) {
         match stmt {
-            Stmt::Block(statements) => {
+            Stmt::Block(statements, _) => {
                 self.resolve_block(statements);
             }
-            Stmt::Expression(expr) => {
+            Stmt::Expression(expr, _) => {
                 self.resolve_expr(expr);
             }
-            Stmt::Function(name, params, body) => {
+            Stmt::Function(name, params, body, _, _) => {
                 self.resolve_function(name, params, body);
             }
-            Stmt::If(condition, then_branch, else_branch) => {
+            Stmt::Class(name, superclass, methods, _) => {
+                self.resolve_class(name, superclass.as_ref(), methods);
+            }
+            Stmt::If(condition, then_branch, else_branch, _) => {
                 self.resolve_expr(condition);
                 self.resolve_stmt(then_branch);
                 if let Some(else_branch) = else_branch {
                     self.resolve_stmt(else_branch);
                 }
             }
-            Stmt::Print(expr) => {
+            Stmt::Print(expr, _) => {
                 self.resolve_expr(expr);
             }
-            Stmt::Return(value) => {
-                if let Some(value) = value {
+            Stmt::ParallelAssign(targets, values, _) => {
+                for value in values {
                     self.resolve_expr(value);
                 }
+                for (target, id) in targets {
+                    self.resolve_variable(target, *id);
+                    self.check_assignable(target);
+                }
             }
-            Stmt::Var(name, initializer) => {
+            Stmt::Return(Some(value), _) => {
+                self.resolve_expr(value);
+            }
+            Stmt::Var(name, _, initializer, _) => {
                 self.resolve_var_declaration(name, initializer.as_ref());
             }
-            Stmt::While(condition, body) => {
+            Stmt::Const(name, _, value, _) => {
+                self.resolve_var_declaration(name, Some(value));
+                if let Some(constants) = self.constants.last_mut() {
+                    constants.insert(name.clone());
+                }
+            }
+            Stmt::While(_, condition, body, _) => {
                 self.resolve_expr(condition);
                 self.resolve_stmt(body);
             }
+            Stmt::For(for_stmt) => {
+                let ForStmt { initializer, condition, increment, body, .. } = for_stmt.as_ref();
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.resolve_stmt(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.resolve_expr(condition);
+                }
+                if let Some(increment) = increment {
+                    self.resolve_expr(increment);
+                }
+                self.resolve_stmt(body);
+                self.end_scope();
+            }
+            Stmt::ForIn(for_in) => {
+                let ForInStmt { name, iterable, body, .. } = for_in.as_ref();
+                self.resolve_expr(iterable);
+                self.begin_scope();
+                self.define(&name.lexeme);
+                self.resolve_stmt(body);
+                self.end_scope();
+            }
             _ => {
                 // Do nothing
             }
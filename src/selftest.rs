@@ -0,0 +1,73 @@
+use std::fmt;
+
+use crate::golden::run_fixture;
+
+/// Embeds a golden fixture and its expected output under the fixture's name.
+macro_rules! case {
+    ($name:literal) => {
+        (
+            $name,
+            include_str!(concat!("../tests/golden/", $name, ".lox")),
+            include_str!(concat!("../tests/golden/", $name, ".expected")),
+        )
+    };
+}
+
+/// Programs built into the binary with the output they must produce,
+/// covering each part of the language and its error reporting.
+const CASES: &[(&str, &str, &str)] = &[
+    case!("block"),
+    case!("break"),
+    case!("counter"),
+    case!("fib"),
+    case!("flow"),
+    case!("for"),
+    case!("fun"),
+    case!("lambdas"),
+    case!("maps_and_methods"),
+    case!("scoping"),
+    case!("stacktrace"),
+    case!("unicode"),
+    case!("while"),
+    case!("runtime_error"),
+    case!("syntax_error"),
+];
+
+/// The outcome of running the built-in programs.
+#[derive(Debug, Default)]
+pub struct SelftestReport {
+    pub passed: Vec<&'static str>,
+    pub failed: Vec<&'static str>,
+}
+
+impl SelftestReport {
+    pub fn success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl fmt::Display for SelftestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for name in &self.passed {
+            writeln!(f, "ok      {}", name)?;
+        }
+        for name in &self.failed {
+            writeln!(f, "FAILED  {}", name)?;
+        }
+        write!(f, "{} passed, {} failed", self.passed.len(), self.failed.len())
+    }
+}
+
+/// Runs every built-in program, so an installed or compiled build can be
+/// checked without the source tree.
+pub fn run_selftest() -> SelftestReport {
+    let mut report = SelftestReport::default();
+    for (name, source, expected) in CASES {
+        if run_fixture(source) == *expected {
+            report.passed.push(name);
+        } else {
+            report.failed.push(name);
+        }
+    }
+    report
+}
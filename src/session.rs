@@ -0,0 +1,92 @@
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{new_globals, run_repl_input, RunOptions};
+
+/// A recorded REPL session, written by `cli --record session.json` and
+/// reproduced by `replay session.json`.
+///
+/// ```json
+/// {
+///   "arguments": ["--integer-division"],
+///   "entries": [
+///     { "timestamp_ms": 1700000000000, "input": "print 7 / 2;\n", "output": "3\n", "diagnostics": "" }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Session {
+    /// Flags the REPL was started with, so a replay runs with the same settings.
+    pub arguments: Vec<String>,
+    pub entries: Vec<SessionEntry>,
+}
+
+/// One line typed into the REPL and what came back on stdout and stderr.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionEntry {
+    /// Milliseconds since the Unix epoch when the input finished running.
+    pub timestamp_ms: u64,
+    pub input: String,
+    /// What the input printed.
+    pub output: String,
+    /// The errors the input stopped with, as the REPL showed them.
+    pub diagnostics: String,
+}
+
+impl SessionEntry {
+    fn new(input: &str, output: &str, diagnostics: &str) -> SessionEntry {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        SessionEntry {
+            timestamp_ms,
+            input: input.to_string(),
+            output: output.to_string(),
+            diagnostics: diagnostics.to_string(),
+        }
+    }
+
+    /// Whether `other` printed and reported the same things.
+    pub fn same_result(&self, other: &SessionEntry) -> bool {
+        self.output == other.output && self.diagnostics == other.diagnostics
+    }
+}
+
+impl Session {
+    pub fn new(arguments: Vec<String>) -> Session {
+        Session { arguments, entries: Vec::new() }
+    }
+
+    /// Appends an input and what running it printed and reported.
+    pub fn record(&mut self, input: &str, output: &str, diagnostics: &str) {
+        self.entries.push(SessionEntry::new(input, output, diagnostics));
+    }
+
+    pub fn load(path: &Path) -> Result<Session, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|_| format!("Failed to read session {}", path.display()))?;
+        serde_json::from_str(&contents).map_err(|e| format!("Invalid session {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|_| format!("Failed to write session {}", path.display()))
+    }
+
+    /// Runs the recorded inputs again, in order and sharing one set of
+    /// globals as the REPL does, and records what they produce now.
+    pub fn replay(&self, options: &RunOptions) -> Session {
+        let globals = new_globals(options.capabilities);
+        let mut replayed = Session::new(self.arguments.clone());
+        for entry in &self.entries {
+            let (output, diagnostics) = run_repl_input(&entry.input, globals.clone(), options);
+            replayed.record(&entry.input, &output, &diagnostics);
+        }
+        replayed
+    }
+}
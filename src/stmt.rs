@@ -1,18 +1,85 @@
-use crate::expr::Expr;
+use crate::expr::{Expr, ExprId};
 use crate::error::ParserError;
+use crate::token::{Span, Token};
 
+/// The type annotations of a function declaration, as in
+/// `fun add(a: Number, b: Number): Number`. Each is the token naming the
+/// type, or `None` where the source leaves it out.
+#[derive(Debug, Clone, Default)]
+pub struct Signature {
+    /// One entry per parameter.
+    pub params: Vec<Option<Token>>,
+    pub returns: Option<Token>,
+    /// Whether the last parameter is a rest parameter, written `...name`,
+    /// which collects the arguments past the others into a list.
+    pub rest: bool,
+}
+
+impl Signature {
+    /// The parameters `params` of this signature as they are written, with
+    /// their annotations.
+    pub fn written_params(&self, params: &[String]) -> Vec<String> {
+        params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| {
+                let rest = if self.rest && i + 1 == params.len() { "..." } else { "" };
+                format!("{}{}{}", rest, param, annotated(self.params.get(i).unwrap_or(&None)))
+            })
+            .collect()
+    }
+}
+
+/// A statement. The last field of every variant, or of the struct it
+/// boxes, is the source range the statement was parsed from, see
+/// [`Stmt::span`].
 #[derive(Debug, Clone)]
 pub enum Stmt {
-    Expression(Expr),
-    Print(Expr),
-    Var(String, Option<Expr>),
-    Block(Vec<Stmt>),
-    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
-    While(Expr, Box<Stmt>),
-    Break,
-    Continue,
-    Function(String, Vec<String>, Vec<Stmt>),
-    Return(Option<Expr>),
+    Expression(Expr, Span),
+    Print(Expr, Span),
+    /// A declaration's name, its type annotation and its initializer.
+    Var(String, Option<Token>, Option<Expr>, Span),
+    /// A constant's name, its type annotation and its value.
+    Const(String, Option<Token>, Expr, Span),
+    Block(Vec<Stmt>, Span),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>, Span),
+    /// The loop's `while` keyword, then its condition and body.
+    While(Token, Expr, Box<Stmt>, Span),
+    For(Box<ForStmt>),
+    ForIn(Box<ForInStmt>),
+    Break(Span),
+    Continue(Span),
+    Function(String, Vec<String>, Vec<Stmt>, Signature, Span),
+    /// A class's name, the name of the class it inherits from, if any, and
+    /// its methods, each a [`Stmt::Function`].
+    Class(String, Option<Token>, Vec<Stmt>, Span),
+    Return(Option<Expr>, Span),
+    /// `a, b = b, a;` assigns every target after evaluating all the values.
+    /// Each target carries an id for the resolver, like [`Expr::Assign`].
+    ParallelAssign(Vec<(Token, ExprId)>, Vec<Expr>, Span),
+}
+
+/// A `for` loop. Its initializer, condition and increment may each be
+/// left out.
+#[derive(Debug, Clone)]
+pub struct ForStmt {
+    pub keyword: Token,
+    pub initializer: Option<Stmt>,
+    pub condition: Option<Expr>,
+    pub increment: Option<Expr>,
+    pub body: Stmt,
+    pub span: Span,
+}
+
+/// A `for (var name in iterable)` loop, which runs its body once for each
+/// item of a list or each key of a map, with `name` bound to it.
+#[derive(Debug, Clone)]
+pub struct ForInStmt {
+    pub keyword: Token,
+    pub name: Token,
+    pub iterable: Expr,
+    pub body: Stmt,
+    pub span: Span,
 }
 
 pub fn pretty_print_program(program: (Vec<Stmt>, Vec<ParserError>)) -> String {
@@ -34,6 +101,9 @@ pub fn pretty_print_program(program: (Vec<Stmt>, Vec<ParserError>)) -> String {
                 "Line {}: {}\n",
                 error.line, error.message
             ));
+            for note in &error.notes {
+                result.push_str(&format!("    note: {}\n", note));
+            }
         }
     }
 
@@ -42,6 +112,27 @@ pub fn pretty_print_program(program: (Vec<Stmt>, Vec<ParserError>)) -> String {
 
 
 impl Stmt {
+    /// The source range the statement was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Expression(_, span)
+            | Stmt::Print(_, span)
+            | Stmt::Var(_, _, _, span)
+            | Stmt::Const(_, _, _, span)
+            | Stmt::Block(_, span)
+            | Stmt::If(_, _, _, span)
+            | Stmt::While(_, _, _, span)
+            | Stmt::Break(span)
+            | Stmt::Continue(span)
+            | Stmt::Function(_, _, _, _, span)
+            | Stmt::Class(_, _, _, span)
+            | Stmt::Return(_, span)
+            | Stmt::ParallelAssign(_, _, span) => *span,
+            Stmt::For(for_stmt) => for_stmt.span,
+            Stmt::ForIn(for_in) => for_in.span,
+        }
+    }
+
     pub fn pretty_print(&self) -> String {
         self.pretty_print_with_indent(0)
     }
@@ -49,33 +140,42 @@ impl Stmt {
     pub fn pretty_print_with_indent(&self, indent: usize) -> String {
         let indentation = " ".repeat(indent * 2);
         match self {
-            Stmt::Expression(expr) => format!(
+            Stmt::Expression(expr, _) => format!(
                 "{}Expression\n{}└── {}",
                 indentation,
                 indentation,
                 expr.pretty_print_with_indent(indent + 1)
             ),
-            Stmt::Print(expr) => format!(
+            Stmt::Print(expr, _) => format!(
                 "{}Print\n{}└── {}",
                 indentation,
                 indentation,
                 expr.pretty_print_with_indent(indent + 1)
             ),
-            Stmt::Var(name, initializer) => {
+            Stmt::Var(name, annotation, initializer, _) => {
                 let initializer_str = if let Some(expr) = initializer {
                     expr.pretty_print_with_indent(indent + 1)
                 } else {
                     format!("{}None", indentation)
                 };
                 format!(
-                    "{}Var ({})\n{}└── {}",
+                    "{}Var ({}{})\n{}└── {}",
                     indentation,
                     name,
+                    annotated(annotation),
                     indentation,
                     initializer_str
                 )
             }
-            Stmt::Block(statements) => {
+            Stmt::Const(name, annotation, value, _) => format!(
+                "{}Const ({}{})\n{}└── {}",
+                indentation,
+                name,
+                annotated(annotation),
+                indentation,
+                value.pretty_print_with_indent(indent + 1)
+            ),
+            Stmt::Block(statements, _) => {
                 let mut result = format!("{}Block", indentation);
                 for statement in statements {
                     result.push_str(&format!(
@@ -86,7 +186,7 @@ impl Stmt {
                 }
                 result
             }
-            Stmt::If(condition, then_branch, else_branch) => {
+            Stmt::If(condition, then_branch, else_branch, _) => {
                 let mut result = format!(
                     "{}If\n{}├── Condition: {}\n{}├── Then: {}",
                     indentation,
@@ -104,7 +204,7 @@ impl Stmt {
                 }
                 result
             }
-            Stmt::While(condition, body) => format!(
+            Stmt::While(_, condition, body, _) => format!(
                 "{}While\n{}├── Condition: {}\n{}└── Body: {}",
                 indentation,
                 indentation,
@@ -112,11 +212,38 @@ impl Stmt {
                 indentation,
                 body.pretty_print_with_indent(indent + 1)
             ),
-            Stmt::Function(name, params, body) => {
+            Stmt::For(for_stmt) => {
+                let ForStmt { initializer, condition, increment, body, .. } = for_stmt.as_ref();
+                let none = format!("{}None", indentation);
+                format!(
+                    "{}For\n{}├── Initializer: {}\n{}├── Condition: {}\n{}├── Increment: {}\n{}└── Body: {}",
+                    indentation,
+                    indentation,
+                    initializer.as_ref().map_or(none.clone(), |initializer| initializer.pretty_print_with_indent(indent + 1)),
+                    indentation,
+                    condition.as_ref().map_or(none.clone(), |condition| condition.pretty_print_with_indent(indent + 1)),
+                    indentation,
+                    increment.as_ref().map_or(none, |increment| increment.pretty_print_with_indent(indent + 1)),
+                    indentation,
+                    body.pretty_print_with_indent(indent + 1)
+                )
+            }
+            Stmt::ForIn(for_in) => format!(
+                "{}ForIn ({})\n{}├── Iterable: {}\n{}└── Body: {}",
+                indentation,
+                for_in.name.lexeme,
+                indentation,
+                for_in.iterable.pretty_print_with_indent(indent + 1),
+                indentation,
+                for_in.body.pretty_print_with_indent(indent + 1)
+            ),
+            Stmt::Function(name, params, body, signature, _) => {
+                let params = signature.written_params(params);
                 let mut result = format!(
-                    "{}Function ({})\n{}├── Parameters: {}",
+                    "{}Function ({}){}\n{}├── Parameters: {}",
                     indentation,
                     name,
+                    annotated(&signature.returns),
                     indentation,
                     params.join(", ")
                 );
@@ -129,7 +256,19 @@ impl Stmt {
                 }
                 result
             }
-            Stmt::Return(expr) => {
+            Stmt::Class(name, superclass, methods, _) => {
+                let superclass = superclass.as_ref().map(|superclass| format!(" < {}", superclass.lexeme)).unwrap_or_default();
+                let mut result = format!("{}Class ({}{})", indentation, name, superclass);
+                for method in methods {
+                    result.push_str(&format!(
+                        "\n{}├── {}",
+                        indentation,
+                        method.pretty_print_with_indent(indent + 1)
+                    ));
+                }
+                result
+            }
+            Stmt::Return(expr, _) => {
                 let expr_str = if let Some(expr) = expr {
                     expr.pretty_print_with_indent(indent + 1)
                 } else {
@@ -141,8 +280,25 @@ impl Stmt {
                     expr_str
                 )
             }
-            Stmt::Break => format!("{}Break", indentation),
-            Stmt::Continue => format!("{}Continue", indentation),
+            Stmt::ParallelAssign(names, values, _) => {
+                let names = names.iter().map(|(name, _)| name.lexeme.as_str()).collect::<Vec<_>>().join(", ");
+                let mut result = format!("{}ParallelAssign ({})", indentation, names);
+                for value in values {
+                    result.push_str(&format!(
+                        "\n{}├── {}",
+                        indentation,
+                        value.pretty_print_with_indent(indent + 1)
+                    ));
+                }
+                result
+            }
+            Stmt::Break(_) => format!("{}Break", indentation),
+            Stmt::Continue(_) => format!("{}Continue", indentation),
         }
     }
 }
+
+/// `: Type` for an annotated name, nothing otherwise.
+pub(crate) fn annotated(annotation: &Option<Token>) -> String {
+    annotation.as_ref().map(|name| format!(": {}", name.lexeme)).unwrap_or_default()
+}
@@ -0,0 +1,273 @@
+//! [proptest] strategies producing random, syntactically valid programs.
+//! Every tree they generate is one the parser can produce, so printing it
+//! with [`crate::printer::to_source`] and parsing the result gives the same
+//! tree back. Enabled with the `proptest` feature.
+
+use proptest::prelude::*;
+
+use crate::expr::{
+    BinaryExpr, BlockExpr, CallExpr, Expr, ExprId, GetExpr, IfExpr, IndexExpr, IndexSetExpr, LambdaExpr, LiteralExpr, LogicalExpr, MapExpr, SetExpr,
+    UnaryExpr,
+};
+use crate::stmt::{ForInStmt, ForStmt, Signature, Stmt};
+use crate::token::{Literal, Span, Token, TokenType};
+
+const NAMES: &[&str] = &["a", "b", "c", "f", "x", "y"];
+
+const TYPE_NAMES: &[&str] = &["Number", "String", "Boolean", "Nil", "Function", "Any"];
+
+const BINARY_OPERATORS: &[(TokenType, &str)] = &[
+    (TokenType::EqualEqual, "=="),
+    (TokenType::BangEqual, "!="),
+    (TokenType::Greater, ">"),
+    (TokenType::GreaterEqual, ">="),
+    (TokenType::Less, "<"),
+    (TokenType::LessEqual, "<="),
+    (TokenType::Plus, "+"),
+    (TokenType::Minus, "-"),
+    (TokenType::Star, "*"),
+    (TokenType::Slash, "/"),
+    (TokenType::Percent, "%"),
+    (TokenType::Ampersand, "&"),
+    (TokenType::Pipe, "|"),
+    (TokenType::Caret, "^"),
+    (TokenType::LessLess, "<<"),
+    (TokenType::GreaterGreater, ">>"),
+];
+
+const LOGICAL_OPERATORS: &[(TokenType, &str)] = &[
+    (TokenType::And, "and"),
+    (TokenType::Or, "or"),
+    (TokenType::QuestionQuestion, "??"),
+];
+
+const UNARY_OPERATORS: &[(TokenType, &str)] = &[(TokenType::Minus, "-"), (TokenType::Bang, "!"), (TokenType::Tilde, "~")];
+
+/// A token carrying only a type and lexeme, as generated trees have no
+/// source. Every span in a generated tree is empty for the same reason.
+fn token(token_type: TokenType, lexeme: &str) -> Token {
+    Token {
+        token_type,
+        lexeme: lexeme.to_string(),
+        literal: None,
+        line: 1,
+        column: 1,
+        span: Span::default(),
+        leading_trivia: Vec::new(),
+    }
+}
+
+fn name() -> impl Strategy<Value = String> {
+    proptest::sample::select(NAMES).prop_map(str::to_string)
+}
+
+fn identifier() -> impl Strategy<Value = Token> {
+    proptest::sample::select(NAMES).prop_map(|name| token(TokenType::Identifier, name))
+}
+
+/// A string literal token, as written for a map key.
+fn string_key() -> impl Strategy<Value = Token> {
+    "[a-z]{0,4}".prop_map(|key| Token {
+        literal: Some(Literal::String(key.clone())),
+        ..token(TokenType::String, &format!("\"{}\"", key))
+    })
+}
+
+/// An optional `: Type` annotation.
+fn annotation() -> impl Strategy<Value = Option<Token>> {
+    proptest::option::of(proptest::sample::select(TYPE_NAMES).prop_map(|name| token(TokenType::Identifier, name)))
+}
+
+fn operator(operators: &'static [(TokenType, &'static str)]) -> impl Strategy<Value = Token> {
+    proptest::sample::select(operators).prop_map(|(token_type, lexeme)| token(token_type, lexeme))
+}
+
+fn literal() -> impl Strategy<Value = LiteralExpr> {
+    prop_oneof![
+        (0u32..10_000).prop_map(|n| LiteralExpr::Number(n as f64)),
+        (0u32..10_000).prop_map(|n| LiteralExpr::Number(n as f64 / 8.0)),
+        "[a-z \\\\\"\n\t]{0,8}".prop_map(LiteralExpr::String),
+        any::<bool>().prop_map(LiteralExpr::Boolean),
+        Just(LiteralExpr::Nil),
+    ]
+}
+
+/// Statements that may appear inside a block expression, built from `expr`.
+fn simple_stmt(expr: BoxedStrategy<Expr>) -> impl Strategy<Value = Stmt> {
+    prop_oneof![
+        expr.clone().prop_map(|expr| Stmt::Expression(expr, Span::default())),
+        expr.clone().prop_map(|expr| Stmt::Print(expr, Span::default())),
+        (name(), annotation(), proptest::option::of(expr.clone()))
+            .prop_map(|(name, annotation, initializer)| Stmt::Var(name, annotation, initializer, Span::default())),
+        (name(), annotation(), expr).prop_map(|(name, annotation, value)| Stmt::Const(name, annotation, value, Span::default())),
+    ]
+}
+
+/// Random expressions of every kind the parser produces.
+pub fn expr() -> BoxedStrategy<Expr> {
+    let leaf = prop_oneof![literal().prop_map(|literal| Expr::Constant(literal, Span::default())), identifier().prop_map(|name| Expr::Variable(name, ExprId::fresh()))];
+    leaf.prop_recursive(4, 48, 4, |inner| {
+        prop_oneof![
+            (inner.clone(), operator(BINARY_OPERATORS), inner.clone())
+                .prop_map(|(left, operator, right)| Expr::Binary(Box::new(BinaryExpr { left, operator, right }))),
+            (inner.clone(), operator(LOGICAL_OPERATORS), inner.clone())
+                .prop_map(|(left, operator, right)| Expr::Logical(Box::new(LogicalExpr { left, operator, right }))),
+            (operator(UNARY_OPERATORS), inner.clone())
+                .prop_map(|(operator, right)| Expr::Unary(Box::new(UnaryExpr { operator, right }))),
+            inner.clone().prop_map(|expr| Expr::Grouping(Box::new(expr), Span::default())),
+            (identifier(), inner.clone()).prop_map(|(name, value)| Expr::Assign(name, Box::new(value), ExprId::fresh())),
+            (inner.clone(), proptest::collection::vec(inner.clone(), 0..3), any::<bool>()).prop_map(|(callee, arguments, spread)| {
+                // Only an argument can be spread.
+                let spread = spread && !arguments.is_empty();
+                Expr::Call(Box::new(CallExpr { callee, paren: token(TokenType::RightParen, ")"), arguments, spread }))
+            }),
+            (inner.clone(), identifier(), any::<bool>())
+                .prop_map(|(object, name, optional)| Expr::Get(Box::new(GetExpr { object, name, optional }))),
+            (inner.clone(), identifier(), inner.clone())
+                .prop_map(|(object, name, value)| Expr::Set(Box::new(SetExpr { object, name, value }))),
+            (inner.clone(), inner.clone()).prop_map(|(object, index)| {
+                Expr::Index(Box::new(IndexExpr { object, index, bracket: token(TokenType::RightBracket, "]") }))
+            }),
+            (inner.clone(), inner.clone(), inner.clone()).prop_map(|(object, index, value)| {
+                Expr::IndexSet(Box::new(IndexSetExpr { object, index, bracket: token(TokenType::RightBracket, "]"), value }))
+            }),
+            proptest::collection::vec((string_key(), inner.clone()), 0..3).prop_map(|entries| {
+                Expr::Map(Box::new(MapExpr { brace: token(TokenType::LeftBrace, "{"), entries, span: Span::default() }))
+            }),
+            (inner.clone(), inner.clone(), inner.clone()).prop_map(|(condition, then_branch, else_branch)| {
+                Expr::If(Box::new(IfExpr { keyword: token(TokenType::If, "if"), condition, then_branch, else_branch }))
+            }),
+            (proptest::collection::vec(name(), 0..3), inner.clone()).prop_map(|(params, body)| {
+                Expr::Lambda(Box::new(LambdaExpr {
+                    arrow: token(TokenType::Arrow, "->"),
+                    params,
+                    body: vec![Stmt::Return(Some(body), Span::default())],
+                    span: Span::default(),
+                }))
+            }),
+            // An empty block would read as an empty map.
+            (proptest::collection::vec(simple_stmt(inner.clone()), 0..3), proptest::option::of(inner))
+                .prop_filter("empty block", |(statements, value)| !statements.is_empty() || value.is_some())
+                .prop_map(|(statements, value)| {
+                    let brace = token(TokenType::LeftBrace, "{");
+                    Expr::Block(Box::new(BlockExpr { brace, statements, value, span: Span::default() }))
+                }),
+        ]
+    })
+    .boxed()
+}
+
+/// Random statements, including nested blocks, loops and functions. Jumps
+/// may land outside of any loop or function; [`program`] takes those out.
+pub fn stmt() -> BoxedStrategy<Stmt> {
+    let leaf = prop_oneof![
+        expr().prop_map(|expr| Stmt::Expression(expr, Span::default())),
+        expr().prop_map(|expr| Stmt::Print(expr, Span::default())),
+        (name(), annotation(), proptest::option::of(expr()))
+            .prop_map(|(name, annotation, initializer)| Stmt::Var(name, annotation, initializer, Span::default())),
+        (name(), annotation(), expr()).prop_map(|(name, annotation, value)| Stmt::Const(name, annotation, value, Span::default())),
+        Just(Stmt::Break(Span::default())),
+        Just(Stmt::Continue(Span::default())),
+        proptest::option::of(expr()).prop_map(|value| Stmt::Return(value, Span::default())),
+        // One target would read as a plain assignment.
+        (2usize..4).prop_flat_map(|count| {
+            (proptest::collection::vec(identifier(), count), proptest::collection::vec(expr(), count)).prop_map(|(names, values)| {
+                let names = names.into_iter().map(|name| (name, ExprId::fresh())).collect();
+                Stmt::ParallelAssign(names, values, Span::default())
+            })
+        }),
+    ];
+    leaf.prop_recursive(3, 24, 4, |inner| {
+        prop_oneof![
+            proptest::collection::vec(inner.clone(), 0..4).prop_map(|statements| Stmt::Block(statements, Span::default())),
+            (expr(), inner.clone(), proptest::option::of(inner.clone())).prop_map(
+                |(condition, then_branch, else_branch)| {
+                    Stmt::If(condition, Box::new(then_branch), else_branch.map(Box::new), Span::default())
+                }
+            ),
+            (expr(), inner.clone()).prop_map(|(condition, body)| {
+                Stmt::While(token(TokenType::While, "while"), condition, Box::new(body), Span::default())
+            }),
+            (
+                proptest::option::of(for_initializer()),
+                proptest::option::of(expr()),
+                proptest::option::of(expr()),
+                inner.clone(),
+            )
+                .prop_map(|(initializer, condition, increment, body)| {
+                    let keyword = token(TokenType::For, "for");
+                    Stmt::For(Box::new(ForStmt { keyword, initializer, condition, increment, body, span: Span::default() }))
+                }),
+            (identifier(), expr(), inner.clone()).prop_map(|(name, iterable, body)| {
+                let keyword = token(TokenType::For, "for");
+                Stmt::ForIn(Box::new(ForInStmt { keyword, name, iterable, body, span: Span::default() }))
+            }),
+            (
+                name(),
+                proptest::collection::vec((name(), annotation()), 0..3),
+                annotation(),
+                any::<bool>(),
+                proptest::collection::vec(inner, 0..4),
+            )
+                .prop_map(|(name, params, returns, rest, body)| {
+                    let (params, mut types): (Vec<_>, Vec<_>) = params.into_iter().unzip();
+                    // A rest parameter takes no annotation.
+                    let rest = rest && !params.is_empty();
+                    if rest {
+                        types.pop();
+                        types.push(None);
+                    }
+                    Stmt::Function(name, params, body, Signature { params: types, returns, rest }, Span::default())
+                }),
+        ]
+    })
+    .boxed()
+}
+
+/// The statements a `for` loop can start with.
+fn for_initializer() -> impl Strategy<Value = Stmt> {
+    prop_oneof![
+        (name(), annotation(), proptest::option::of(expr()))
+            .prop_map(|(name, annotation, initializer)| Stmt::Var(name, annotation, initializer, Span::default())),
+        expr().prop_map(|expr| Stmt::Expression(expr, Span::default())),
+    ]
+}
+
+/// Random whole programs.
+pub fn program() -> BoxedStrategy<Vec<Stmt>> {
+    proptest::collection::vec(stmt().prop_map(|stmt| place_jumps(stmt, false, false)), 0..6).boxed()
+}
+
+/// Replaces `break` and `continue` outside of a loop, and `return` outside
+/// of a function, which the parser rejects, with `nil;`.
+fn place_jumps(stmt: Stmt, in_loop: bool, in_function: bool) -> Stmt {
+    match stmt {
+        Stmt::Break(span) | Stmt::Continue(span) if !in_loop => Stmt::Expression(Expr::Constant(LiteralExpr::Nil, span), span),
+        Stmt::Return(_, span) if !in_function => Stmt::Expression(Expr::Constant(LiteralExpr::Nil, span), span),
+        Stmt::Block(statements, span) => {
+            Stmt::Block(statements.into_iter().map(|stmt| place_jumps(stmt, in_loop, in_function)).collect(), span)
+        }
+        Stmt::If(condition, then_branch, else_branch, span) => Stmt::If(
+            condition,
+            Box::new(place_jumps(*then_branch, in_loop, in_function)),
+            else_branch.map(|branch| Box::new(place_jumps(*branch, in_loop, in_function))),
+            span,
+        ),
+        Stmt::While(keyword, condition, body, span) => {
+            Stmt::While(keyword, condition, Box::new(place_jumps(*body, true, in_function)), span)
+        }
+        Stmt::For(for_stmt) => {
+            let ForStmt { body, .. } = *for_stmt;
+            Stmt::For(Box::new(ForStmt { body: place_jumps(body, true, in_function), ..*for_stmt }))
+        }
+        Stmt::ForIn(for_in) => {
+            let ForInStmt { body, .. } = *for_in;
+            Stmt::ForIn(Box::new(ForInStmt { body: place_jumps(body, true, in_function), ..*for_in }))
+        }
+        Stmt::Function(name, params, body, signature, span) => {
+            let body = body.into_iter().map(|stmt| place_jumps(stmt, false, true)).collect();
+            Stmt::Function(name, params, body, signature, span)
+        }
+        stmt => stmt,
+    }
+}
@@ -0,0 +1,313 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, LazyLock, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::callable::{LoxCallable, LoxFunction};
+use crate::environ::Environment;
+use crate::error::EvalError;
+use crate::value::Value;
+use crate::interpreter::{interpret_with_env, Context, LanguageOptions};
+use crate::natives::{define_native_functions_where, Capabilities};
+use crate::printer::to_source;
+use crate::resolver::Resolver;
+use crate::stmt::{Signature, Stmt};
+use crate::token::Span;
+use crate::parse_program;
+
+/// Tasks started with `spawn` and channels made with `chan`, by handle.
+/// Unlike sockets they are shared by every thread, since a channel made in
+/// one task is used from others.
+static TASKS: LazyLock<Mutex<HashMap<usize, JoinHandle<TaskOutcome>>>> = LazyLock::new(Mutex::default);
+static CHANNELS: LazyLock<Mutex<HashMap<usize, Arc<Channel>>>> = LazyLock::new(Mutex::default);
+static NEXT_HANDLE: AtomicUsize = AtomicUsize::new(1);
+
+/// A runtime value copied out of one interpreter so that it can be moved to
+/// another thread. Functions cannot be sent; see [`Task`] for how a spawned
+/// function gets to its thread.
+#[derive(Debug)]
+enum TaskValue {
+    Number(f64),
+    String(String),
+    Boolean(bool),
+    Map(BTreeMap<String, TaskValue>),
+    List(Vec<TaskValue>),
+    Nil,
+    #[cfg(feature = "bignum")]
+    BigNumber(num_rational::BigRational),
+}
+
+impl TaskValue {
+    fn copy_of(value: &Value) -> Result<TaskValue, EvalError> {
+        TaskValue::copy_within(value, &mut Vec::new())
+    }
+
+    /// [`TaskValue::copy_of`] for a value nested in the maps and lists in
+    /// `open`, which are being copied around it.
+    fn copy_within(value: &Value, open: &mut Vec<*const ()>) -> Result<TaskValue, EvalError> {
+        Ok(match value {
+            Value::Number(n) => TaskValue::Number(*n),
+            Value::String(s) => TaskValue::String(s.clone()),
+            Value::Boolean(b) => TaskValue::Boolean(*b),
+            Value::Map(map) => {
+                enter(open, Rc::as_ptr(map) as *const ())?;
+                let entries = map
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), TaskValue::copy_within(value, open)?)))
+                    .collect::<Result<_, EvalError>>();
+                open.pop();
+                TaskValue::Map(entries?)
+            }
+            Value::List(list) => {
+                enter(open, Rc::as_ptr(list) as *const ())?;
+                let items = list.borrow().iter().map(|item| TaskValue::copy_within(item, open)).collect::<Result<_, _>>();
+                open.pop();
+                TaskValue::List(items?)
+            }
+            Value::Nil => TaskValue::Nil,
+            #[cfg(feature = "bignum")]
+            Value::BigNumber(n) => TaskValue::BigNumber((**n).clone()),
+            Value::Callable(_) => {
+                return Err(EvalError::TypeError("Functions cannot be passed between tasks".to_string()))
+            }
+            Value::Instance(_) => {
+                return Err(EvalError::TypeError("Instances cannot be passed between tasks".to_string()))
+            }
+        })
+    }
+
+    fn into_literal(self) -> Value {
+        match self {
+            TaskValue::Number(n) => Value::Number(n),
+            TaskValue::String(s) => Value::String(s),
+            TaskValue::Boolean(b) => Value::Boolean(b),
+            TaskValue::Map(map) => Value::map(map.into_iter().map(|(key, value)| (key, value.into_literal())).collect()),
+            TaskValue::List(list) => Value::list(list.into_iter().map(TaskValue::into_literal).collect()),
+            TaskValue::Nil => Value::Nil,
+            #[cfg(feature = "bignum")]
+            TaskValue::BigNumber(n) => Value::BigNumber(Rc::new(n)),
+        }
+    }
+}
+
+/// Adds the map or list `id` to those being copied, failing if it is
+/// already among them: a copy of a collection that holds itself never ends.
+fn enter(open: &mut Vec<*const ()>, id: *const ()) -> Result<(), EvalError> {
+    if open.contains(&id) {
+        return Err(EvalError::TypeError("A map or list that holds itself cannot be passed between tasks".to_string()));
+    }
+    open.push(id);
+    Ok(())
+}
+
+/// Everything a spawned function needs to run on another thread. Syntax
+/// trees and environments hold `Rc`s, so the function and the functions it
+/// can see travel as source code and are parsed again by the task, and the
+/// other variables it can see are copied.
+struct Task {
+    /// The spawned function, as a declaration.
+    entry: String,
+    /// Declarations of the other functions visible to it.
+    functions: String,
+    values: Vec<(String, TaskValue)>,
+    /// Names of the natives visible to it, so a task can do no more than its spawner.
+    natives: HashSet<String>,
+    options: LanguageOptions,
+    fuel: Option<u64>,
+    max_call_depth: usize,
+    max_loop_iterations: Option<u64>,
+}
+
+/// What a task printed and the value its function returned, or why it failed.
+struct TaskOutcome {
+    output: String,
+    result: Result<TaskValue, String>,
+}
+
+impl Task {
+    fn new(function: &LoxFunction, context: &Context) -> Task {
+        let mut functions = Vec::new();
+        let mut values = Vec::new();
+        let mut natives = HashSet::new();
+
+        // Innermost bindings first, so shadowed names keep the value the
+        // function sees. Functions found along the way bring what their own
+        // closures see, for names not yet taken.
+        let mut seen = HashSet::new();
+        let mut closures = VecDeque::from([function.closure.clone()]);
+        while let Some(closure) = closures.pop_front() {
+            let mut environment = Some(closure);
+            while let Some(current) = environment {
+                for (name, value) in current.borrow().variables() {
+                    if !seen.insert(name.to_string()) {
+                        continue;
+                    }
+                    match value {
+                        Value::Callable(callable) => match callable.as_function() {
+                            Some(function) => {
+                                functions.push(declaration(name, function));
+                                closures.push_back(function.closure.clone());
+                            }
+                            None => {
+                                natives.insert(callable.name().to_string());
+                            }
+                        },
+                        // Values the task cannot use are left out; using them fails as undefined.
+                        value => values.extend(TaskValue::copy_of(value).ok().map(|value| (name.to_string(), value))),
+                    }
+                }
+                environment = current.borrow().enclosing.clone();
+            }
+        }
+
+        Task {
+            entry: to_source(&[declaration(&function.name, function)]),
+            functions: to_source(&functions),
+            values,
+            natives,
+            options: context.options,
+            fuel: context.fuel,
+            max_call_depth: context.max_call_depth,
+            max_loop_iterations: context.max_loop_iterations,
+        }
+    }
+
+    /// Runs the task in fresh globals holding the copied variables and
+    /// functions, calling the spawned function last.
+    fn run(self) -> TaskOutcome {
+        let mut context = Context::new(self.options);
+        context.fuel = self.fuel;
+        context.max_call_depth = self.max_call_depth;
+        context.max_loop_iterations = self.max_loop_iterations;
+
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let every_capability = Capabilities { exec: true, net: true, fs: true };
+        define_native_functions_where(&mut globals.borrow_mut(), every_capability, |name| self.natives.contains(name));
+        for (name, value) in self.values {
+            globals.borrow_mut().define(name, value.into_literal());
+        }
+
+        let result = Task::call(&self.entry, &self.functions, globals, &mut context);
+        TaskOutcome { output: context.output, result }
+    }
+
+    fn call(entry: &str, functions: &str, globals: Rc<RefCell<Environment>>, context: &mut Context) -> Result<TaskValue, String> {
+        // Both were printed from parsed programs, so they parse again.
+        let functions = parse_program(functions).map_err(|_| "Failed to copy the task's functions".to_string())?;
+        let entry = parse_program(entry).map_err(|_| "Failed to copy the task's function".to_string())?;
+        let mut resolver = Resolver::new();
+        resolver.resolve(&functions);
+        resolver.resolve(&entry);
+        let Some(Stmt::Function(name, params, body, _, _)) = entry.into_iter().next() else {
+            return Err("Failed to copy the task's function".to_string());
+        };
+
+        interpret_with_env(&functions, Some(globals.clone()), &resolver, context).map_err(|e| e.to_string())?;
+        let function = LoxFunction::new(name, params, body, globals.clone());
+        let value = function.call(Vec::new(), globals, &resolver, context).map_err(|e| e.to_string())?;
+        TaskValue::copy_of(&value).map_err(|e| e.to_string())
+    }
+}
+
+/// A function declaration with the body of `function`, named `name`.
+fn declaration(name: &str, function: &LoxFunction) -> Stmt {
+    let signature = Signature { rest: function.rest, ..Signature::default() };
+    Stmt::Function(name.to_string(), function.params.clone(), function.body.clone(), signature, Span::default())
+}
+
+fn handle_arg(args: &[Value], kind: &str) -> Result<usize, EvalError> {
+    match args[0] {
+        Value::Number(handle) => Ok(handle as usize),
+        ref other => Err(EvalError::TypeError(format!("Expected a {} handle but got {}", kind, other.type_name()))),
+    }
+}
+
+fn next_handle() -> usize {
+    NEXT_HANDLE.fetch_add(1, Ordering::Relaxed)
+}
+
+/// `spawn(function)` runs a function taking no arguments on a new thread
+/// and returns a handle for `join`. The task gets copies of the variables
+/// the function can see, so changes on either side stay on that side; use
+/// channels to talk to it.
+pub fn spawn(args: Vec<Value>, context: &mut Context) -> Result<Value, EvalError> {
+    let function = match &args[0] {
+        Value::Callable(callable) => callable.as_function().filter(|function| function.params.is_empty()),
+        _ => None,
+    }
+    .ok_or_else(|| EvalError::TypeError("spawn expects a function taking no arguments".to_string()))?;
+
+    let task = Task::new(function, context);
+    let thread = thread::Builder::new()
+        .spawn(move || task.run())
+        .map_err(|e| EvalError::NativeError(format!("Failed to start a task: {}", e)))?;
+    let handle = next_handle();
+    TASKS.lock().unwrap_or_else(|e| e.into_inner()).insert(handle, thread);
+    Ok(Value::Number(handle as f64))
+}
+
+/// `join(task)` waits for a task to finish, adds what it printed to the
+/// output and returns what its function returned. A task that failed
+/// fails the join with its error.
+pub fn join(args: Vec<Value>, context: &mut Context) -> Result<Value, EvalError> {
+    let handle = handle_arg(&args, "task")?;
+    let thread = TASKS.lock().unwrap_or_else(|e| e.into_inner()).remove(&handle);
+    let thread = thread.ok_or_else(|| EvalError::NativeError(format!("No running task with handle {}", handle)))?;
+    let outcome = thread
+        .join()
+        .map_err(|_| EvalError::NativeError(format!("Task {} crashed", handle)))?;
+    context.output.push_str(&outcome.output);
+    match outcome.result {
+        Ok(value) => Ok(value.into_literal()),
+        Err(message) => Err(EvalError::NativeError(format!("Task {} failed: {}", handle, message.trim_end_matches('.')))),
+    }
+}
+
+/// An unbounded queue of values between tasks.
+#[derive(Default)]
+struct Channel {
+    values: Mutex<VecDeque<TaskValue>>,
+    sent: Condvar,
+}
+
+fn with_channel<T>(args: &[Value], f: impl FnOnce(&Channel) -> Result<T, EvalError>) -> Result<T, EvalError> {
+    let handle = handle_arg(args, "channel")?;
+    let channel = CHANNELS.lock().unwrap_or_else(|e| e.into_inner()).get(&handle).cloned();
+    match channel {
+        Some(channel) => f(&channel),
+        None => Err(EvalError::NativeError(format!("No channel with handle {}", handle))),
+    }
+}
+
+/// `chan()` makes a channel and returns its handle.
+pub fn chan(_args: Vec<Value>) -> Result<Value, EvalError> {
+    let handle = next_handle();
+    CHANNELS.lock().unwrap_or_else(|e| e.into_inner()).insert(handle, Arc::default());
+    Ok(Value::Number(handle as f64))
+}
+
+/// `send(channel, value)` queues a copy of a value without waiting for it to be received.
+pub fn send(args: Vec<Value>) -> Result<Value, EvalError> {
+    let value = TaskValue::copy_of(&args[1])?;
+    with_channel(&args, |channel| {
+        channel.values.lock().unwrap_or_else(|e| e.into_inner()).push_back(value);
+        channel.sent.notify_one();
+        Ok(Value::Nil)
+    })
+}
+
+/// `recv(channel)` takes the oldest value off a channel, waiting for one to be sent if it is empty.
+pub fn recv(args: Vec<Value>) -> Result<Value, EvalError> {
+    with_channel(&args, |channel| {
+        let mut values = channel.values.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(value) = values.pop_front() {
+                return Ok(value.into_literal());
+            }
+            values = channel.sent.wait(values).unwrap_or_else(|e| e.into_inner());
+        }
+    })
+}
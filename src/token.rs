@@ -1,17 +1,24 @@
+use std::fmt;
+use std::ops::Range;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     // Single-character tokens.
     LeftParen, RightParen,
     LeftBrace, RightBrace,
+    LeftBracket, RightBracket,
     Comma, Dot, Minus, Plus,
     Semicolon, Slash, Star,
-    Percent,
+    Percent, Colon,
+    Ampersand, Pipe, Caret, Tilde,
 
     // One or two character tokens.
     Bang, BangEqual,
     Equal, EqualEqual,
-    Greater, GreaterEqual,
-    Less, LessEqual,
+    Greater, GreaterEqual, GreaterGreater,
+    Less, LessEqual, LessLess,
+    QuestionQuestion, QuestionDot, DotDotDot,
+    Arrow,
 
     // Literals.
     Identifier, String, Number,
@@ -19,15 +26,88 @@ pub enum TokenType {
     // Keywords.
     And, Class, Else, False, Fun, For, If, Nil, Or,
     Print, Return, Super, This, True, Var, While,
-    Break, Continue,
+    Break, Continue, In, Const,
+
+    // Input the lexer could not tokenize, with a message describing why.
+    Error(String),
 
     // End of file.
     Eof,
 }
 
+impl TokenType {
+    /// Whether this is a reserved word that cannot be used as an identifier.
+    pub fn is_keyword(&self) -> bool {
+        matches!(
+            self,
+            TokenType::And | TokenType::Class | TokenType::Else | TokenType::False
+                | TokenType::Fun | TokenType::For | TokenType::If | TokenType::Nil
+                | TokenType::Or | TokenType::Print | TokenType::Return | TokenType::Super
+                | TokenType::This | TokenType::True | TokenType::Var | TokenType::While
+                | TokenType::Break | TokenType::Continue | TokenType::In
+                | TokenType::Const
+        )
+    }
+}
+
+/// The value of a literal token, decoded once by the lexer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Number(f64),
+    String(String),
+}
+
+/// Source text between tokens, kept when lexing in trivia mode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trivia {
+    /// A run of spaces, tabs and line breaks.
+    Whitespace(String),
+    /// A comment, including its delimiters.
+    Comment(String),
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
+    pub literal: Option<Literal>,
     pub line: usize,
+    /// Column of the lexeme's first character on its line, counting
+    /// characters from 1.
+    pub column: usize,
+    /// Byte range of the lexeme in the source.
+    pub span: Span,
+    /// Whitespace and comments preceding the token; empty unless the lexer
+    /// runs in trivia mode.
+    pub leading_trivia: Vec<Trivia>,
+}
+
+/// A byte range in the source, from `start` up to but not including `end`.
+/// Tokens and syntax tree nodes carry one so tools can map them back to the
+/// exact text they came from.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// The span from the start of this one to the end of `last`.
+    pub fn to(self, last: Span) -> Span {
+        Span { start: self.start, end: last.end }
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
 }
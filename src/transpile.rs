@@ -0,0 +1,554 @@
+use crate::expr::{Expr, LiteralExpr};
+use crate::stmt::{ForInStmt, ForStmt, Stmt};
+use crate::token::{Literal, Token, TokenType};
+
+/// Runtime helpers every transpiled program relies on. They reproduce the
+/// Lox semantics that JavaScript does not share (truthiness, printing, nil).
+const JS_PRELUDE: &str = r#"const lox = {
+    truthy(value) {
+        return value !== null && value !== false;
+    },
+    stringify(value) {
+        if (value === null) return "nil";
+        if (typeof value === "function") return `<fn ${value.name || "lambda"}>`;
+        if (value instanceof Map) {
+            const entries = [...value].sort(([a], [b]) => (a < b ? -1 : a > b ? 1 : 0));
+            return `{${entries.map(([k, v]) => `${k}: ${lox.element(v)}`).join(", ")}}`;
+        }
+        if (typeof value === "object") return `${value.constructor.name} instance`;
+        return String(value);
+    },
+    element(value) {
+        return typeof value === "string" ? JSON.stringify(value) : lox.stringify(value);
+    },
+    methods: {
+        string: {
+            len: (s) => [...s].length,
+            upper: (s) => s.toUpperCase(),
+            lower: (s) => s.toLowerCase(),
+            trim: (s) => s.trim(),
+            contains: (s, needle) => s.includes(needle),
+        },
+        number: {
+            floor: Math.floor,
+            ceil: Math.ceil,
+            round: Math.round,
+            abs: Math.abs,
+        },
+    },
+    equal(left, right) {
+        if (left instanceof Map && right instanceof Map) {
+            return left.size === right.size && [...left].every(([k, v]) => right.has(k) && lox.equal(v, right.get(k)));
+        }
+        return left === right;
+    },
+    multiply(left, right) {
+        if (typeof left === "string" || typeof right === "string") {
+            const [s, count] = typeof left === "string" ? [left, right] : [right, left];
+            if (typeof count !== "number" || typeof s !== "string") throw new TypeError("Unsupported operation for mixed types");
+            if (!Number.isInteger(count) || count < 0) throw new TypeError(`A string can only be repeated a whole number of times, not ${count}`);
+            return s.repeat(count);
+        }
+        return left * right;
+    },
+    bitwise(operator, left, right) {
+        // JavaScript's own bitwise operators work on 32 bits; Lox's on 64.
+        if (!Number.isInteger(left) || !Number.isInteger(right)) throw new TypeError("Bitwise operands must be whole numbers");
+        const [l, r] = [BigInt(left), BigInt(right)];
+        const results = { "&": () => l & r, "|": () => l | r, "^": () => l ^ r, "<<": () => l << r, ">>": () => l >> r };
+        return Number(BigInt.asIntN(64, results[operator]()));
+    },
+    get(object, name) {
+        if (object instanceof Map) return object.has(name) ? object.get(name) : null;
+        if (object !== null && typeof object === "object") {
+            if (!(name in object)) throw new TypeError(`${object.constructor.name} instance has no property '${name}'`);
+            const value = object[name];
+            return typeof value === "function" ? value.bind(object) : value;
+        }
+        const type = object === null ? "nil" : typeof object;
+        const method = (lox.methods[type] || {})[name];
+        if (method === undefined) throw new TypeError(`${type} has no method '${name}'`);
+        return (...args) => method(object, ...args);
+    },
+    getOptional(object, name) {
+        return object === null ? null : lox.get(object, name);
+    },
+    index(object, key) {
+        if (object instanceof Map) {
+            if (typeof key !== "string") throw new TypeError("Map keys must be strings");
+            return object.has(key) ? object.get(key) : null;
+        }
+        if (Array.isArray(object)) {
+            if (!Number.isInteger(key) || key < 0 || key >= object.length) throw new TypeError(`List index ${key} is out of range`);
+            return object[key];
+        }
+        throw new TypeError("Can only index maps and lists");
+    },
+    indexSet(object, key, value) {
+        if (object instanceof Map) {
+            if (typeof key !== "string") throw new TypeError("Map keys must be strings");
+            object.set(key, value);
+            return value;
+        }
+        if (Array.isArray(object)) {
+            if (!Number.isInteger(key) || key < 0 || key >= object.length) throw new TypeError(`List index ${key} is out of range`);
+            object[key] = value;
+            return value;
+        }
+        throw new TypeError("Can only index maps and lists");
+    },
+    iterate(value) {
+        if (Array.isArray(value)) return [...value];
+        if (value instanceof Map) return [...value.keys()].sort();
+        throw new TypeError("Can only iterate over lists and maps");
+    },
+    set(object, name, value) {
+        if (object === null || typeof object !== "object") throw new TypeError("Only instances have fields");
+        object[name] = value;
+        return value;
+    },
+    class(cls) {
+        return new Proxy(cls, {
+            apply(target, _, args) {
+                const instance = new target();
+                if (typeof instance.init === "function") instance.init(...args);
+                return instance;
+            },
+        });
+    },
+};
+
+function clock() {
+    return Date.now() / 1000;
+}
+
+function range(start, end) {
+    const numbers = [];
+    for (let n = start; n < end; n++) numbers.push(n);
+    return numbers;
+}
+"#;
+
+/// Words that are valid Lox identifiers but reserved in JavaScript.
+const JS_RESERVED: &[&str] = &[
+    "await", "case", "catch", "debugger", "default", "delete", "do",
+    "enum", "export", "extends", "finally", "function", "import",
+    "instanceof", "let", "new", "null", "static", "switch", "throw", "try",
+    "typeof", "undefined", "void", "with", "yield", "lox",
+];
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Translates a parsed Lox program into readable JavaScript source.
+pub fn to_javascript(statements: &[Stmt]) -> String {
+    emit_javascript(statements).output
+}
+
+/// Translates a program into JavaScript and a version 3 source map pointing
+/// each generated line back at the Lox line it came from.
+pub fn to_javascript_with_source_map(statements: &[Stmt], source_name: &str, file_name: &str) -> (String, String) {
+    let emitter = emit_javascript(statements);
+    let source_map = format!(
+        "{{\"version\":3,\"file\":{:?},\"sources\":[{:?}],\"names\":[],\"mappings\":\"{}\"}}",
+        file_name,
+        source_name,
+        encode_mappings(&emitter.mappings)
+    );
+    (emitter.output, source_map)
+}
+
+fn emit_javascript(statements: &[Stmt]) -> JsEmitter {
+    let mut emitter = JsEmitter::new();
+    for prelude_line in JS_PRELUDE.lines() {
+        emitter.output.push_str(prelude_line);
+        emitter.output.push('\n');
+        emitter.mappings.push(None);
+    }
+    emitter.output.push('\n');
+    emitter.mappings.push(None);
+    for statement in statements {
+        emitter.emit_stmt(statement);
+    }
+    emitter
+}
+
+struct JsEmitter {
+    output: String,
+    indent: usize,
+    /// Lox line of the statement currently being emitted.
+    source_line: usize,
+    /// Source line for every generated line; `None` for prelude code.
+    mappings: Vec<Option<usize>>,
+}
+
+impl JsEmitter {
+    fn new() -> Self {
+        JsEmitter {
+            output: String::new(),
+            indent: 0,
+            source_line: 1,
+            mappings: Vec::new(),
+        }
+    }
+
+    /// Writes a line at the current indentation level. Text spanning several
+    /// lines, such as a function expression, maps all of them to the statement.
+    fn line(&mut self, text: &str) {
+        self.output.push_str(&"    ".repeat(self.indent));
+        self.output.push_str(text);
+        self.output.push('\n');
+        for _ in 0..=text.matches('\n').count() {
+            self.mappings.push(Some(self.source_line));
+        }
+    }
+
+    /// Emits the statements of a block body one level deeper.
+    fn emit_body(&mut self, statements: &[Stmt]) {
+        self.indent += 1;
+        for statement in statements {
+            self.emit_stmt(statement);
+        }
+        self.indent -= 1;
+    }
+
+    /// Emits a statement used as the body of `if`/`while`, always braced.
+    fn emit_branch(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block(statements, _) => self.emit_body(statements),
+            other => self.emit_body(std::slice::from_ref(other)),
+        }
+    }
+
+    fn emit_stmt(&mut self, stmt: &Stmt) {
+        if let Some(line) = stmt_line(stmt) {
+            self.source_line = line;
+        }
+        match stmt {
+            Stmt::Expression(expr, _) => {
+                let code = format!("{};", self.expr(expr));
+                self.line(&code);
+            }
+            Stmt::Print(expr, _) => {
+                let code = format!("console.log(lox.stringify({}));", self.expr(expr));
+                self.line(&code);
+            }
+            Stmt::Var(name, _, initializer, _) => {
+                let code = match initializer {
+                    Some(expr) => format!("let {} = {};", identifier(name), self.expr(expr)),
+                    None => format!("let {} = null;", identifier(name)),
+                };
+                self.line(&code);
+            }
+            Stmt::Const(name, _, value, _) => {
+                let code = format!("const {} = {};", identifier(name), self.expr(value));
+                self.line(&code);
+            }
+            Stmt::Block(statements, _) => {
+                self.line("{");
+                self.emit_body(statements);
+                self.line("}");
+            }
+            Stmt::If(condition, then_branch, else_branch, _) => {
+                let code = format!("if (lox.truthy({})) {{", self.expr(condition));
+                self.line(&code);
+                self.emit_branch(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.line("} else {");
+                    self.emit_branch(else_branch);
+                }
+                self.line("}");
+            }
+            Stmt::While(_, condition, body, _) => {
+                let code = format!("while (lox.truthy({})) {{", self.expr(condition));
+                self.line(&code);
+                self.emit_branch(body);
+                self.line("}");
+            }
+            Stmt::For(for_stmt) => {
+                let ForStmt { initializer, condition, increment, body, .. } = for_stmt.as_ref();
+                // Declared outside the loop header, the variable is one binding
+                // shared by every iteration, as in Lox, rather than one per iteration.
+                self.line("{");
+                self.indent += 1;
+                if let Some(initializer) = initializer {
+                    self.emit_stmt(initializer);
+                }
+                let condition = condition.as_ref().map(|condition| format!("lox.truthy({})", self.expr(condition))).unwrap_or_default();
+                let increment = increment.as_ref().map(|increment| self.expr(increment)).unwrap_or_default();
+                self.line(&format!("for (; {}; {}) {{", condition, increment));
+                self.emit_branch(body);
+                self.line("}");
+                self.indent -= 1;
+                self.line("}");
+            }
+            Stmt::ForIn(for_in) => {
+                let ForInStmt { name, iterable, body, .. } = for_in.as_ref();
+                // `let` gives each iteration its own binding, as in Lox.
+                let code = format!("for (let {} of lox.iterate({})) {{", identifier(&name.lexeme), self.expr(iterable));
+                self.line(&code);
+                self.emit_branch(body);
+                self.line("}");
+            }
+            Stmt::ParallelAssign(names, values, _) => {
+                let names = names.iter().map(|(name, _)| identifier(&name.lexeme)).collect::<Vec<_>>().join(", ");
+                let values = values.iter().map(|value| self.expr(value)).collect::<Vec<_>>().join(", ");
+                let code = format!("[{}] = [{}];", names, values);
+                self.line(&code);
+            }
+            Stmt::Break(_) => self.line("break;"),
+            Stmt::Continue(_) => self.line("continue;"),
+            Stmt::Function(name, params, body, signature, _) => {
+                let code = format!("function {}({}) {{", identifier(name), parameters(params, signature.rest));
+                self.line(&code);
+                self.emit_body(body);
+                self.line("}");
+            }
+            Stmt::Class(name, superclass, methods, _) => {
+                // Lox classes are called like functions to make instances.
+                let extends = superclass.as_ref().map(|superclass| format!(" extends {}", identifier(&superclass.lexeme))).unwrap_or_default();
+                let code = format!("const {} = lox.class(class {}{} {{", identifier(name), identifier(name), extends);
+                self.line(&code);
+                self.indent += 1;
+                for method in methods {
+                    if let Stmt::Function(name, params, body, signature, _) = method {
+                        let code = format!("{}({}) {{", identifier(name), parameters(params, signature.rest));
+                        self.line(&code);
+                        // Functions declared in the method have their own
+                        // `this`, so they reach the instance through `$this`,
+                        // a name no Lox identifier can take.
+                        self.line("    const $this = this;");
+                        if superclass.is_some() {
+                            self.line("    const $super = (name) => super[name].bind($this);");
+                        }
+                        self.emit_body(body);
+                        self.line("}");
+                    }
+                }
+                self.indent -= 1;
+                self.line("});");
+            }
+            Stmt::Return(value, _) => {
+                let code = match value {
+                    Some(expr) => format!("return {};", self.expr(expr)),
+                    None => "return null;".to_string(),
+                };
+                self.line(&code);
+            }
+        }
+    }
+
+    fn expr(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Constant(literal, _) => literal_to_js(literal),
+            Expr::Grouping(inner, _) => format!("({})", self.expr(inner)),
+            Expr::Variable(name, _) => identifier(&name.lexeme),
+            Expr::This(_) => "$this".to_string(),
+            Expr::Super(_, method) => format!("$super({:?})", method.lexeme),
+            Expr::Assign(name, value, _) => format!("{} = {}", identifier(&name.lexeme), self.expr(value)),
+            Expr::Unary(unary) => match unary.operator.token_type {
+                TokenType::Bang => format!("!lox.truthy({})", self.expr(&unary.right)),
+                TokenType::Tilde => format!("lox.bitwise(\"^\", {}, -1)", self.expr(&unary.right)),
+                _ => format!("{}{}", unary.operator.lexeme, self.expr(&unary.right)),
+            },
+            Expr::Binary(binary) => {
+                let (left, right) = (self.expr(&binary.left), self.expr(&binary.right));
+                match binary.operator.token_type {
+                    // Maps compare by their contents, as in the interpreter.
+                    TokenType::EqualEqual => format!("lox.equal({}, {})", left, right),
+                    TokenType::BangEqual => format!("!lox.equal({}, {})", left, right),
+                    // `*` also repeats strings.
+                    TokenType::Star => format!("lox.multiply({}, {})", left, right),
+                    TokenType::Ampersand | TokenType::Pipe | TokenType::Caret | TokenType::LessLess | TokenType::GreaterGreater => {
+                        format!("lox.bitwise({:?}, {}, {})", binary.operator.lexeme, left, right)
+                    }
+                    _ => format!("{} {} {}", left, binary.operator.lexeme, right),
+                }
+            }
+            Expr::Logical(logical) => {
+                // Lox truthiness differs from JavaScript's (0 and "" are truthy),
+                // so `and`/`or` go through lox.truthy while keeping operand values.
+                let left = self.expr(&logical.left);
+                let right = self.expr(&logical.right);
+                match logical.operator.token_type {
+                    TokenType::Or => format!("((l) => lox.truthy(l) ? l : {})({})", right, left),
+                    TokenType::QuestionQuestion => format!("({} ?? {})", left, right),
+                    _ => format!("((l) => lox.truthy(l) ? {} : l)({})", right, left),
+                }
+            }
+            Expr::Call(call) => {
+                let mut arguments = call.arguments.iter().map(|a| self.expr(a)).collect::<Vec<_>>();
+                if let Some(last) = arguments.last_mut().filter(|_| call.spread) {
+                    last.insert_str(0, "...");
+                }
+                let arguments = arguments.join(", ");
+                if call.callee.is_optional_get() {
+                    format!("({}?.({}) ?? null)", self.expr(&call.callee), arguments)
+                } else {
+                    format!("{}({})", self.expr(&call.callee), arguments)
+                }
+            }
+            Expr::Get(get) => {
+                let helper = if get.optional { "getOptional" } else { "get" };
+                format!("lox.{}({}, {:?})", helper, self.expr(&get.object), get.name.lexeme)
+            }
+            Expr::Set(set) => {
+                format!("lox.set({}, {:?}, {})", self.expr(&set.object), set.name.lexeme, self.expr(&set.value))
+            }
+            Expr::Index(index) => format!("lox.index({}, {})", self.expr(&index.object), self.expr(&index.index)),
+            Expr::IndexSet(set) => {
+                format!("lox.indexSet({}, {}, {})", self.expr(&set.object), self.expr(&set.index), self.expr(&set.value))
+            }
+            Expr::Map(map) => {
+                let entries = map.entries.iter().map(|(key, value)| format!("[{}, {}]", string_key(key), self.expr(value)));
+                format!("new Map([{}])", entries.collect::<Vec<_>>().join(", "))
+            }
+            Expr::Lambda(lambda) => {
+                let params = lambda.params.iter().map(|p| identifier(p)).collect::<Vec<_>>().join(", ");
+                match lambda.body.as_slice() {
+                    [Stmt::Return(Some(body), _)] => format!("(({}) => {})", params, self.expr(body)),
+                    body => {
+                        let mut emitter = JsEmitter::new();
+                        emitter.indent = self.indent + 1;
+                        for statement in body {
+                            emitter.emit_stmt(statement);
+                        }
+                        format!("(function ({}) {{\n{}{}}})", params, emitter.output, "    ".repeat(self.indent))
+                    }
+                }
+            }
+            Expr::Block(block) => {
+                // Emitted as an immediately invoked arrow function so the
+                // statements get their own scope.
+                let mut emitter = JsEmitter::new();
+                emitter.indent = self.indent + 1;
+                for statement in &block.statements {
+                    emitter.emit_stmt(statement);
+                }
+                let value = match &block.value {
+                    Some(value) => emitter.expr(value),
+                    None => "null".to_string(),
+                };
+                emitter.line(&format!("return {};", value));
+                format!("(() => {{\n{}{}}})()", emitter.output, "    ".repeat(self.indent))
+            }
+            Expr::If(if_expr) => format!(
+                "(lox.truthy({}) ? {} : {})",
+                self.expr(&if_expr.condition),
+                self.expr(&if_expr.then_branch),
+                self.expr(&if_expr.else_branch)
+            ),
+        }
+    }
+}
+
+/// A map key as a JS string, written from its decoded value so escapes
+/// come out the JS way.
+fn string_key(key: &Token) -> String {
+    match &key.literal {
+        Some(Literal::String(s)) => format!("{:?}", s),
+        _ => key.lexeme.clone(),
+    }
+}
+
+fn literal_to_js(literal: &LiteralExpr) -> String {
+    match literal {
+        LiteralExpr::Number(n) => format!("{}", n),
+        LiteralExpr::String(s) => format!("{:?}", s),
+        LiteralExpr::Boolean(b) => format!("{}", b),
+        LiteralExpr::Nil => "null".to_string(),
+    }
+}
+
+/// A function's parameter list, the last collecting the remaining
+/// arguments if `rest` is set, as in Lox.
+fn parameters(params: &[String], rest: bool) -> String {
+    let mut params = params.iter().map(|p| identifier(p)).collect::<Vec<_>>();
+    if let Some(last) = params.last_mut().filter(|_| rest) {
+        last.insert_str(0, "...");
+    }
+    params.join(", ")
+}
+
+/// Renames Lox identifiers that would clash with JavaScript keywords.
+fn identifier(name: &str) -> String {
+    if JS_RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Finds the first source line referenced by a statement. Spans hold byte
+/// offsets rather than lines, so this looks at the tokens it contains.
+fn stmt_line(stmt: &Stmt) -> Option<usize> {
+    match stmt {
+        Stmt::Expression(expr, _) | Stmt::Print(expr, _) => expr_line(expr),
+        Stmt::Var(_, _, initializer, _) => initializer.as_ref().and_then(expr_line),
+        Stmt::Const(_, _, value, _) => expr_line(value),
+        Stmt::Block(statements, _) | Stmt::Function(_, _, statements, _, _) | Stmt::Class(_, _, statements, _) => {
+            statements.iter().find_map(stmt_line)
+        }
+        Stmt::If(condition, then_branch, _, _) => expr_line(condition).or_else(|| stmt_line(then_branch)),
+        Stmt::While(keyword, _, _, _) => Some(keyword.line),
+        Stmt::For(for_stmt) => Some(for_stmt.keyword.line),
+        Stmt::ForIn(for_in) => Some(for_in.keyword.line),
+        Stmt::Return(value, _) => value.as_ref().and_then(expr_line),
+        Stmt::ParallelAssign(names, _, _) => names.first().map(|(name, _)| name.line),
+        Stmt::Break(_) | Stmt::Continue(_) => None,
+    }
+}
+
+fn expr_line(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::Constant(..) => None,
+        Expr::Grouping(inner, _) => expr_line(inner),
+        Expr::Variable(name, _) | Expr::This(name) | Expr::Super(name, _) | Expr::Assign(name, ..) => Some(name.line),
+        Expr::Unary(unary) => Some(unary.operator.line),
+        Expr::Binary(binary) => expr_line(&binary.left).or(Some(binary.operator.line)),
+        Expr::Logical(logical) => expr_line(&logical.left).or(Some(logical.operator.line)),
+        Expr::Call(call) => expr_line(&call.callee).or(Some(call.paren.line)),
+        Expr::Get(get) => expr_line(&get.object).or(Some(get.name.line)),
+        Expr::Set(set) => expr_line(&set.object).or(Some(set.name.line)),
+        Expr::Index(index) => expr_line(&index.object).or(Some(index.bracket.line)),
+        Expr::IndexSet(set) => expr_line(&set.object).or(Some(set.bracket.line)),
+        Expr::Map(map) => Some(map.brace.line),
+        Expr::If(if_expr) => Some(if_expr.keyword.line),
+        Expr::Lambda(lambda) => Some(lambda.arrow.line),
+        Expr::Block(block) => Some(block.brace.line),
+    }
+}
+
+/// Encodes per-line mappings in the source map "mappings" format, with one
+/// segment at column zero for each generated line that has a source line.
+fn encode_mappings(mappings: &[Option<usize>]) -> String {
+    let mut encoded = String::new();
+    let mut previous_line = 0;
+    for (i, mapping) in mappings.iter().enumerate() {
+        if i > 0 {
+            encoded.push(';');
+        }
+        if let Some(line) = mapping {
+            let line = *line as i64 - 1;
+            for value in [0, 0, line - previous_line, 0] {
+                encode_vlq(value, &mut encoded);
+            }
+            previous_line = line;
+        }
+    }
+    encoded
+}
+
+fn encode_vlq(value: i64, out: &mut String) {
+    let mut vlq = if value < 0 { ((-value) << 1) | 1 } else { value << 1 };
+    loop {
+        let mut digit = vlq & 0b11111;
+        vlq >>= 5;
+        if vlq > 0 {
+            digit |= 0b100000;
+        }
+        out.push(BASE64[digit as usize] as char);
+        if vlq == 0 {
+            break;
+        }
+    }
+}
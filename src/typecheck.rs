@@ -0,0 +1,739 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::error::{TypeCheckError, Warning};
+use crate::expr::{Expr, LiteralExpr};
+use crate::stmt::{ForInStmt, ForStmt, Signature, Stmt};
+use crate::token::{Span, Token, TokenType};
+
+/// A type that an annotation can name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Number,
+    String,
+    Boolean,
+    Nil,
+    Function,
+    Map,
+    List,
+    /// Any value. Also the type of every name without an annotation and of
+    /// every expression whose type is only known once it runs.
+    Any,
+}
+
+impl Type {
+    /// Every type but `Any`.
+    const CONCRETE: [Type; 7] =
+        [Type::Number, Type::String, Type::Boolean, Type::Nil, Type::Function, Type::Map, Type::List];
+
+    /// The type an annotation names, if it names one.
+    pub fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "Number" => Some(Type::Number),
+            "String" => Some(Type::String),
+            "Boolean" => Some(Type::Boolean),
+            "Nil" => Some(Type::Nil),
+            "Function" => Some(Type::Function),
+            "Map" => Some(Type::Map),
+            "List" => Some(Type::List),
+            "Any" => Some(Type::Any),
+            _ => None,
+        }
+    }
+
+    pub fn of_literal(literal: &LiteralExpr) -> Type {
+        match literal {
+            LiteralExpr::Number(_) => Type::Number,
+            LiteralExpr::String(_) => Type::String,
+            LiteralExpr::Boolean(_) => Type::Boolean,
+            LiteralExpr::Nil => Type::Nil,
+        }
+    }
+
+    /// Whether a value of type `actual` may go where one of this type is expected.
+    pub fn accepts(self, actual: Type) -> bool {
+        self == Type::Any || actual == Type::Any || self == actual
+    }
+
+    /// The type of a value that is one of `self` or `other`.
+    fn join(self, other: Type) -> Type {
+        if self == other {
+            self
+        } else {
+            Type::Any
+        }
+    }
+
+    /// The concrete types a value of this type may have.
+    fn candidates(self) -> Vec<Type> {
+        if self == Type::Any {
+            Type::CONCRETE.to_vec()
+        } else {
+            vec![self]
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Type::Number => "Number",
+            Type::String => "String",
+            Type::Boolean => "Boolean",
+            Type::Nil => "Nil",
+            Type::Function => "Function",
+            Type::Map => "Map",
+            Type::List => "List",
+            Type::Any => "Any",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The type the interpreter gives `operator` applied to values of concrete
+/// types, or `None` when it stops with a type error.
+fn binary_result(operator: &TokenType, left: Type, right: Type) -> Option<Type> {
+    if matches!(operator, TokenType::EqualEqual | TokenType::BangEqual) {
+        return Some(Type::Boolean);
+    }
+    match (left, right) {
+        (Type::Number, Type::Number) => Some(match operator {
+            TokenType::Plus | TokenType::Minus | TokenType::Star | TokenType::Slash | TokenType::Percent
+            | TokenType::Ampersand | TokenType::Pipe | TokenType::Caret | TokenType::LessLess
+            | TokenType::GreaterGreater => Type::Number,
+            _ => Type::Boolean,
+        }),
+        (Type::String, Type::String | Type::Number) | (Type::Number, Type::String) if *operator == TokenType::Plus => {
+            Some(Type::String)
+        }
+        (Type::String, Type::Number) | (Type::Number, Type::String) if *operator == TokenType::Star => Some(Type::String),
+        (Type::String, Type::String)
+            if matches!(operator, TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual) =>
+        {
+            Some(Type::Boolean)
+        }
+        _ => None,
+    }
+}
+
+/// Like [`binary_result`], for the unary operators.
+fn unary_result(operator: &TokenType, right: Type) -> Option<Type> {
+    match (operator, right) {
+        (TokenType::Minus | TokenType::Tilde, Type::Number) => Some(Type::Number),
+        // `!` tests truthiness, which every value has.
+        (TokenType::Bang, _) => Some(Type::Boolean),
+        _ => None,
+    }
+}
+
+/// Joins the results of an operator over every combination of the types its
+/// operands may have. `None` when it fails for all of them.
+fn operator_type(results: impl Iterator<Item = Option<Type>>) -> Option<Type> {
+    let mut results = results.flatten();
+    let first = results.next()?;
+    Some(if results.all(|result| result == first) { first } else { Type::Any })
+}
+
+/// Describes operands whose types make an operator fail, for a warning.
+fn operands(types: &[Type]) -> String {
+    let known = types.iter().filter(|t| **t != Type::Any).map(Type::to_string).collect::<Vec<_>>();
+    match known.as_slice() {
+        [one] => format!("a {} operand", one),
+        _ => format!("{} operands", known.join(" and ")),
+    }
+}
+
+/// What the checker knows about a name in scope.
+#[derive(Debug, Clone, PartialEq)]
+enum Binding {
+    /// A variable's declared type, and the type of the value it holds at the
+    /// point of the program being checked.
+    Value(Type, Type),
+    /// A declared function's parameter types and return type.
+    Function(Vec<Type>, Type),
+}
+
+impl Binding {
+    fn declared(&self) -> Type {
+        match self {
+            Binding::Value(declared, _) => *declared,
+            Binding::Function(..) => Type::Function,
+        }
+    }
+
+    fn current(&self) -> Type {
+        match self {
+            Binding::Value(_, current) => *current,
+            Binding::Function(..) => Type::Function,
+        }
+    }
+
+    /// What a name holds where two paths through the program meet, having
+    /// left it as `self` on one and `other` on the other.
+    fn join(&self, other: &Binding) -> Binding {
+        if self == other {
+            return self.clone();
+        }
+        Binding::Value(self.declared().join(other.declared()), self.current().join(other.current()))
+    }
+}
+
+type Scopes = Vec<HashMap<String, Binding>>;
+
+/// What checking a program found.
+#[derive(Debug, Default)]
+pub struct TypeReport {
+    /// Places the program disagrees with its type annotations.
+    pub errors: Vec<TypeCheckError>,
+    /// Expressions that stop the program with a type error whenever they run.
+    pub warnings: Vec<Warning>,
+}
+
+/// Checks a program without running it. Reports where it disagrees with its
+/// type annotations, and warns about operators, calls and arities that fail
+/// whatever happens, working out the types of unannotated variables from
+/// the values they are given along the way. `source` is the text the
+/// statements were parsed from, used to give each problem its line.
+pub fn typecheck(source: &str, statements: &[Stmt]) -> TypeReport {
+    let mut reassigned = HashSet::new();
+    assignments_in_functions(statements, false, &mut reassigned);
+    let mut checker = TypeChecker {
+        source,
+        scopes: vec![HashMap::new()],
+        reassigned,
+        function: None,
+        report: TypeReport::default(),
+    };
+    for statement in statements {
+        checker.check_stmt(statement);
+    }
+    checker.report
+}
+
+/// Collects the names assigned inside function and lambda bodies. A call can
+/// change them at any point, so their values' types are not followed.
+fn assignments_in_functions(statements: &[Stmt], inside: bool, names: &mut HashSet<String>) {
+    for stmt in statements {
+        match stmt {
+            Stmt::Expression(expr, _) | Stmt::Print(expr, _) => expr_assignments(expr, inside, names),
+            Stmt::Var(_, _, initializer, _) | Stmt::Return(initializer, _) => {
+                if let Some(expr) = initializer {
+                    expr_assignments(expr, inside, names);
+                }
+            }
+            Stmt::Const(_, _, value, _) => expr_assignments(value, inside, names),
+            Stmt::Block(statements, _) => assignments_in_functions(statements, inside, names),
+            Stmt::If(condition, then_branch, else_branch, _) => {
+                expr_assignments(condition, inside, names);
+                assignments_in_functions(std::slice::from_ref(then_branch), inside, names);
+                if let Some(else_branch) = else_branch {
+                    assignments_in_functions(std::slice::from_ref(else_branch), inside, names);
+                }
+            }
+            Stmt::While(_, condition, body, _) => {
+                expr_assignments(condition, inside, names);
+                assignments_in_functions(std::slice::from_ref(body), inside, names);
+            }
+            Stmt::For(for_stmt) => {
+                let ForStmt { initializer, condition, increment, body, .. } = for_stmt.as_ref();
+                if let Some(initializer) = initializer {
+                    assignments_in_functions(std::slice::from_ref(initializer), inside, names);
+                }
+                for expr in condition.iter().chain(increment) {
+                    expr_assignments(expr, inside, names);
+                }
+                assignments_in_functions(std::slice::from_ref(body), inside, names);
+            }
+            Stmt::ForIn(for_in) => {
+                expr_assignments(&for_in.iterable, inside, names);
+                assignments_in_functions(std::slice::from_ref(&for_in.body), inside, names);
+            }
+            Stmt::Function(_, _, body, _, _) => assignments_in_functions(body, true, names),
+            Stmt::Class(_, _, methods, _) => assignments_in_functions(methods, inside, names),
+            Stmt::ParallelAssign(targets, values, _) => {
+                if inside {
+                    names.extend(targets.iter().map(|(target, _)| target.lexeme.clone()));
+                }
+                for value in values {
+                    expr_assignments(value, inside, names);
+                }
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => {}
+        }
+    }
+}
+
+fn expr_assignments(expr: &Expr, inside: bool, names: &mut HashSet<String>) {
+    match expr {
+        Expr::Constant(..) | Expr::Variable(..) | Expr::This(_) | Expr::Super(..) => {}
+        Expr::Assign(name, value, _) => {
+            if inside {
+                names.insert(name.lexeme.clone());
+            }
+            expr_assignments(value, inside, names);
+        }
+        Expr::Grouping(inner, _) => expr_assignments(inner, inside, names),
+        Expr::Unary(unary) => expr_assignments(&unary.right, inside, names),
+        Expr::Binary(binary) => {
+            expr_assignments(&binary.left, inside, names);
+            expr_assignments(&binary.right, inside, names);
+        }
+        Expr::Logical(logical) => {
+            expr_assignments(&logical.left, inside, names);
+            expr_assignments(&logical.right, inside, names);
+        }
+        Expr::Call(call) => {
+            expr_assignments(&call.callee, inside, names);
+            for argument in &call.arguments {
+                expr_assignments(argument, inside, names);
+            }
+        }
+        Expr::Get(get) => expr_assignments(&get.object, inside, names),
+        Expr::Set(set) => {
+            expr_assignments(&set.object, inside, names);
+            expr_assignments(&set.value, inside, names);
+        }
+        Expr::Index(index) => {
+            expr_assignments(&index.object, inside, names);
+            expr_assignments(&index.index, inside, names);
+        }
+        Expr::IndexSet(set) => {
+            expr_assignments(&set.object, inside, names);
+            expr_assignments(&set.index, inside, names);
+            expr_assignments(&set.value, inside, names);
+        }
+        Expr::Map(map) => {
+            for (_, value) in &map.entries {
+                expr_assignments(value, inside, names);
+            }
+        }
+        Expr::If(if_expr) => {
+            expr_assignments(&if_expr.condition, inside, names);
+            expr_assignments(&if_expr.then_branch, inside, names);
+            expr_assignments(&if_expr.else_branch, inside, names);
+        }
+        Expr::Lambda(lambda) => assignments_in_functions(&lambda.body, true, names),
+        Expr::Block(block) => {
+            assignments_in_functions(&block.statements, inside, names);
+            if let Some(value) = &block.value {
+                expr_assignments(value, inside, names);
+            }
+        }
+    }
+}
+
+struct TypeChecker<'a> {
+    source: &'a str,
+    /// Innermost scope last. The first holds the globals.
+    scopes: Scopes,
+    /// Names some function body assigns, see [`assignments_in_functions`].
+    reassigned: HashSet<String>,
+    /// Name and declared return type of the function whose body is being checked.
+    function: Option<(String, Type)>,
+    report: TypeReport,
+}
+
+impl TypeChecker<'_> {
+    fn error(&mut self, line: usize, message: String) {
+        self.report.errors.push(TypeCheckError::new(line, message));
+    }
+
+    fn warn(&mut self, line: usize, message: String) {
+        self.report.warnings.push(Warning::new(line, message));
+    }
+
+    /// The line `span` starts on.
+    fn line(&self, span: Span) -> usize {
+        let start = span.start.min(self.source.len());
+        self.source[..start].matches('\n').count() + 1
+    }
+
+    fn define(&mut self, name: &str, binding: Binding) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), binding);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    /// The type of the value `name` holds after being given one of type
+    /// `actual`. Annotations are trusted where the value's type is unknown.
+    fn held_type(&self, name: &str, declared: Type, actual: Type) -> Type {
+        if actual == Type::Any || self.reassigned.contains(name) {
+            declared
+        } else {
+            actual
+        }
+    }
+
+    /// Records that `name` now holds a value of type `actual`.
+    fn assign(&mut self, name: &str, actual: Type) {
+        let Some(declared) = self.lookup(name).map(Binding::declared) else {
+            return;
+        };
+        let current = self.held_type(name, declared, actual);
+        if let Some(binding) = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name)) {
+            *binding = Binding::Value(declared, current);
+        }
+    }
+
+    /// Combines the state left by another path through the program with the
+    /// current one, where the two paths meet.
+    fn merge(&mut self, other: Scopes) {
+        for (scope, other) in self.scopes.iter_mut().zip(other) {
+            for (name, binding) in scope.iter_mut() {
+                if let Some(other) = other.get(name) {
+                    *binding = binding.join(other);
+                }
+            }
+        }
+    }
+
+    /// Forgets the types of the values variables hold, keeping what they
+    /// were declared as. A function body can run long after the point it is
+    /// declared at, with its enclosing variables holding anything by then.
+    fn forget_values(&mut self) {
+        for binding in self.scopes.iter_mut().flat_map(|scope| scope.values_mut()) {
+            if let Binding::Value(declared, current) = binding {
+                *current = *declared;
+            }
+        }
+    }
+
+    /// The type an annotation names, reporting names that are not types.
+    fn annotation(&mut self, annotation: &Option<Token>) -> Type {
+        let Some(name) = annotation else {
+            return Type::Any;
+        };
+        match Type::from_name(&name.lexeme) {
+            Some(declared) => declared,
+            None => {
+                self.error(name.line, format!("Unknown type '{}'.", name.lexeme));
+                Type::Any
+            }
+        }
+    }
+
+    fn check_assignment(&mut self, name: &Token, actual: Type) {
+        let declared = self.lookup(&name.lexeme).map_or(Type::Any, Binding::declared);
+        if !declared.accepts(actual) {
+            self.error(
+                name.line,
+                format!("Cannot assign {} to '{}', which is declared {}.", actual, name.lexeme, declared),
+            );
+        }
+        self.assign(&name.lexeme, actual);
+    }
+
+    fn check_block(&mut self, statements: &[Stmt]) {
+        self.scopes.push(HashMap::new());
+        for statement in statements {
+            self.check_stmt(statement);
+        }
+        self.scopes.pop();
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr, _) | Stmt::Print(expr, _) => {
+                self.infer(expr);
+            }
+            Stmt::Var(name, annotation, initializer, _) => {
+                let declared = self.annotation(annotation);
+                let actual = match initializer {
+                    Some(initializer) => {
+                        let actual = self.infer(initializer);
+                        if !declared.accepts(actual) {
+                            let line = self.line(initializer.span());
+                            self.error(
+                                line,
+                                format!("Variable '{}' is declared {} but initialized with {}.", name, declared, actual),
+                            );
+                        }
+                        actual
+                    }
+                    None => Type::Nil,
+                };
+                let current = self.held_type(name, declared, actual);
+                self.define(name, Binding::Value(declared, current));
+            }
+            Stmt::Const(name, annotation, value, _) => {
+                let declared = self.annotation(annotation);
+                let actual = self.infer(value);
+                if !declared.accepts(actual) {
+                    let line = self.line(value.span());
+                    self.error(line, format!("Constant '{}' is declared {} but initialized with {}.", name, declared, actual));
+                }
+                let current = self.held_type(name, declared, actual);
+                self.define(name, Binding::Value(declared, current));
+            }
+            Stmt::Block(statements, _) => self.check_block(statements),
+            Stmt::If(condition, then_branch, else_branch, _) => {
+                self.infer(condition);
+                let before = self.scopes.clone();
+                self.check_stmt(then_branch);
+                let after_then = std::mem::replace(&mut self.scopes, before);
+                if let Some(else_branch) = else_branch {
+                    self.check_stmt(else_branch);
+                }
+                self.merge(after_then);
+            }
+            Stmt::While(_, condition, body, _) => {
+                self.infer(condition);
+                // The body may not run at all.
+                let before = self.scopes.clone();
+                self.check_stmt(body);
+                self.merge(before);
+            }
+            Stmt::For(for_stmt) => {
+                let ForStmt { initializer, condition, increment, body, .. } = for_stmt.as_ref();
+                self.scopes.push(HashMap::new());
+                if let Some(initializer) = initializer {
+                    self.check_stmt(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.infer(condition);
+                }
+                // The body and increment may not run at all.
+                let before = self.scopes.clone();
+                self.check_stmt(body);
+                if let Some(increment) = increment {
+                    self.infer(increment);
+                }
+                self.merge(before);
+                self.scopes.pop();
+            }
+            Stmt::ForIn(for_in) => {
+                let ForInStmt { keyword, name, iterable, body, .. } = for_in.as_ref();
+                let iterable = self.infer(iterable);
+                if !matches!(iterable, Type::List | Type::Map | Type::Any) {
+                    self.error(keyword.line, format!("Can only iterate over lists and maps, not {}.", iterable));
+                }
+                self.scopes.push(HashMap::new());
+                self.define(&name.lexeme, Binding::Value(Type::Any, Type::Any));
+                // The body may not run at all.
+                let before = self.scopes.clone();
+                self.check_stmt(body);
+                self.merge(before);
+                self.scopes.pop();
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => {}
+            Stmt::Function(name, params, body, signature, _) => self.check_function(name, params, body, signature),
+            Stmt::Class(name, _, methods, _) => {
+                self.define(name, Binding::Value(Type::Any, Type::Any));
+                for method in methods {
+                    if let Stmt::Function(method, params, body, signature, _) = method {
+                        let (param_types, returns) = self.signature_types(params, signature);
+                        self.check_function_body(method, params, param_types, returns, body);
+                    }
+                }
+            }
+            Stmt::Return(value, span) => {
+                let actual = value.as_ref().map_or(Type::Nil, |value| self.infer(value));
+                if let Some((function, expected)) = &self.function {
+                    if !expected.accepts(actual) {
+                        let message = format!("Function '{}' must return {} but returns {}.", function, expected, actual);
+                        self.error(self.line(*span), message);
+                    }
+                }
+            }
+            Stmt::ParallelAssign(names, values, _) => {
+                let actual = values.iter().map(|value| self.infer(value)).collect::<Vec<_>>();
+                for ((name, _), actual) in names.iter().zip(actual) {
+                    self.check_assignment(name, actual);
+                }
+            }
+        }
+    }
+
+    fn check_function(&mut self, name: &str, params: &[String], body: &[Stmt], signature: &Signature) {
+        let (param_types, returns) = self.signature_types(params, signature);
+        // Defined before the body is checked, so recursive calls are checked too.
+        if self.reassigned.contains(name) {
+            self.define(name, Binding::Value(Type::Any, Type::Any));
+        } else if signature.rest {
+            // Calls to it pass any number of arguments, so they are not checked.
+            self.define(name, Binding::Value(Type::Function, Type::Function));
+        } else {
+            self.define(name, Binding::Function(param_types.clone(), returns));
+        }
+        self.check_function_body(name, params, param_types, returns, body);
+    }
+
+    fn signature_types(&mut self, params: &[String], signature: &Signature) -> (Vec<Type>, Type) {
+        let param_types = (0..params.len())
+            .map(|i| match signature.params.get(i) {
+                _ if signature.rest && i + 1 == params.len() => Type::List,
+                Some(annotation) => self.annotation(annotation),
+                None => Type::Any,
+            })
+            .collect::<Vec<_>>();
+        (param_types, self.annotation(&signature.returns))
+    }
+
+    /// Checks a function's or method's body in the scope it is declared in.
+    fn check_function_body(&mut self, name: &str, params: &[String], param_types: Vec<Type>, returns: Type, body: &[Stmt]) {
+        let declared_at = self.scopes.clone();
+        self.forget_values();
+        self.scopes.push(HashMap::new());
+        for (param, declared) in params.iter().zip(param_types) {
+            self.define(param, Binding::Value(declared, declared));
+        }
+        let enclosing = self.function.replace((name.to_string(), returns));
+        for statement in body {
+            self.check_stmt(statement);
+        }
+        self.function = enclosing;
+        self.scopes = declared_at;
+    }
+
+    /// Works out the type of an expression, checking the expressions inside it.
+    fn infer(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Constant(literal, _) => Type::of_literal(literal),
+            Expr::Grouping(inner, _) => self.infer(inner),
+            Expr::Variable(name, _) => self.lookup(&name.lexeme).map_or(Type::Any, Binding::current),
+            Expr::This(_) | Expr::Super(..) => Type::Any,
+            Expr::Assign(name, value, _) => {
+                let actual = self.infer(value);
+                self.check_assignment(name, actual);
+                actual
+            }
+            Expr::Unary(unary) => {
+                let right = self.infer(&unary.right);
+                let operator = &unary.operator;
+                let results = right.candidates().into_iter().map(|right| unary_result(&operator.token_type, right));
+                operator_type(results).unwrap_or_else(|| {
+                    let message = format!("Operator '{}' always fails on {}.", operator.lexeme, operands(&[right]));
+                    self.warn(operator.line, message);
+                    Type::Any
+                })
+            }
+            Expr::Binary(binary) => {
+                let left = self.infer(&binary.left);
+                let right = self.infer(&binary.right);
+                let operator = &binary.operator;
+                let results = left.candidates().into_iter().flat_map(|left| {
+                    right.candidates().into_iter().map(move |right| binary_result(&operator.token_type, left, right))
+                });
+                operator_type(results).unwrap_or_else(|| {
+                    let message = format!("Operator '{}' always fails on {}.", operator.lexeme, operands(&[left, right]));
+                    self.warn(operator.line, message);
+                    Type::Any
+                })
+            }
+            Expr::Logical(logical) => {
+                let left = self.infer(&logical.left);
+                // The right operand may not run at all.
+                let before = self.scopes.clone();
+                let right = self.infer(&logical.right);
+                self.merge(before);
+                left.join(right)
+            }
+            Expr::Call(call) => {
+                let callee = self.infer(&call.callee);
+                let arguments = call.arguments.iter().map(|argument| self.infer(argument)).collect::<Vec<_>>();
+                if let Some(&spread) = arguments.last().filter(|_| call.spread) {
+                    if !Type::List.accepts(spread) {
+                        self.error(call.paren.line, format!("Can only spread a list, not {}.", spread));
+                    }
+                }
+                if !Type::Function.accepts(callee) {
+                    self.warn(call.paren.line, format!("Calling a {} always fails; only functions can be called.", callee));
+                    return Type::Any;
+                }
+                let Expr::Variable(name, _) = &call.callee else {
+                    return Type::Any;
+                };
+                let Some(Binding::Function(params, returns)) = self.lookup(&name.lexeme).cloned() else {
+                    return Type::Any;
+                };
+                // How many arguments a spread passes is only known once it runs.
+                if call.spread {
+                    return returns;
+                }
+                if params.len() != arguments.len() {
+                    self.warn(
+                        call.paren.line,
+                        format!(
+                            "'{}' expects {} argument{} but is called with {}.",
+                            name.lexeme,
+                            params.len(),
+                            if params.len() == 1 { "" } else { "s" },
+                            arguments.len()
+                        ),
+                    );
+                }
+                for (i, (expected, actual)) in params.iter().zip(arguments).enumerate() {
+                    if !expected.accepts(actual) {
+                        self.error(
+                            call.paren.line,
+                            format!("Argument {} of '{}' expects {} but got {}.", i + 1, name.lexeme, expected, actual),
+                        );
+                    }
+                }
+                returns
+            }
+            Expr::Get(get) => {
+                self.infer(&get.object);
+                Type::Any
+            }
+            Expr::Set(set) => {
+                self.infer(&set.object);
+                self.infer(&set.value)
+            }
+            Expr::Index(index) => {
+                self.infer(&index.object);
+                self.infer(&index.index);
+                Type::Any
+            }
+            Expr::IndexSet(set) => {
+                self.infer(&set.object);
+                self.infer(&set.index);
+                self.infer(&set.value)
+            }
+            Expr::Map(map) => {
+                for (_, value) in &map.entries {
+                    self.infer(value);
+                }
+                Type::Map
+            }
+            Expr::If(if_expr) => {
+                self.infer(&if_expr.condition);
+                let before = self.scopes.clone();
+                let then_type = self.infer(&if_expr.then_branch);
+                let after_then = std::mem::replace(&mut self.scopes, before);
+                let else_type = self.infer(&if_expr.else_branch);
+                self.merge(after_then);
+                then_type.join(else_type)
+            }
+            Expr::Lambda(lambda) => {
+                let declared_at = self.scopes.clone();
+                self.forget_values();
+                self.scopes.push(HashMap::new());
+                for param in &lambda.params {
+                    self.define(param, Binding::Value(Type::Any, Type::Any));
+                }
+                // Lambdas have no return annotation to check against.
+                let enclosing = self.function.take();
+                for statement in &lambda.body {
+                    self.check_stmt(statement);
+                }
+                self.function = enclosing;
+                self.scopes = declared_at;
+                Type::Function
+            }
+            Expr::Block(block) => {
+                self.scopes.push(HashMap::new());
+                for statement in &block.statements {
+                    self.check_stmt(statement);
+                }
+                let value = block.value.as_ref().map_or(Type::Nil, |value| self.infer(value));
+                self.scopes.pop();
+                value
+            }
+        }
+    }
+}
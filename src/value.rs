@@ -0,0 +1,88 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::rc::Rc;
+
+use crate::callable::LoxCallable;
+use crate::class::LoxInstance;
+use crate::expr::LiteralExpr;
+
+/// A value a program computes with while it runs.
+#[derive(Clone)]
+pub enum Value {
+    Number(f64),
+    String(String),
+    Boolean(bool),
+    Callable(Rc<dyn LoxCallable>),
+    /// String-keyed record, written as a map literal `{"key": value}` or
+    /// returned by natives such as `exec`. Indexed with `map["key"]` and
+    /// changed with `map["key"] = value`, which copies of the map share.
+    Map(Rc<RefCell<BTreeMap<String, Value>>>),
+    /// Ordered values, such as the arguments gathered by a rest parameter
+    /// or the numbers from `range`. Indexed with `list[i]` and walked by
+    /// `for (var x in list)`. Copies of a list share it, so `list.push(x)`
+    /// is seen through all of them.
+    List(Rc<RefCell<Vec<Value>>>),
+    Nil,
+    /// An object made by calling a class.
+    Instance(Rc<LoxInstance>),
+    /// An exact number, used instead of `Number` with `--bignum`.
+    #[cfg(feature = "bignum")]
+    BigNumber(Rc<num_rational::BigRational>),
+}
+
+impl Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "Number({})", n),
+            Value::String(s) => write!(f, "String(\"{}\")", s),
+            Value::Boolean(b) => write!(f, "Boolean({})", b),
+            Value::Callable(_) => write!(f, "Callable(<function>)"),
+            Value::Map(map) => write!(f, "Map({:?})", map.borrow()),
+            Value::List(list) => write!(f, "List({:?})", list.borrow()),
+            Value::Nil => write!(f, "Nil"),
+            Value::Instance(instance) => write!(f, "Instance({:?})", instance),
+            #[cfg(feature = "bignum")]
+            Value::BigNumber(n) => write!(f, "BigNumber({})", n),
+        }
+    }
+}
+
+impl Value {
+    /// A new map holding `entries`.
+    pub fn map(entries: BTreeMap<String, Value>) -> Value {
+        Value::Map(Rc::new(RefCell::new(entries)))
+    }
+
+    /// A new list holding `items`.
+    pub fn list(items: Vec<Value>) -> Value {
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+
+    /// The name of the value's type, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Boolean(_) => "boolean",
+            Value::Callable(_) => "function",
+            Value::Map(_) => "map",
+            Value::List(_) => "list",
+            Value::Nil => "nil",
+            Value::Instance(_) => "instance",
+            #[cfg(feature = "bignum")]
+            Value::BigNumber(_) => "number",
+        }
+    }
+}
+
+impl From<&LiteralExpr> for Value {
+    fn from(literal: &LiteralExpr) -> Self {
+        match literal {
+            LiteralExpr::Number(n) => Value::Number(*n),
+            LiteralExpr::String(s) => Value::String(s.clone()),
+            LiteralExpr::Boolean(b) => Value::Boolean(*b),
+            LiteralExpr::Nil => Value::Nil,
+        }
+    }
+}
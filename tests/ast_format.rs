@@ -0,0 +1,51 @@
+//! Snapshots of every `AstFormat` version for the programs in
+//! `tests/ast_format`. Released versions must not change, so a failure here
+//! means a pretty printer change needs a new version instead. Set `BLESS=1`
+//! only to record the snapshots of a newly added version.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use rustylox::ast_format::AstFormat;
+use rustylox::lexer::Lexer;
+use rustylox::parser::Parser;
+
+const VERSIONS: &[(AstFormat, &str)] = &[(AstFormat::V1, "v1"), (AstFormat::V2, "v2")];
+
+#[test]
+fn ast_format_snapshots() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ast_format");
+    let mut programs = fs::read_dir(&dir)
+        .expect("failed to read the AST format fixtures")
+        .map(|entry| entry.expect("failed to read the AST format fixtures").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "lox"))
+        .collect::<Vec<_>>();
+    programs.sort();
+    assert!(!programs.is_empty(), "no programs found in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for program in &programs {
+        let source = fs::read_to_string(program).expect("failed to read a program");
+        for (format, version) in VERSIONS {
+            let (statements, errors) = Parser::new(Lexer::new(source.clone())).parse();
+            let actual = format.emit(statements, errors);
+            let snapshot = program.with_extension(format!("{}.ast", version));
+            let expected = fs::read_to_string(&snapshot).ok();
+            if expected.as_deref() == Some(actual.as_str()) {
+                continue;
+            }
+            if env::var_os("BLESS").is_some() {
+                fs::write(&snapshot, &actual).expect("failed to write a snapshot");
+            } else {
+                failures.push(format!(
+                    "{}: snapshot differs\n--- expected\n{}--- actual\n{}",
+                    snapshot.display(),
+                    expected.unwrap_or_default(),
+                    actual
+                ));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{} AST format snapshots failed:\n\n{}", failures.len(), failures.join("\n"));
+}
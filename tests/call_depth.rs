@@ -0,0 +1,27 @@
+//! Unbounded recursion stops at the default call depth limit with an
+//! error, instead of overflowing the stack of the thread running it.
+
+use std::thread;
+
+use rustylox::{interpret, new_globals, run_captured, RunOptions};
+
+const RECURSE: &str = "fun f(n) { return f(n + 1); }\nf(0);\n";
+
+#[test]
+fn default_call_depth_limit_reports_stack_overflow() {
+    assert_eq!(interpret(RECURSE), "[line 1] Error: Stack overflow in 'f' at call depth 1025.");
+}
+
+#[test]
+fn call_depth_limit_holds_on_a_small_stack() {
+    let diagnostics = thread::Builder::new()
+        .stack_size(512 * 1024)
+        .spawn(|| {
+            let options = RunOptions::default();
+            run_captured(RECURSE, new_globals(options.capabilities), &options).1
+        })
+        .expect("failed to start a thread")
+        .join()
+        .expect("the interpreter crashed");
+    assert_eq!(diagnostics, "[line 1] Error: Stack overflow in 'f' at call depth 1025.\n");
+}
@@ -0,0 +1,25 @@
+//! Runs the fixtures in `tests/golden`. Set `BLESS=1` to rewrite the
+//! `.expected` files from the current output instead of comparing.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use rustylox::golden::run_golden;
+
+#[test]
+fn golden_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let report = run_golden(&dir).expect("failed to read the golden fixtures");
+
+    if env::var_os("BLESS").is_some() {
+        for failure in &report.failures {
+            fs::write(failure.expected_path(), &failure.actual).expect("failed to write an expected file");
+        }
+        return;
+    }
+
+    let failures = report.failures.iter().map(|failure| failure.to_string()).collect::<Vec<_>>();
+    assert!(failures.is_empty(), "{} golden fixtures failed:\n\n{}", failures.len(), failures.join("\n"));
+    assert!(report.passed > 0, "no golden fixtures found in {}", dir.display());
+}
@@ -0,0 +1,35 @@
+//! Printing a syntax tree as source and parsing it again must give the same
+//! tree. Trees are compared through their printed source.
+
+use proptest::prelude::*;
+use rustylox::parse_program;
+use rustylox::printer::{to_minified_source, to_source};
+use rustylox::strategy::program;
+
+proptest! {
+    #[test]
+    fn printed_programs_parse_back(statements in program()) {
+        let source = to_source(&statements);
+        let reparsed = match parse_program(&source) {
+            Ok(reparsed) => reparsed,
+            Err(errors) => {
+                let messages = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                return Err(TestCaseError::fail(format!("{}\nin:\n{}", messages.join("\n"), source)));
+            }
+        };
+        prop_assert_eq!(to_source(&reparsed), source);
+    }
+
+    #[test]
+    fn minified_programs_parse_back(statements in program()) {
+        let source = to_minified_source(&statements);
+        let reparsed = match parse_program(&source) {
+            Ok(reparsed) => reparsed,
+            Err(errors) => {
+                let messages = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                return Err(TestCaseError::fail(format!("{}\nin:\n{}", messages.join("\n"), source)));
+            }
+        };
+        prop_assert_eq!(to_source(&reparsed), to_source(&statements));
+    }
+}
@@ -0,0 +1,18 @@
+//! Output and `eprint` reach the terminal in the order the program
+//! printed them, even though output is buffered.
+
+use std::process::Command;
+
+#[test]
+fn eprint_keeps_its_place_among_printed_lines() {
+    let script = std::env::temp_dir().join(format!("rustylox-std-streams-{}.lox", std::process::id()));
+    std::fs::write(&script, "print \"first\";\neprint(\"second\");\nprint \"third\";\n").expect("failed to write the script");
+    // Both streams go to the same pipe, as they would to a terminal.
+    let run = Command::new("sh")
+        .arg("-c")
+        .arg(format!("'{}' interpret '{}' 2>&1", env!("CARGO_BIN_EXE_rustylox"), script.display()))
+        .output()
+        .expect("failed to run the interpreter");
+    let _ = std::fs::remove_file(&script);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "first\nsecond\nthird\n");
+}